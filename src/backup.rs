@@ -0,0 +1,422 @@
+use crate::conversion;
+use crate::store::{ConflictError, ExpectedVersion, Image, ImageMetadata, Post, Redirect, Store};
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use object_store::path::PathPart;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// The name of the manifest within the archive. It is always the first entry.
+const MANIFEST_NAME: &str = "manifest.json";
+/// The version of the [Manifest] format, which must be bumped when it changes in a way older binaries can't read.
+const MANIFEST_VERSION: u32 = 1;
+
+/// Describes the contents of an export archive. The manifest is JSON so that the archive can be inspected and used
+/// without this binary, and so that it does not depend on the encodings used inside the store.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct Manifest {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    /// The [Store::SCHEMA_VERSION] of the binary which wrote the archive.
+    pub schema_version: u32,
+    pub posts: Vec<ManifestPost>,
+    pub images: Vec<ManifestImage>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct ManifestPost {
+    /// The archive entry holding the raw markdown content.
+    pub file: String,
+    pub post: Post,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct ManifestImage {
+    pub slug: String,
+    /// The archive entry holding the original image, which is what gets imported.
+    pub original: String,
+    /// The archive entries holding the resized variants. These are kept for completeness, but are generated again from
+    /// the original on import.
+    pub variants: Vec<String>,
+    /// The alt text, caption, and capture time are restored on import, while the dimensions and size are taken from the
+    /// original.
+    /// This is missing from archives written before images had metadata.
+    #[serde(default)]
+    pub metadata: Option<ImageMetadata>,
+}
+
+/// The outcome of [import], with one line per item.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct ImportReport {
+    pub imported: Vec<String>,
+    /// Items which already exist in the store with different content, and were left alone.
+    pub conflicts: Vec<String>,
+    /// Items which could not be imported, such as posts which fail validation.
+    pub failed: Vec<String>,
+}
+
+/// Write every post, image variant, and redirect in the store to a tar archive along with a [Manifest].
+pub(crate) async fn export(store: &Store, mut w: impl Write) -> Result<Manifest, Error> {
+    let mut files: Vec<(String, Vec<u8>)> = vec![];
+    let mut manifest = Manifest {
+        version: MANIFEST_VERSION,
+        exported_at: Utc::now(),
+        schema_version: Store::SCHEMA_VERSION,
+        posts: vec![],
+        images: vec![],
+        redirects: store.list_redirects().await?,
+    };
+
+    let mut posts = store.list_posts().await?;
+    posts.sort_by(|a, b| a.slug.cmp(&b.slug));
+    for listed in posts {
+        // The post may have been deleted since it was listed.
+        let Some((post, content)) = store.get_post_raw(&listed.slug).await? else {
+            continue;
+        };
+        let file = format!("posts/{}/content.md", post.slug);
+        files.push((file.clone(), content.into_bytes()));
+        manifest.posts.push(ManifestPost { file, post });
+    }
+
+    let mut images = store.list_images().await?;
+    images.sort();
+    for image in images {
+        let mut entry = ManifestImage {
+            slug: image.slug().to_string(),
+            original: String::new(),
+            variants: vec![],
+            metadata: store.get_image_metadata(&image).await?,
+        };
+        for variant in store.list_image_variants(&image).await? {
+            let Some(raw) = store.get_image_raw(&variant).await? else {
+                continue;
+            };
+            let file = format!("images/{}/{}", image.to_path_part().as_ref(), variant.to_path_part().as_ref());
+            match variant == image {
+                true => entry.original = file.clone(),
+                false => entry.variants.push(file.clone()),
+            }
+            files.push((file, raw.to_vec()));
+        }
+        if !entry.original.is_empty() {
+            manifest.images.push(entry);
+        }
+    }
+
+    let mtime = manifest.exported_at.timestamp();
+    tar::write_entry(&mut w, MANIFEST_NAME, serde_json::to_string_pretty(&manifest)?.as_bytes(), mtime)?;
+    for (name, data) in files {
+        tar::write_entry(&mut w, name.as_str(), data.as_slice(), mtime)?;
+    }
+    tar::finish(&mut w)?;
+    Ok(manifest)
+}
+
+/// Read an archive written by [export] and write its contents into the store. Redirects and images are imported first
+/// so that the posts which link to them pass validation. Nothing which already exists in the store is overwritten.
+pub(crate) async fn import(store: &Store, r: impl Read) -> Result<ImportReport, Error> {
+    let mut files = tar::read_entries(r)?;
+    let manifest: Manifest = match files.remove(MANIFEST_NAME) {
+        Some(raw) => serde_json::from_slice(&raw)?,
+        None => return Err(anyhow!("invalid archive - missing {}", MANIFEST_NAME)),
+    };
+    if manifest.version != MANIFEST_VERSION {
+        return Err(anyhow!(
+            "unsupported archive - manifest version {} is not {}",
+            manifest.version,
+            MANIFEST_VERSION
+        ));
+    }
+    let mut report = ImportReport::default();
+
+    let existing_redirects = store.list_redirects().await?;
+    for redirect in manifest.redirects.iter() {
+        let item = format!("redirect '{}'", redirect.from);
+        match existing_redirects.iter().find(|r| r.from == redirect.from) {
+            Some(existing) if existing == redirect => {}
+            Some(existing) => report
+                .conflicts
+                .push(format!("{} already exists and redirects to '{}'", item, existing.to)),
+            None => match store.upsert_redirect(redirect).await {
+                Ok(_) => report.imported.push(item),
+                Err(e) => report.failed.push(format!("{}: {}", item, e)),
+            },
+        }
+    }
+
+    for image in manifest.images.iter() {
+        let item = format!("image '{}'", image.slug);
+        let Some(raw) = files.get(&image.original) else {
+            report.failed.push(format!("{}: missing {} in archive", item, image.original));
+            continue;
+        };
+        match Image::try_from_path_part(PathPart::from(image.original.rsplit('/').next().unwrap_or_default())) {
+            Ok(original) if store.check_image_exists(&original).await? => {
+                report.conflicts.push(format!("{} already exists", item));
+            }
+            _ => match store.create_image(image.slug.as_str(), raw).await {
+                Ok(created) => {
+                    if let Some(archived) = &image.metadata {
+                        let metadata = store.get_image_metadata(&created).await?.unwrap_or_default();
+                        let restored = ImageMetadata {
+                            alt: archived.alt.clone(),
+                            caption: archived.caption.clone(),
+                            uploaded: archived.uploaded,
+                            captured: archived.captured,
+                            ..metadata
+                        };
+                        store.put_image_metadata(&created, &restored).await?;
+                    }
+                    report.imported.push(item)
+                }
+                Err(e) => report.failed.push(format!("{}: {:#}", item, e)),
+            },
+        }
+    }
+
+    // Posts may link to each other, so links to any post in the archive are allowed while importing.
+    let archived_posts = manifest.posts.iter().map(|p| p.post.clone()).collect::<Vec<_>>();
+    let pending_links = conversion::build_valid_links(&archived_posts, &[], &[]);
+    for entry in manifest.posts.iter() {
+        let item = format!("post '{}'", entry.post.slug);
+        let content = match files.get(&entry.file).map(|raw| String::from_utf8(raw.clone())) {
+            Some(Ok(content)) => content,
+            Some(Err(e)) => {
+                report.failed.push(format!("{}: {}", item, e));
+                continue;
+            }
+            None => {
+                report.failed.push(format!("{}: missing {} in archive", item, entry.file));
+                continue;
+            }
+        };
+        match store
+            .upsert_post_with_links(&entry.post, content.as_str(), &ExpectedVersion::Absent, &pending_links)
+            .await
+        {
+            Ok(_) => report.imported.push(item),
+            Err(e) if e.is::<ConflictError>() => report.conflicts.push(format!("{} already exists", item)),
+            Err(e) => report.failed.push(format!("{}: {}", item, e)),
+        }
+    }
+    Ok(report)
+}
+
+/// Just enough of the ustar format to write and read back the archives produced by [export]. Only regular files are
+/// supported, and other entry types are skipped when reading.
+mod tar {
+    use anyhow::{anyhow, Error};
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+
+    const BLOCK: usize = 512;
+
+    pub(super) fn write_entry(w: &mut impl Write, path: &str, data: &[u8], mtime: i64) -> Result<(), Error> {
+        let mut header = [0u8; BLOCK];
+        // Names longer than the name field are split at a directory boundary into the prefix field.
+        let (prefix, name) = match path.len() {
+            0..=100 => ("", path),
+            _ => path
+                .match_indices('/')
+                .map(|(i, _)| (&path[..i], &path[i + 1..]))
+                .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100)
+                .ok_or_else(|| anyhow!("path '{}' is too long for the archive", path))?,
+        };
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        write_octal(&mut header[100..108], 0o644);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], data.len() as u64);
+        write_octal(&mut header[136..148], mtime.max(0) as u64);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        let checksum = checksum(&header);
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+        w.write_all(&header)?;
+        w.write_all(data)?;
+        w.write_all(&[0u8; BLOCK][..padding(data.len())])?;
+        Ok(())
+    }
+
+    /// Write the two empty blocks which mark the end of the archive.
+    pub(super) fn finish(w: &mut impl Write) -> Result<(), Error> {
+        w.write_all(&[0u8; BLOCK * 2])?;
+        w.flush()?;
+        Ok(())
+    }
+
+    pub(super) fn read_entries(mut r: impl Read) -> Result<HashMap<String, Vec<u8>>, Error> {
+        let mut entries = HashMap::new();
+        let mut header = [0u8; BLOCK];
+        loop {
+            match r.read_exact(&mut header) {
+                Ok(_) => {}
+                // Some writers omit the end of archive blocks.
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            if header.iter().all(|b| *b == 0) {
+                break;
+            }
+            if read_octal(&header[148..156])? != checksum(&header) {
+                return Err(anyhow!("invalid archive - bad header checksum"));
+            }
+            let size = read_octal(&header[124..136])?;
+            let mut data = vec![];
+            (&mut r).take(size).read_to_end(&mut data)?;
+            if data.len() as u64 != size {
+                return Err(anyhow!("invalid archive - truncated entry"));
+            }
+            std::io::copy(&mut (&mut r).take(padding(data.len()) as u64), &mut std::io::sink())?;
+            if header[156] != b'0' && header[156] != 0 {
+                continue;
+            }
+            let name = read_str(&header[..100])?;
+            let path = match &header[257..262] == b"ustar" {
+                true => match read_str(&header[345..500])? {
+                    "" => name.to_string(),
+                    prefix => format!("{}/{}", prefix, name),
+                },
+                false => name.to_string(),
+            };
+            entries.insert(path, data);
+        }
+        Ok(entries)
+    }
+
+    fn padding(len: usize) -> usize {
+        (BLOCK - len % BLOCK) % BLOCK
+    }
+
+    /// The checksum is the sum of the header bytes, with the checksum field itself counted as spaces.
+    fn checksum(header: &[u8; BLOCK]) -> u64 {
+        header
+            .iter()
+            .enumerate()
+            .map(|(i, b)| if (148..156).contains(&i) { b' ' as u64 } else { *b as u64 })
+            .sum()
+    }
+
+    fn write_octal(field: &mut [u8], value: u64) {
+        let s = format!("{:0width$o}\0", value, width = field.len() - 1);
+        field.copy_from_slice(&s.as_bytes()[s.len() - field.len()..]);
+    }
+
+    fn read_octal(field: &[u8]) -> Result<u64, Error> {
+        let s = read_str(field)?.trim_matches(|c: char| c == ' ' || c == '\0');
+        match s {
+            "" => Ok(0),
+            s => Ok(u64::from_str_radix(s, 8)?),
+        }
+    }
+
+    fn read_str(field: &[u8]) -> Result<&str, Error> {
+        let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+        Ok(std::str::from_utf8(&field[..end])?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ColorType, DynamicImage};
+
+    #[test]
+    fn test_tar_round_trip() -> Result<(), Error> {
+        let long = format!("posts/{}/content.md", "x".repeat(99));
+        let mut out = vec![];
+        tar::write_entry(&mut out, "a.txt", b"hello", 0)?;
+        tar::write_entry(&mut out, long.as_str(), &[1u8; 600], 0)?;
+        tar::write_entry(&mut out, "empty", b"", 0)?;
+        tar::finish(&mut out)?;
+        assert_eq!(out.len() % 512, 0);
+        assert!(tar::write_entry(&mut vec![], "y".repeat(101).as_str(), b"", 0).is_err());
+
+        let entries = tar::read_entries(out.as_slice())?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries.get("a.txt").map(|d| d.as_slice()), Some(b"hello".as_slice()));
+        assert_eq!(entries.get(&long).map(|d| d.len()), Some(600));
+        assert_eq!(entries.get("empty").map(|d| d.len()), Some(0));
+
+        out[0] = b'b';
+        assert!(tar::read_entries(out.as_slice()).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_import() -> Result<(), Error> {
+        let source = Store::default();
+        let mut raw_image = vec![];
+        DynamicImage::new(100, 100, ColorType::Rgb8).write_with_encoder(JpegEncoder::new(&mut raw_image))?;
+        let img = source.create_image("photo", raw_image.as_slice()).await?;
+        let metadata = ImageMetadata {
+            alt: "A black square".to_string(),
+            ..source.get_image_metadata(&img).await?.unwrap_or_default()
+        };
+        source.put_image_metadata(&img, &metadata).await?;
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap_or_default();
+        let first = Post {
+            date,
+            slug: "first-post".to_string(),
+            title: "First".to_string(),
+            published: true,
+            labels: vec!["blue".to_string()],
+            cover_image: Some(format!("/images/{}", img)),
+            ..Post::default()
+        };
+        let second = Post {
+            slug: "second-post".to_string(),
+            title: "Second".to_string(),
+            ..first.clone()
+        };
+        source.upsert_post(&first, "first", &ExpectedVersion::Absent).await?;
+        source
+            .upsert_post(&second, "[first](/posts/first-post)", &ExpectedVersion::Absent)
+            .await?;
+        source
+            .upsert_post(&first, "[second](/posts/second-post)", &ExpectedVersion::Any)
+            .await?;
+        let redirect = Redirect {
+            from: "/old/".to_string(),
+            to: "/posts/".to_string(),
+            prefix: true,
+            permanent: true,
+        };
+        source.upsert_redirect(&redirect).await?;
+
+        let mut archive = vec![];
+        let manifest = export(&source, &mut archive).await?;
+        assert_eq!((manifest.posts.len(), manifest.images.len()), (2, 1));
+        assert_eq!(manifest.images[0].variants.len(), 4);
+
+        // The posts link to each other, so they only pass validation when imported together.
+        let target = Store::default();
+        let report = import(&target, archive.as_slice()).await?;
+        assert_eq!(report.imported.len(), 4, "{:?}", report);
+        assert!(report.conflicts.is_empty() && report.failed.is_empty(), "{:?}", report);
+        assert_eq!(
+            target.get_post_raw("first-post").await?,
+            Some((first.clone(), "[second](/posts/second-post)".to_string()))
+        );
+        assert_eq!(target.list_redirects().await?, vec![redirect]);
+        assert_eq!(target.list_image_variants(&img).await?, source.list_image_variants(&img).await?);
+        let imported = target.get_image_metadata(&img).await?.unwrap_or_default();
+        assert_eq!(
+            (imported.alt, imported.width, imported.height, imported.uploaded),
+            (metadata.alt, 100, 100, metadata.uploaded)
+        );
+
+        // Importing again leaves everything alone and reports the conflicts.
+        let report = import(&target, archive.as_slice()).await?;
+        assert_eq!(report.imported, Vec::<String>::new());
+        assert_eq!(report.conflicts.len(), 3, "{:?}", report);
+        Ok(())
+    }
+}
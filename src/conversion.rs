@@ -1,8 +1,11 @@
-use crate::store::{Image, Post};
+use crate::path_utils::strip_path_prefix;
+use crate::store::{Image, ImageMetadata, Post, Redirect};
 use anyhow::anyhow;
 use maud::html;
-use pulldown_cmark::{html, BrokenLink, BrokenLinkCallback, CowStr, Event, HeadingLevel, Parser, Tag};
-use std::collections::HashSet;
+use object_store::path::PathPart;
+use pulldown_cmark::{html, BrokenLink, BrokenLinkCallback, CowStr, Event, HeadingLevel, LinkType, Parser, Tag, TagEnd};
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
 use std::sync::{Arc, Mutex};
 use tracing::instrument;
 
@@ -37,7 +40,12 @@ fn pulldown_parser(content: &str) -> (Arc<Mutex<Option<anyhow::Error>>>, Parser<
     (error_capture, parser)
 }
 
-pub fn build_valid_links(ps: &[Post], is: &[Image]) -> HashSet<String> {
+/// A valid link ending in this marker accepts any link starting with the rest of it.
+const PREFIX_MARKER: char = '*';
+
+/// Build the set of relative links which posts may use: the posts, images, and redirect sources. The source of a
+/// prefix redirect is included with the [PREFIX_MARKER] appended.
+pub fn build_valid_links(ps: &[Post], is: &[Image], rs: &[Redirect]) -> HashSet<String> {
     is.iter()
         .flat_map(|i| {
             vec![
@@ -47,11 +55,107 @@ pub fn build_valid_links(ps: &[Post], is: &[Image]) -> HashSet<String> {
             .into_iter()
         })
         .chain(ps.iter().map(|p| format!("/posts/{}", p.slug)))
+        .chain(rs.iter().map(|r| match r.prefix {
+            true => format!("{}{}", r.from, PREFIX_MARKER),
+            false => r.from.clone(),
+        }))
         .collect::<HashSet<String>>()
 }
 
+fn is_valid_link(links: &HashSet<String>, link: &str) -> bool {
+    links.contains(link)
+        || links
+            .iter()
+            .filter_map(|l| l.strip_suffix(PREFIX_MARKER))
+            .any(|prefix| strip_path_prefix(link, prefix).is_some())
+}
+
+/// Returns the destinations of the links and images in the content, in order. Broken reference links are skipped.
+pub fn find_links(content: &str) -> Vec<String> {
+    let (_, parser) = pulldown_parser(content);
+    parser
+        .filter_map(|evt| match evt {
+            Event::Start(Tag::Image { dest_url, .. }) | Event::Start(Tag::Link { dest_url, .. }) => Some(dest_url.to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Rewrite the destinations of the links to the post `from` so that they point to the post `to`, leaving the rest of
+/// the content untouched. Both inline links and reference definitions are rewritten. Returns None if the content has
+/// no such links.
+pub fn rewrite_post_links(content: &str, from: &str, to: &str) -> Option<String> {
+    let from_path = format!("/posts/{}", from);
+    let to_path = format!("/posts/{}", to);
+    let is_match = |dest: &str| {
+        dest.strip_prefix(from_path.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('#'))
+    };
+    let (_, parser) = pulldown_parser(content);
+    let mut iter = parser.into_offset_iter();
+    // The offsets of the destinations to replace. The destination of an inline link is the first thing after the link
+    // text, so we track where the text ends. The destination of a reference definition is the first thing after the label.
+    let mut offsets = vec![];
+    let mut open_link: Option<(usize, Range<usize>)> = None;
+    for (event, range) in iter.by_ref() {
+        match event {
+            Event::Start(Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url,
+                ..
+            }) if is_match(&dest_url) => open_link = Some((range.start, range)),
+            Event::End(TagEnd::Link) => {
+                if let Some((text_end, link)) = open_link.take() {
+                    offsets.extend(
+                        content
+                            .get(text_end..link.end)
+                            .and_then(|s| s.find(from_path.as_str()))
+                            .map(|o| text_end + o),
+                    );
+                }
+            }
+            _ => {
+                if let Some((text_end, _)) = open_link.as_mut() {
+                    *text_end = (*text_end).max(range.end);
+                }
+            }
+        }
+    }
+    for (_, def) in iter.reference_definitions().iter() {
+        if is_match(&def.dest) {
+            offsets.extend(
+                content
+                    .get(def.span.clone())
+                    .and_then(|s| s.find(from_path.as_str()))
+                    .map(|o| def.span.start + o),
+            );
+        }
+    }
+    if offsets.is_empty() {
+        return None;
+    }
+    offsets.sort();
+    offsets.dedup();
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for offset in offsets {
+        out.push_str(content.get(last..offset)?);
+        out.push_str(to_path.as_str());
+        last = offset + from_path.len();
+    }
+    out.push_str(content.get(last..)?);
+    Some(out)
+}
+
+/// Convert the markdown content to html, returning it along with the table of contents. Links are checked against the
+/// valid links unless there are none, and images in the store are given the dimensions and alt text from their
+/// metadata, keyed by the original image.
 #[instrument(skip_all, err)]
-pub fn convert(content: &str, valid_links: &HashSet<String>) -> Result<(String, String), anyhow::Error> {
+pub fn convert(
+    content: &str,
+    valid_links: &HashSet<String>,
+    image_metadata: &BTreeMap<Image, ImageMetadata>,
+) -> Result<(String, String), anyhow::Error> {
     let (error_capture, parser) = pulldown_parser(content);
     let mut hn = HeadingChecker {
         level: 0,
@@ -60,15 +164,22 @@ pub fn convert(content: &str, valid_links: &HashSet<String>) -> Result<(String,
         toc: String::new(),
     };
     let lc = RelativeLinkChecker { links: valid_links };
+    let mut iw = ImageTagWriter {
+        metadata: image_metadata,
+        current: None,
+    };
     let mut output = String::new();
     {
         let mapped_parser = parser.map(|evt| {
-            lc.observe(&evt).and_then(|_| hn.observe(&evt)).unwrap_or_else(|e| {
-                if let Ok(mut l) = error_capture.as_ref().lock() {
-                    l.replace(e);
-                }
-                evt.clone()
-            })
+            lc.observe(&evt)
+                .and_then(|_| hn.observe(&evt))
+                .map(|evt| iw.observe(evt))
+                .unwrap_or_else(|e| {
+                    if let Ok(mut l) = error_capture.as_ref().lock() {
+                        l.replace(e);
+                    }
+                    evt.clone()
+                })
         });
         html::push_html(&mut output, mapped_parser);
     };
@@ -95,7 +206,7 @@ impl RelativeLinkChecker<'_> {
         };
         if let Some((link_type, dest_url)) = capture
             .filter(|_| !self.links.is_empty())
-            .filter(|(_, dl)| !dl.starts_with("http://") && !dl.starts_with("https://") && !self.links.contains(&dl.to_string()))
+            .filter(|(_, dl)| !dl.starts_with("http://") && !dl.starts_with("https://") && !is_valid_link(self.links, dl))
         {
             return Err(anyhow!(
                 "{} '{}' references a relative path which does not exist",
@@ -107,6 +218,111 @@ impl RelativeLinkChecker<'_> {
     }
 }
 
+/// Writes the `<img>` tags of images in the store along with their dimensions, so that the page does not shift as they
+/// load. The alt text and caption of the image's metadata are used when the markdown has no alt text or title. Images
+/// with responsive variants are wrapped in a `<picture>` offering the AVIF and WebP widths, so that the browser picks
+/// the smallest one which is sharp on the screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ImageTagWriter<'a> {
+    metadata: &'a BTreeMap<Image, ImageMetadata>,
+    current: Option<PendingImage>,
+}
+
+/// An image tag which is being written, while its alt text is collected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PendingImage {
+    src: String,
+    title: String,
+    alt: String,
+    default_alt: String,
+    dimensions: Option<(u32, u32)>,
+    /// The type and srcset of each `<source>` in the `<picture>`, if there is one.
+    sources: Vec<(&'static str, String)>,
+}
+
+impl ImageTagWriter<'_> {
+    /// The events making up an image are swallowed until its end, which is replaced with the whole tag.
+    fn observe<'a>(&mut self, event: Event<'a>) -> Event<'a> {
+        match (&mut self.current, &event) {
+            (None, Event::Start(Tag::Image { dest_url, title, .. })) => {
+                let Some((image, metadata)) = dest_url
+                    .strip_prefix("/images/")
+                    .and_then(|rest| Image::try_from_path_part(PathPart::from(rest)).ok())
+                    .and_then(|image| self.metadata.get(&image.to_original()).map(|m| (image, m)))
+                else {
+                    return event;
+                };
+                self.current = Some(PendingImage {
+                    src: dest_url.to_string(),
+                    title: match title.is_empty() {
+                        true => metadata.caption.clone(),
+                        false => title.to_string(),
+                    },
+                    alt: String::new(),
+                    default_alt: metadata.alt.clone(),
+                    dimensions: metadata.variant_dimensions(&image),
+                    sources: [
+                        ("image/avif", Image::to_avif_width as fn(&Image, u32) -> Image),
+                        ("image/webp", Image::to_webp_width),
+                    ]
+                    .into_iter()
+                    .filter(|_| !metadata.widths.is_empty())
+                    .map(|(content_type, variant)| {
+                        let srcset = metadata
+                            .widths
+                            .iter()
+                            .map(|w| format!("/images/{} {}w", variant(&image, *w), w))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        (content_type, srcset)
+                    })
+                    .collect(),
+                });
+                Event::Text(CowStr::from(""))
+            }
+            (None, _) => event,
+            (Some(_), Event::End(TagEnd::Image)) => {
+                let Some(pending) = self.current.take() else {
+                    return event;
+                };
+                let alt = match pending.alt.trim().is_empty() {
+                    true => pending.default_alt,
+                    false => pending.alt,
+                };
+                let img = html! {
+                    img src=(pending.src) alt=(alt) title=[Some(pending.title).filter(|t| !t.is_empty())]
+                        width=[pending.dimensions.map(|(w, _)| w)] height=[pending.dimensions.map(|(_, h)| h)];
+                };
+                if pending.sources.is_empty() {
+                    return Event::InlineHtml(CowStr::from(img.0));
+                }
+                // The image is shown at the width of the variant in the markdown, or narrower on small screens.
+                let sizes = pending.dimensions.map(|(w, _)| format!("(max-width: {w}px) 100vw, {w}px"));
+                Event::InlineHtml(CowStr::from(
+                    html! {
+                        picture {
+                            @for (content_type, srcset) in pending.sources {
+                                source type=(content_type) srcset=(srcset) sizes=[sizes.as_ref()];
+                            }
+                            (img)
+                        }
+                    }
+                    .0,
+                ))
+            }
+            (Some(pending), Event::Text(text) | Event::Code(text) | Event::InlineHtml(text)) => {
+                pending.alt.push_str(text);
+                Event::Text(CowStr::from(""))
+            }
+            (Some(pending), Event::SoftBreak | Event::HardBreak) => {
+                pending.alt.push(' ');
+                Event::Text(CowStr::from(""))
+            }
+            (Some(_), _) => Event::Text(CowStr::from("")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
 struct HeadingChecker {
     level: i16,
@@ -227,6 +443,7 @@ _italic_
 ~~strike~~
 ",
             &HashSet::new(),
+            &BTreeMap::new(),
         )
         .unwrap_or_else(|e| (e.to_string(), String::new()));
         assert_eq!(
@@ -251,20 +468,117 @@ _italic_
 [internal](/some-link)
 ![internal](/does-not-exist)
 ",
-                &HashSet::from(["/some-link".to_string()])
+                &HashSet::from(["/some-link".to_string()]),
+                &BTreeMap::new()
             )
             .unwrap_or_else(|e| (e.to_string(), String::new()))
             .0,
             "image '/does-not-exist' references a relative path which does not exist",
         );
         assert_eq!(
-            convert(r"![internal](/does-not-exist)", &HashSet::new())
+            convert(r"![internal](/does-not-exist)", &HashSet::new(), &BTreeMap::new())
                 .unwrap_or_else(|e| (e.to_string(), String::new()))
                 .0,
             "<p><img src=\"/does-not-exist\" alt=\"internal\" /></p>\n",
         );
     }
 
+    #[test]
+    fn test_rewrite_post_links() {
+        let content = r"
+[old](/posts/old) and [older](/posts/older) and [anchor](/posts/old#heading 'about /posts/old')
+`[code](/posts/old)` and [reference][ref]
+
+[ref]: /posts/old
+";
+        assert_eq!(
+            rewrite_post_links(content, "old", "new").as_deref(),
+            Some(
+                r"
+[old](/posts/new) and [older](/posts/older) and [anchor](/posts/new#heading 'about /posts/old')
+`[code](/posts/old)` and [reference][ref]
+
+[ref]: /posts/new
+"
+            )
+        );
+        assert_eq!(rewrite_post_links(content, "other", "new"), None);
+    }
+
+    #[test]
+    fn test_image_tags() {
+        let metadata = ImageMetadata {
+            alt: "A photo".to_string(),
+            caption: "Caption".to_string(),
+            width: 1600,
+            height: 900,
+            ..ImageMetadata::default()
+        };
+        let images = BTreeMap::from([(Image::Webp { slug: Arc::from("photo") }, metadata)]);
+        let (content, _) = convert(
+            r#"![](/images/photo.webp) ![My *own* alt](/images/photo.medium.jpg "Title") ![](/images/other.webp)"#,
+            &HashSet::new(),
+            &images,
+        )
+        .unwrap_or_else(|e| (e.to_string(), String::new()));
+        assert_eq!(
+            content,
+            r#"<p><img src="/images/photo.webp" alt="A photo" title="Caption" width="1600" height="900"> <img src="/images/photo.medium.jpg" alt="My own alt" title="Title" width="800" height="450"> <img src="/images/other.webp" alt="" /></p>
+"#
+        );
+
+        // Images with responsive variants are offered at each width.
+        let images = images
+            .into_iter()
+            .map(|(image, metadata)| {
+                let widths = vec![400, 800];
+                (image, ImageMetadata { widths, ..metadata })
+            })
+            .collect::<BTreeMap<_, _>>();
+        let (content, _) =
+            convert("![](/images/photo.medium.jpg)", &HashSet::new(), &images).unwrap_or_else(|e| (e.to_string(), String::new()));
+        assert_eq!(
+            content,
+            r#"<p><picture><source type="image/avif" srcset="/images/photo.w400.avif 400w, /images/photo.w800.avif 800w" sizes="(max-width: 800px) 100vw, 800px"><source type="image/webp" srcset="/images/photo.w400.webp 400w, /images/photo.w800.webp 800w" sizes="(max-width: 800px) 100vw, 800px"><img src="/images/photo.medium.jpg" alt="A photo" title="Caption" width="800" height="450"></picture></p>
+"#
+        );
+    }
+
+    #[test]
+    fn test_redirect_links() {
+        let redirects = [
+            Redirect {
+                from: "/old".to_string(),
+                to: "/posts/new".to_string(),
+                ..Redirect::default()
+            },
+            Redirect {
+                from: "/blog/".to_string(),
+                to: "/posts/".to_string(),
+                prefix: true,
+                ..Redirect::default()
+            },
+        ];
+        let valid_links = build_valid_links(&[], &[], &redirects);
+        assert!(convert("[old](/old) [prefixed](/blog/some-post)", &valid_links, &BTreeMap::new()).is_ok());
+        assert!(convert("[old](/old/child)", &valid_links, &BTreeMap::new()).is_err());
+        assert!(convert("[prefix](/blog)", &valid_links, &BTreeMap::new()).is_err());
+
+        // Prefixes without a trailing slash still only match whole segments.
+        let redirects = [Redirect {
+            from: "/blog".to_string(),
+            to: "/posts".to_string(),
+            prefix: true,
+            ..Redirect::default()
+        }];
+        let valid_links = build_valid_links(&[], &[], &redirects);
+        assert!(convert("[a](/blog) [b](/blog/some-post)", &valid_links, &BTreeMap::new()).is_ok());
+        assert!(convert("[roll](/blogroll)", &valid_links, &BTreeMap::new()).is_err());
+        assert_eq!(redirects[0].location("/blog/some-post"), Some("/posts/some-post".to_string()));
+        assert_eq!(redirects[0].location("/blogroll"), None);
+        assert_eq!(redirects[0].location("/blog-archive/x"), None);
+    }
+
     #[test]
     fn test_bad_heading() {
         assert_eq!(
@@ -276,7 +590,8 @@ _italic_
 # unindented
 ### not fine
 ",
-                &HashSet::new()
+                &HashSet::new(),
+                &BTreeMap::new()
             )
             .unwrap_or_else(|e| (e.to_string(), String::new()))
             .0,
@@ -294,6 +609,7 @@ _italic_
 # unindented
 ",
             &HashSet::new(),
+            &BTreeMap::new(),
         )
         .unwrap_or_else(|e| (e.to_string(), String::new()));
         assert_eq!(
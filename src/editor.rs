@@ -1,42 +1,83 @@
+pub(crate) mod auth;
 mod views;
 
-use super::store::{Image, Post, Store};
+use super::store::{ConflictError, ExpectedVersion, Image, ImageInUseError, ImageMetadata, Post, Store};
 use crate::htmx::HtmxContext;
+use crate::signing::{preview_purpose, SigningKey};
 use crate::statics::{get_favicon_ico_handler, get_static_handler};
-use crate::{conversion, customhttptrace, statics};
-use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
-use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use crate::{conversion, customhttptrace, statics, store};
+use auth::AuthConfig;
+use auth::Session;
+use axum::body::Body;
+use axum::extract::{DefaultBodyLimit, FromRequest, Multipart, Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri};
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Redirect, Response};
 use axum::routing::{delete, get, post};
-use axum::{Form, Router};
+use axum::{middleware, Extension, Form, Router};
+use bytes::Bytes;
 use chrono::NaiveDate;
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
 use image::EncodableLayout;
+use itertools::Itertools;
+use log::info;
 use maud::PreEscaped;
 use object_store::path::PathPart;
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 use std::collections::HashSet;
+use std::fmt::Display;
+use std::str::FromStr;
 use std::sync::Arc;
 use tower_http::trace::TraceLayer;
+use url::Url;
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone)]
+#[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
+    pub auth: AuthConfig,
+    pub preview_key: Option<SigningKey>,
+    pub base_url: Option<Url>,
 }
 
-impl Default for Config {
-    fn default() -> Config {
-        Config { port: 8080 }
+/// How long the preview links shown on the edit page remain valid.
+const PREVIEW_LINK_TTL: TimeDelta = TimeDelta::days(7);
+
+impl Config {
+    /// Returns a link to the viewer which allows a draft post to be viewed before it is published. The link is only
+    /// absolute if the base url of the viewer is known.
+    fn preview_link(&self, post: &Post) -> Option<String> {
+        self.preview_key.as_ref().filter(|_| !post.is_live(Utc::now())).map(|k| {
+            let token = k.sign_expiring(preview_purpose(&post.slug).as_str(), Utc::now() + PREVIEW_LINK_TTL);
+            let path = format!("/posts/{}?preview={}", post.slug, token);
+            match self.base_url.as_ref().and_then(|b| b.join(path.as_str()).ok()) {
+                Some(u) => u.to_string(),
+                None => path,
+            }
+        })
     }
 }
 
 pub async fn run(cfg: Config, store: Store) -> Result<(), anyhow::Error> {
+    store.check_schema_version().await?;
+    if let Some(n) = store.ensure_post_index().await? {
+        info!("Built the post index with {} posts", n);
+    }
+    if let Some(n) = store.ensure_image_index().await? {
+        info!("Built the image index with {} images", n);
+    }
+    let port = cfg.port;
+    let auth_config = Arc::new(cfg.auth.clone());
     let app = Router::new()
         .route("/", get(home_handler))
+        .route(auth::LOGIN_PATH, get(login_handler))
+        .route(auth::LOGIN_PATH, post(submit_login_handler))
+        .route(auth::LOGOUT_PATH, post(submit_logout_handler))
         .route(statics::FAVICON_ICO, get(get_favicon_ico_handler))
         .route(statics::ROUTE, get(get_static_handler))
         .route("/images", get(list_images_handler))
         .route("/images", post(submit_image_handler))
         .route("/images/{slug}", get(get_image_handler))
+        .route("/images/{slug}", post(submit_image_metadata_handler))
         .route("/images/{slug}", delete(submit_delete_image_handler))
         .route("/posts", get(posts_handler))
         .route("/posts/new", get(new_post_handler))
@@ -44,11 +85,26 @@ pub async fn run(cfg: Config, store: Store) -> Result<(), anyhow::Error> {
         .route("/posts/{id}", get(edit_post_handler))
         .route("/posts/{id}", post(submit_edit_post_handler))
         .route("/posts/{id}", delete(submit_delete_post_handler))
+        .route("/posts/{id}/rename", post(submit_rename_post_handler))
+        .route("/posts/{id}/revisions", get(list_revisions_handler))
+        .route("/posts/{id}/revisions/{revision}", get(get_revision_handler))
+        .route("/posts/{id}/revisions/{revision}/restore", post(submit_restore_revision_handler))
+        .route("/posts/{id}/diff", get(diff_handler))
+        .route("/redirects", get(list_redirects_handler))
+        .route("/redirects", post(submit_redirect_handler))
+        .route("/redirects/delete", post(submit_delete_redirect_handler))
         .route("/debug", get(debug_handler))
+        .route("/debug/rebuild-index", post(submit_rebuild_index_handler))
+        .route("/debug/repair", post(submit_repair_handler))
+        .route("/debug/fsck", get(fsck_handler))
+        .route("/debug/fsck", post(submit_fsck_handler))
         .route("/livez", get(livez_handler))
         .route("/readyz", get(readyz_handler))
         .fallback(not_found_handler)
         .layer(DefaultBodyLimit::disable())
+        .layer(middleware::from_fn_with_state(auth_config.clone(), verify_csrf))
+        .layer(middleware::from_fn_with_state(auth_config.clone(), auth::require_session))
+        .layer(Extension(Arc::new(cfg)))
         .with_state(Arc::new(store))
         .layer(
             TraceLayer::new_for_http()
@@ -57,7 +113,7 @@ pub async fn run(cfg: Config, store: Store) -> Result<(), anyhow::Error> {
                 .on_response(customhttptrace::HttpTraceLayerHooks)
                 .on_failure(customhttptrace::HttpTraceLayerHooks),
         );
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", cfg.port)).await?;
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
     axum::serve(listener, app).await?;
     Ok(())
 }
@@ -86,6 +142,69 @@ where
     }
 }
 
+/// Find the CSRF token in the form body when the request was not made by htmx and so does not carry the header. The
+/// body is buffered so that it can be handed on to the handler afterwards.
+async fn csrf_token_from_body(request: Request) -> Result<(Option<String>, Request), anyhow::Error> {
+    let (parts, body) = request.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await?;
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|hv| hv.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let token = if content_type.starts_with("application/x-www-form-urlencoded") {
+        url::form_urlencoded::parse(bytes.as_ref())
+            .find(|(k, _)| k == auth::CSRF_FIELD)
+            .map(|(_, v)| v.to_string())
+    } else if content_type.starts_with("multipart/form-data") {
+        let inner = Request::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(bytes.clone()))?;
+        let mut multipart = Multipart::from_request(inner, &()).await?;
+        let mut token = None;
+        while let Some(f) = multipart.next_field().await? {
+            if f.name().is_some_and(|n| n == auth::CSRF_FIELD) {
+                token = Some(f.text().await?);
+                break;
+            }
+        }
+        token
+    } else {
+        None
+    };
+    Ok((token, Request::from_parts(parts, Body::from(bytes))))
+}
+
+/// The middleware layer which rejects state-changing requests that do not carry the CSRF token of the session,
+/// either in the htmx header or in the form body.
+async fn verify_csrf(State(auth_config): State<Arc<AuthConfig>>, request: Request, next: Next) -> Response {
+    if !matches!(*request.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE) {
+        return next.run(request).await;
+    }
+    let Some(session) = request.extensions().get::<Session>().cloned() else {
+        // Only the public routes such as login get here without a session.
+        return next.run(request).await;
+    };
+    let htmx_context = HtmxContext::try_from(request.headers()).map(Box::new).ok();
+    let (token, request) = match request.headers().get(auth::CSRF_HEADER).and_then(|hv| hv.to_str().ok()) {
+        Some(t) => (Some(t.to_string()), request),
+        None => match csrf_token_from_body(request).await {
+            Ok(x) => x,
+            Err(e) => return views::internal_error_page(e, htmx_context),
+        },
+    };
+    if token.is_some_and(|t| auth_config.verify_csrf_token(&session, t.as_str())) {
+        next.run(request).await
+    } else {
+        tracing::event!(tracing::Level::WARN, "rejected request with missing or invalid csrf token");
+        views::forbidden_page(
+            "The form was missing a valid CSRF token. Please reload the page and try again.",
+            htmx_context,
+        )
+    }
+}
+
 async fn not_found_handler(method: Method, uri: Uri, headers: HeaderMap) -> Result<Response, ResponseError> {
     Ok(views::not_found_page(
         method,
@@ -105,6 +224,62 @@ async fn home_handler(headers: HeaderMap) -> Result<Response, ResponseError> {
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+async fn login_handler(headers: HeaderMap, Query(query): Query<LoginQuery>) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    Ok(views::login_page(auth::sanitize_next(query.next), None, htmx_context))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LoginForm {
+    password: String,
+    next: Option<String>,
+}
+
+async fn submit_login_handler(
+    Extension(cfg): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let auth_config = &cfg.auth;
+    let next = auth::sanitize_next(form.next);
+    // Password verification is intentionally slow, so keep it off the async workers.
+    let verifier = cfg.clone();
+    let ok = tokio::task::spawn_blocking(move || verifier.auth.verify_password(form.password.as_str()))
+        .await
+        .map_resp_err(&htmx_context)?;
+    if !ok {
+        tracing::event!(tracing::Level::WARN, "failed login attempt");
+        return Ok(views::login_page(next, Some("Incorrect password".to_string()), htmx_context));
+    }
+    let (_, cookie) = auth_config.new_session();
+    let set_cookie = auth::session_cookie_header(&headers, cookie.as_str(), auth_config.ttl()).map_resp_err(&htmx_context)?;
+    // Use a full page load rather than a swap after login so that the body picks up the csrf token of the new session.
+    let mut resp = match htmx_context {
+        None => Redirect::to(next.as_str()).into_response(),
+        Some(_) => {
+            let mut hm = HeaderMap::new();
+            hm.insert("HX-Redirect", HeaderValue::from_str(next.as_str()).map_resp_err(&htmx_context)?);
+            (StatusCode::NO_CONTENT, hm).into_response()
+        }
+    };
+    resp.headers_mut().insert("Set-Cookie", set_cookie);
+    Ok(resp)
+}
+
+async fn submit_logout_handler(headers: HeaderMap) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let set_cookie = auth::session_cookie_header(&headers, "", TimeDelta::zero()).map_resp_err(&htmx_context)?;
+    let mut resp = redirect_response(auth::LOGIN_PATH, htmx_context)?;
+    resp.headers_mut().insert("Set-Cookie", set_cookie);
+    Ok(resp)
+}
+
 async fn posts_handler(headers: HeaderMap, State(store): State<Arc<Store>>) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
     let mut posts = store.list_posts().await.map_resp_err(&htmx_context)?;
@@ -118,6 +293,19 @@ async fn new_post_handler(headers: HeaderMap) -> Result<Response, ResponseError>
     Ok(views::new_posts_page(None, None, htmx_context))
 }
 
+/// Optional form fields are submitted as empty strings when left blank, so treat those as missing.
+fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match Option::<String>::deserialize(de)?.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => s.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
 #[derive(Debug, Default, Deserialize)]
 struct NewPostForm {
     slug: String,
@@ -126,6 +314,16 @@ struct NewPostForm {
     published: Option<bool>,
     raw_content: String,
     labels: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    summary: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    updated: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    author: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    cover_image: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    publish_at: Option<DateTime<FixedOffset>>,
 }
 
 async fn submit_new_post_handler(
@@ -144,18 +342,23 @@ async fn submit_new_post_handler(
             .split(",")
             .filter_map(|s| Some(s.to_string()).filter(|s| !s.is_empty()))
             .collect(),
+        summary: form.summary,
+        updated: form.updated,
+        author: form.author,
+        cover_image: form.cover_image,
+        publish_at: form.publish_at,
     };
-    if store.get_post_raw(form.slug.as_str()).await.map_resp_err(&htmx_context)?.is_some() {
+    if let Err(e) = store
+        .upsert_post(&temporary_post, form.raw_content.as_str(), &ExpectedVersion::Absent)
+        .await
+    {
+        let error = match e.downcast_ref::<ConflictError>() {
+            Some(_) => "slug already exists".to_string(),
+            None => e.to_string(),
+        };
         return Ok(views::new_posts_page(
             Some((&temporary_post, form.raw_content.as_str())),
-            Some("slug already exists".to_string()),
-            htmx_context,
-        ));
-    }
-    if let Err(e) = store.upsert_post(&temporary_post, form.raw_content.as_str()).await {
-        return Ok(views::new_posts_page(
-            Some((&temporary_post, form.raw_content.as_str())),
-            Some(e.to_string()),
+            Some(error),
             htmx_context,
         ));
     }
@@ -178,27 +381,41 @@ async fn edit_post_handler(
     Path(id): Path<String>,
     headers: HeaderMap,
     State(store): State<Arc<Store>>,
+    Extension(cfg): Extension<Arc<Config>>,
 ) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
-    match store.get_post_raw(&id).await.map_resp_err(&htmx_context)? {
-        Some((post, raw_content)) => match conversion::convert(raw_content.as_str(), &HashSet::new()) {
-            Ok((html_output, toc)) => Ok(views::edit_posts_page(
-                post,
-                raw_content,
-                PreEscaped(html_output),
-                PreEscaped(toc),
-                None,
-                htmx_context,
-            )),
-            Err(e) => Ok(views::edit_posts_page(
-                post,
-                raw_content,
-                PreEscaped::default(),
-                PreEscaped::default(),
-                Some(e.to_string()),
-                htmx_context,
-            )),
-        },
+    match store.get_post_raw_with_e_tag(&id).await.map_resp_err(&htmx_context)? {
+        Some((post, raw_content, e_tag)) => {
+            let preview_link = cfg.preview_link(&post);
+            let image_metadata = store.list_image_metadata().await.map_resp_err(&htmx_context)?;
+            match conversion::convert(raw_content.as_str(), &HashSet::new(), &image_metadata) {
+                Ok((html_output, toc)) => Ok(views::edit_posts_page(
+                    post,
+                    raw_content,
+                    PreEscaped(html_output),
+                    PreEscaped(toc),
+                    views::EditPostState {
+                        version: e_tag,
+                        preview_link,
+                        ..Default::default()
+                    },
+                    htmx_context,
+                )),
+                Err(e) => Ok(views::edit_posts_page(
+                    post,
+                    raw_content,
+                    PreEscaped::default(),
+                    PreEscaped::default(),
+                    views::EditPostState {
+                        version: e_tag,
+                        preview_link,
+                        error: Some(e.to_string()),
+                        ..Default::default()
+                    },
+                    htmx_context,
+                )),
+            }
+        }
         None => Ok(views::not_found_page(
             Method::GET,
             uri,
@@ -214,10 +431,23 @@ struct EditPostForm {
     published: Option<bool>,
     raw_content: String,
     labels: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    summary: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    updated: Option<NaiveDate>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    author: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    cover_image: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    publish_at: Option<DateTime<FixedOffset>>,
+    /// The etag of the content which the form was loaded from, if known.
+    version: Option<String>,
 }
 
 async fn submit_edit_post_handler(
     State(store): State<Arc<Store>>,
+    Extension(cfg): Extension<Arc<Config>>,
     headers: HeaderMap,
     Path(slug): Path<String>,
     Form(form): Form<EditPostForm>,
@@ -233,17 +463,204 @@ async fn submit_edit_post_handler(
             .split(",")
             .filter_map(|s| Some(s.to_string()).filter(|s| !s.is_empty()))
             .collect(),
+        summary: form.summary,
+        updated: form.updated,
+        author: form.author,
+        cover_image: form.cover_image,
+        publish_at: form.publish_at,
+    };
+    let version = form.version.filter(|v| !v.is_empty());
+    let expected = match version.clone() {
+        Some(e_tag) => ExpectedVersion::ETag(e_tag),
+        None => ExpectedVersion::Any,
     };
-    let ((html_content, toc), error) = match store.upsert_post(&temporary_post, form.raw_content.as_str()).await {
-        Err(e) => ((String::new(), String::new()), Some(e.to_string())),
-        Ok((html_content, toc)) => ((html_content, toc), None),
+    let preview_link = cfg.preview_link(&temporary_post);
+    match store.upsert_post(&temporary_post, form.raw_content.as_str(), &expected).await {
+        Ok(upserted) => Ok(views::edit_posts_page(
+            temporary_post,
+            form.raw_content,
+            PreEscaped(upserted.html_content),
+            PreEscaped(upserted.toc),
+            views::EditPostState {
+                version: upserted.e_tag,
+                preview_link,
+                ..Default::default()
+            },
+            htmx_context,
+        )),
+        Err(e) if e.is::<ConflictError>() => {
+            // Keep the submitted changes in the form, but show what was saved in the meantime. The form now carries
+            // the current version, so submitting again deliberately overwrites it.
+            let current = store.get_post_raw_with_e_tag(&slug).await.map_resp_err(&htmx_context)?;
+            let (error, conflict, e_tag) = match current {
+                Some((current_post, current_content, e_tag)) => {
+                    let diff = views::render_diff(
+                        revision_text(&current_post, current_content.as_str()).as_str(),
+                        revision_text(&temporary_post, form.raw_content.as_str()).as_str(),
+                    );
+                    (
+                        "This post was changed by someone else since you loaded it, and your changes have not been saved. \
+                        Review the differences below and submit again to overwrite it."
+                            .to_string(),
+                        Some(views::conflict_section(current_content, diff)),
+                        e_tag,
+                    )
+                }
+                None => (
+                    "This post was deleted since you loaded it. Submit again to recreate it.".to_string(),
+                    None,
+                    None,
+                ),
+            };
+            Ok(views::edit_posts_page(
+                temporary_post,
+                form.raw_content,
+                PreEscaped::default(),
+                PreEscaped::default(),
+                views::EditPostState {
+                    version: e_tag,
+                    preview_link,
+                    error: Some(error),
+                    conflict,
+                },
+                htmx_context,
+            ))
+        }
+        Err(e) => Ok(views::edit_posts_page(
+            temporary_post,
+            form.raw_content,
+            PreEscaped::default(),
+            PreEscaped::default(),
+            views::EditPostState {
+                version,
+                preview_link,
+                error: Some(e.to_string()),
+                ..Default::default()
+            },
+            htmx_context,
+        )),
+    }
+}
+
+/// The text compared between revisions. The metadata is included as a header so that changes to it show in the diff.
+fn revision_text(post: &Post, content: &str) -> String {
+    format!(
+        "title: {}\ndate: {}\npublished: {}\nlabels: {}\nsummary: {}\nupdated: {}\nauthor: {}\ncover image: {}\npublish at: {}\n\n{}",
+        post.title,
+        post.date,
+        post.published,
+        post.labels.join(","),
+        post.summary.as_deref().unwrap_or_default(),
+        post.updated.map(|d| d.to_string()).unwrap_or_default(),
+        post.author.as_deref().unwrap_or_default(),
+        post.cover_image.as_deref().unwrap_or_default(),
+        post.publish_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+        content
+    )
+}
+
+async fn list_revisions_handler(
+    uri: Uri,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    State(store): State<Arc<Store>>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let Some((post, _)) = store.get_post_raw(&id).await.map_resp_err(&htmx_context)? else {
+        return Ok(views::not_found_page(Method::GET, uri, htmx_context));
+    };
+    let revisions = store.list_revisions(&id).await.map_resp_err(&htmx_context)?;
+    Ok(views::list_revisions_page(post, revisions, htmx_context))
+}
+
+async fn get_revision_handler(
+    uri: Uri,
+    Path((id, revision)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(store): State<Arc<Store>>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    revision_response(&store, uri, id.as_str(), revision.as_str(), None, htmx_context).await
+}
+
+async fn revision_response(
+    store: &Store,
+    uri: Uri,
+    id: &str,
+    revision: &str,
+    error: Option<String>,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> Result<Response, ResponseError> {
+    let Some((current, current_content)) = store.get_post_raw(id).await.map_resp_err(&htmx_context)? else {
+        return Ok(views::not_found_page(Method::GET, uri, htmx_context));
+    };
+    let info = store
+        .list_revisions(id)
+        .await
+        .map_resp_err(&htmx_context)?
+        .into_iter()
+        .find(|r| r.id == revision);
+    let (Some(info), Some((post, content))) = (info, store.get_revision(id, revision).await.map_resp_err(&htmx_context)?) else {
+        return Ok(views::not_found_page(Method::GET, uri, htmx_context));
+    };
+    let diff = views::render_diff(
+        revision_text(&post, content.as_str()).as_str(),
+        revision_text(&current, current_content.as_str()).as_str(),
+    );
+    Ok(views::revision_page(current, info, content, diff, error, htmx_context))
+}
+
+async fn submit_restore_revision_handler(
+    uri: Uri,
+    Path((id, revision)): Path<(String, String)>,
+    headers: HeaderMap,
+    State(store): State<Arc<Store>>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let Some((post, content)) = store.get_revision(&id, &revision).await.map_resp_err(&htmx_context)? else {
+        return Ok(views::not_found_page(Method::POST, uri, htmx_context));
     };
-    Ok(views::edit_posts_page(
-        temporary_post,
-        form.raw_content,
-        PreEscaped(html_content),
-        PreEscaped(toc),
-        error,
+    // Restoring goes through the same validation as any other edit, since links may have broken since it was saved.
+    if let Err(e) = store.upsert_post(&post, content.as_str(), &ExpectedVersion::Any).await {
+        let error = format!("failed to restore revision: {}", e);
+        return revision_response(&store, uri, id.as_str(), revision.as_str(), Some(error), htmx_context).await;
+    }
+    redirect_response(format!("/posts/{}", id).as_str(), htmx_context)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DiffQuery {
+    from: Option<String>,
+    to: Option<String>,
+}
+
+async fn diff_handler(
+    uri: Uri,
+    Path(id): Path<String>,
+    Query(query): Query<DiffQuery>,
+    headers: HeaderMap,
+    State(store): State<Arc<Store>>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let revisions = store.list_revisions(&id).await.map_resp_err(&htmx_context)?;
+    // An empty or missing revision refers to the current version of the post.
+    let mut texts = Vec::with_capacity(2);
+    for revision in [&query.from, &query.to] {
+        let found = match revision.as_deref().filter(|r| !r.is_empty()) {
+            Some(r) => store.get_revision(&id, r).await,
+            None => store.get_post_raw(&id).await,
+        }
+        .map_resp_err(&htmx_context)?;
+        match found {
+            Some((post, content)) => texts.push(revision_text(&post, content.as_str())),
+            None => return Ok(views::not_found_page(Method::GET, uri, htmx_context)),
+        }
+    }
+    Ok(views::diff_page(
+        id.as_str(),
+        views::revision_label(query.from.as_deref(), &revisions),
+        views::revision_label(query.to.as_deref(), &revisions),
+        views::render_diff(texts[0].as_str(), texts[1].as_str()),
         htmx_context,
     ))
 }
@@ -259,6 +676,52 @@ fn redirect_response(to: &str, htmx_context: Option<Box<HtmxContext>>) -> Result
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct RenamePostForm {
+    slug: String,
+}
+
+async fn submit_rename_post_handler(
+    uri: Uri,
+    State(store): State<Arc<Store>>,
+    Extension(cfg): Extension<Arc<Config>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Form(form): Form<RenamePostForm>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let slug = form.slug.trim();
+    match store.rename_post(id.as_str(), slug).await {
+        Ok(rewritten) => {
+            info!("Renamed post {} to {} and rewrote the links in {:?}", id, slug, rewritten);
+            redirect_response(format!("/posts/{}", slug).as_str(), htmx_context)
+        }
+        Err(e) => {
+            let Some((post, raw_content, e_tag)) = store.get_post_raw_with_e_tag(&id).await.map_resp_err(&htmx_context)? else {
+                return Ok(views::not_found_page(Method::POST, uri, htmx_context));
+            };
+            let error = match e.downcast_ref::<ConflictError>() {
+                Some(_) => format!("failed to rename post: a post with slug '{}' already exists", slug),
+                None => format!("failed to rename post: {}", e),
+            };
+            let preview_link = cfg.preview_link(&post);
+            Ok(views::edit_posts_page(
+                post,
+                raw_content,
+                PreEscaped::default(),
+                PreEscaped::default(),
+                views::EditPostState {
+                    version: e_tag,
+                    preview_link,
+                    error: Some(error),
+                    ..Default::default()
+                },
+                htmx_context,
+            ))
+        }
+    }
+}
+
 async fn submit_delete_post_handler(
     State(store): State<Arc<Store>>,
     headers: HeaderMap,
@@ -272,13 +735,115 @@ async fn submit_delete_post_handler(
 async fn debug_handler(State(store): State<Arc<Store>>, headers: HeaderMap) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
     let objects = store.list_object_meta().await.map_resp_err(&htmx_context)?;
-    Ok(views::debug_objects_page(objects, htmx_context).into_response())
+    Ok(views::debug_objects_page(objects, None, htmx_context).into_response())
+}
+
+async fn submit_rebuild_index_handler(State(store): State<Arc<Store>>, headers: HeaderMap) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let count = store.rebuild_post_index().await.map_resp_err(&htmx_context)?;
+    let image_count = store.rebuild_image_index().await.map_resp_err(&htmx_context)?;
+    let objects = store.list_object_meta().await.map_resp_err(&htmx_context)?;
+    let message = format!(
+        "Rebuilt the post index with {} posts and the image index with {} images.",
+        count, image_count
+    );
+    Ok(views::debug_objects_page(objects, Some(message), htmx_context).into_response())
+}
+
+async fn submit_repair_handler(State(store): State<Arc<Store>>, headers: HeaderMap) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let deleted = store.repair_posts(Store::TX_GRACE_PERIOD).await.map_resp_err(&htmx_context)?;
+    let objects = store.list_object_meta().await.map_resp_err(&htmx_context)?;
+    let message = format!(
+        "Removed {} objects left behind by interrupted writes{}",
+        deleted.len(),
+        deleted.iter().map(|p| format!(" {}", p)).join(",")
+    );
+    Ok(views::debug_objects_page(objects, Some(message), htmx_context).into_response())
+}
+
+async fn fsck_handler(State(store): State<Arc<Store>>, headers: HeaderMap) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let problems = store.fsck(false).await.map_resp_err(&htmx_context)?;
+    Ok(views::fsck_page(problems, htmx_context).into_response())
+}
+
+async fn submit_fsck_handler(State(store): State<Arc<Store>>, headers: HeaderMap) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let problems = store.fsck(true).await.map_resp_err(&htmx_context)?;
+    Ok(views::fsck_page(problems, htmx_context).into_response())
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RedirectQuery {
+    from: Option<String>,
+}
+
+async fn list_redirects_handler(
+    State(store): State<Arc<Store>>,
+    headers: HeaderMap,
+    Query(query): Query<RedirectQuery>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let redirects = store.list_redirects().await.map_resp_err(&htmx_context)?;
+    // Editing a redirect prefills the form with it, and submitting replaces it.
+    let current = query.from.and_then(|from| redirects.iter().find(|r| r.from == from).cloned());
+    Ok(views::list_redirects_page(redirects, current, None, htmx_context))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RedirectForm {
+    from: String,
+    to: String,
+    prefix: Option<bool>,
+    permanent: Option<bool>,
+}
+
+async fn submit_redirect_handler(
+    State(store): State<Arc<Store>>,
+    headers: HeaderMap,
+    Form(form): Form<RedirectForm>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let redirect = store::Redirect {
+        from: form.from.trim().to_string(),
+        to: form.to.trim().to_string(),
+        prefix: form.prefix.unwrap_or_default(),
+        permanent: form.permanent.unwrap_or_default(),
+    };
+    if let Err(e) = store.upsert_redirect(&redirect).await {
+        let redirects = store.list_redirects().await.map_resp_err(&htmx_context)?;
+        return Ok(views::list_redirects_page(
+            redirects,
+            Some(redirect),
+            Some(e.to_string()),
+            htmx_context,
+        ));
+    }
+    redirect_response("/redirects", htmx_context)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DeleteRedirectForm {
+    from: String,
+}
+
+async fn submit_delete_redirect_handler(
+    State(store): State<Arc<Store>>,
+    headers: HeaderMap,
+    Form(form): Form<DeleteRedirectForm>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    store.delete_redirect(form.from.as_str()).await.map_resp_err(&htmx_context)?;
+    redirect_response("/redirects", htmx_context)
 }
 
 async fn list_images_handler(State(store): State<Arc<Store>>, headers: HeaderMap) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
     let images = store.list_images().await.map_resp_err(&htmx_context)?;
-    Ok(views::list_images_page(images, None, htmx_context).into_response())
+    let references = store.list_image_references().await.map_resp_err(&htmx_context)?;
+    let metadata = store.list_image_metadata().await.map_resp_err(&htmx_context)?;
+    Ok(views::list_images_page(images, metadata, references, None, htmx_context).into_response())
 }
 
 async fn submit_image_handler(
@@ -287,21 +852,25 @@ async fn submit_image_handler(
     mut multipart: Multipart,
 ) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
-    let error: Option<anyhow::Error> = match multipart.next_field().await.map_resp_err(&htmx_context)? {
-        Some(f) if f.name().is_some_and(|x| x == "slug") => {
-            let pre_slug = f.text().await.map_resp_err(&htmx_context)?;
-            match multipart.next_field().await.map_resp_err(&htmx_context)? {
-                Some(f) if f.name().is_some_and(|x| x == "image") => {
-                    let image_bytes = f.bytes().await.map_resp_err(&htmx_context)?;
-                    store.create_image(pre_slug.as_str(), image_bytes.as_bytes()).await.err()
-                }
-                _ => Some(anyhow::anyhow!("Multipart missing image field")),
-            }
+    let mut pre_slug: Option<String> = None;
+    let mut image_bytes: Option<Bytes> = None;
+    // The fields are matched by name since the form also carries the csrf token.
+    while let Some(f) = multipart.next_field().await.map_resp_err(&htmx_context)? {
+        match f.name() {
+            Some("slug") => pre_slug = Some(f.text().await.map_resp_err(&htmx_context)?),
+            Some("image") => image_bytes = Some(f.bytes().await.map_resp_err(&htmx_context)?),
+            _ => {}
         }
-        _ => Some(anyhow::anyhow!("Multipart missing slug field")),
+    }
+    let error: Option<anyhow::Error> = match (pre_slug, image_bytes) {
+        (Some(pre_slug), Some(image_bytes)) => store.create_image(pre_slug.as_str(), image_bytes.as_bytes()).await.err(),
+        (None, _) => Some(anyhow::anyhow!("Multipart missing slug field")),
+        (_, None) => Some(anyhow::anyhow!("Multipart missing image field")),
     };
     let images = store.list_images().await.map_resp_err(&htmx_context)?;
-    Ok(views::list_images_page(images, error, htmx_context).into_response())
+    let references = store.list_image_references().await.map_resp_err(&htmx_context)?;
+    let metadata = store.list_image_metadata().await.map_resp_err(&htmx_context)?;
+    Ok(views::list_images_page(images, metadata, references, error, htmx_context).into_response())
 }
 
 async fn get_image_handler(
@@ -321,7 +890,8 @@ async fn get_image_handler(
 
     if can_html {
         if store.check_image_exists(&img).await.map_resp_err(&htmx_context)? {
-            Ok(views::get_image_page(&img, htmx_context).into_response())
+            let metadata = store.get_image_metadata(&img).await.map_resp_err(&htmx_context)?;
+            Ok(views::get_image_page(&img, metadata, htmx_context).into_response())
         } else {
             Ok(views::not_found_page(Method::GET, url, htmx_context).into_response())
         }
@@ -334,15 +904,54 @@ async fn get_image_handler(
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct ImageMetadataForm {
+    alt: String,
+    caption: String,
+}
+
+async fn submit_image_metadata_handler(
+    State(store): State<Arc<Store>>,
+    headers: HeaderMap,
+    Path(slug): Path<String>,
+    Form(form): Form<ImageMetadataForm>,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let img = Image::try_from_path_part(PathPart::from(slug)).unwrap_or_default();
+    let metadata = store.get_image_metadata(&img).await.map_resp_err(&htmx_context)?;
+    let updated = ImageMetadata {
+        alt: form.alt.trim().to_string(),
+        caption: form.caption.trim().to_string(),
+        ..metadata.unwrap_or_default()
+    };
+    store.put_image_metadata(&img, &updated).await.map_resp_err(&htmx_context)?;
+    redirect_response(format!("/images/{}", img.to_original()).as_str(), htmx_context)
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DeleteImageQuery {
+    /// Delete the image even though posts still reference it.
+    force: Option<bool>,
+}
+
 async fn submit_delete_image_handler(
     State(store): State<Arc<Store>>,
     headers: HeaderMap,
     Path(slug): Path<String>,
+    Query(query): Query<DeleteImageQuery>,
 ) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
     let img = Image::try_from_path_part(PathPart::from(slug)).unwrap_or_default();
-    store.delete_image(&img).await.map_resp_err(&htmx_context)?;
-    redirect_response("/images", htmx_context)
+    match store.delete_image(&img, query.force.unwrap_or_default()).await {
+        Ok(_) => redirect_response("/images", htmx_context),
+        Err(e) if e.is::<ImageInUseError>() => {
+            let images = store.list_images().await.map_resp_err(&htmx_context)?;
+            let references = store.list_image_references().await.map_resp_err(&htmx_context)?;
+            let metadata = store.list_image_metadata().await.map_resp_err(&htmx_context)?;
+            Ok(views::list_images_page(images, metadata, references, Some(e), htmx_context).into_response())
+        }
+        Err(e) => Err(e).map_resp_err(&htmx_context),
+    }
 }
 
 async fn livez_handler() -> Response {
@@ -0,0 +1,281 @@
+use crate::htmx::HtmxContext;
+use crate::signing::{random_token, SigningKey};
+use anyhow::{anyhow, Error};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Redirect, Response};
+use chrono::{DateTime, SubsecRound, TimeDelta, Utc};
+use std::sync::Arc;
+
+pub(crate) const SESSION_COOKIE: &str = "bloog_session";
+pub(crate) const CSRF_HEADER: &str = "X-CSRF-Token";
+pub(crate) const CSRF_FIELD: &str = "csrf_token";
+pub(crate) const LOGIN_PATH: &str = "/login";
+pub(crate) const LOGOUT_PATH: &str = "/logout";
+
+/// Paths which are available without a session. The health checks must be reachable by the orchestrator and the
+/// embedded statics are public anyway.
+const PUBLIC_PATHS: &[&str] = &["/livez", "/readyz", LOGIN_PATH, "/favicon.ico"];
+const PUBLIC_PREFIXES: &[&str] = &["/statics/"];
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    password_hash: String,
+    key: SigningKey,
+    session_ttl: TimeDelta,
+}
+
+impl AuthConfig {
+    /// Build the config from an argon2 PHC-format password hash and an optional signing key for the session cookies.
+    /// Without a signing key, a random one is generated and sessions will not survive a restart.
+    pub fn new(password_hash: &str, session_key: Option<&str>) -> Result<Self, Error> {
+        PasswordHash::new(password_hash).map_err(|e| anyhow!("invalid password hash: {}", e))?;
+        Ok(Self {
+            password_hash: password_hash.to_string(),
+            key: session_key
+                .map(|k| SigningKey::new(k.as_bytes()))
+                .unwrap_or_else(SigningKey::generate)?,
+            session_ttl: TimeDelta::days(7),
+        })
+    }
+
+    pub(crate) fn verify_password(&self, password: &str) -> bool {
+        PasswordHash::new(self.password_hash.as_str()).is_ok_and(|ph| Argon2::default().verify_password(password.as_bytes(), &ph).is_ok())
+    }
+
+    /// Mint a new session and return it along with the signed cookie value.
+    pub(crate) fn new_session(&self) -> (Session, String) {
+        let session = Session {
+            id: random_token(16),
+            expires: (Utc::now() + self.session_ttl).trunc_subsecs(0),
+        };
+        let payload = session.payload();
+        let signature = self.key.sign(payload.as_str());
+        (session, format!("{}.{}", payload, signature))
+    }
+
+    /// Parse and verify the session cookie value, returning the session if it is valid and not expired.
+    pub(crate) fn verify_session(&self, raw: &str) -> Option<Session> {
+        let (payload, signature) = raw.rsplit_once('.')?;
+        if !self.key.verify(payload, signature) {
+            return None;
+        }
+        let (id, expires) = payload.split_once('.')?;
+        let expires = DateTime::from_timestamp(expires.parse::<i64>().ok()?, 0)?;
+        Some(Session {
+            id: id.to_string(),
+            expires,
+        })
+        .filter(|s| s.expires > Utc::now())
+    }
+
+    /// The CSRF token is derived from the session id, so it changes with each login but needs no storage.
+    pub(crate) fn csrf_token(&self, session: &Session) -> String {
+        self.key.sign(format!("csrf:{}", session.id).as_str())
+    }
+
+    pub(crate) fn verify_csrf_token(&self, session: &Session, token: &str) -> bool {
+        self.key.verify(format!("csrf:{}", session.id).as_str(), token)
+    }
+
+    pub(crate) fn ttl(&self) -> TimeDelta {
+        self.session_ttl
+    }
+}
+
+tokio::task_local! {
+    /// The CSRF token of the session handling the current request. This is scoped by [require_session] so that the
+    /// views can embed it in forms without every handler having to pass it through.
+    static CSRF_TOKEN: String;
+}
+
+/// Returns the CSRF token for the current request, if it has an authenticated session.
+pub(crate) fn current_csrf_token() -> Option<String> {
+    CSRF_TOKEN.try_with(|t| t.clone()).ok()
+}
+
+/// An authenticated editor session. This is added to the request extensions by [require_session].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Session {
+    pub(crate) id: String,
+    pub(crate) expires: DateTime<Utc>,
+}
+
+impl Session {
+    fn payload(&self) -> String {
+        format!("{}.{}", self.id, self.expires.timestamp())
+    }
+}
+
+/// Find the value of the named cookie in the request headers.
+pub(crate) fn get_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all("Cookie")
+        .iter()
+        .filter_map(|hv| hv.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|kv| kv.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+/// Build the Set-Cookie header value for the session cookie. Passing an empty value and zero age clears it.
+pub(crate) fn session_cookie_header(headers: &HeaderMap, value: &str, max_age: TimeDelta) -> Result<HeaderValue, Error> {
+    // Only mark the cookie as secure if we know the browser is talking https to us or to the proxy in front of us.
+    let secure = headers
+        .get("X-Forwarded-Proto")
+        .is_some_and(|hv| hv.as_bytes().eq_ignore_ascii_case(b"https"));
+    Ok(HeaderValue::from_str(
+        format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict{}",
+            SESSION_COOKIE,
+            value,
+            max_age.num_seconds(),
+            if secure { "; Secure" } else { "" }
+        )
+        .as_str(),
+    )?)
+}
+
+fn is_public(path: &str) -> bool {
+    PUBLIC_PATHS.contains(&path) || PUBLIC_PREFIXES.iter().any(|p| path.starts_with(p))
+}
+
+/// Redirect the browser to the login page, remembering where it was trying to go.
+fn login_redirect(uri: &Uri, headers: &HeaderMap) -> Response {
+    let next = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    let to = format!(
+        "{}?{}",
+        LOGIN_PATH,
+        url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("next", next)
+            .finish()
+    );
+    if HtmxContext::try_from(headers).is_ok() {
+        let mut hm = HeaderMap::new();
+        if let Ok(hv) = HeaderValue::from_str(to.as_str()) {
+            // Use a full redirect rather than HX-Location so that the whole page including the nav is replaced.
+            hm.insert("HX-Redirect", hv);
+        }
+        (StatusCode::UNAUTHORIZED, hm).into_response()
+    } else {
+        Redirect::to(to.as_str()).into_response()
+    }
+}
+
+/// Only allow redirecting to local paths after login, otherwise the login page becomes an open redirect. Browsers
+/// treat a backslash like a slash, so `/\evil.com` would leave the site just like `//evil.com`, and the encoded form
+/// is rejected too in case anything decodes it on the way.
+pub(crate) fn sanitize_next(next: Option<String>) -> String {
+    next.filter(|n| {
+        n.starts_with('/')
+            && !n.starts_with("//")
+            && !n.starts_with(LOGIN_PATH)
+            && !n.chars().any(|c| c == '\\' || c.is_control())
+            && !n.to_ascii_lowercase().contains("%5c")
+    })
+    .unwrap_or("/posts".to_string())
+}
+
+/// The middleware layer which rejects requests that do not carry a valid session cookie.
+pub(crate) async fn require_session(State(auth): State<Arc<AuthConfig>>, mut request: Request, next: Next) -> Response {
+    if is_public(request.uri().path()) {
+        return next.run(request).await;
+    }
+    match get_cookie(request.headers(), SESSION_COOKIE).and_then(|c| auth.verify_session(c)) {
+        Some(session) => {
+            let token = auth.csrf_token(&session);
+            request.extensions_mut().insert(session);
+            CSRF_TOKEN.scope(token, next.run(request)).await
+        }
+        None => login_redirect(request.uri(), request.headers()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use argon2::password_hash::{PasswordHasher, SaltString};
+
+    fn hash() -> Result<String, Error> {
+        let salt = SaltString::from_b64("c2FsdHNhbHRzYWx0").map_err(|e| anyhow!(e))?;
+        Ok(Argon2::default()
+            .hash_password(b"password", &salt)
+            .map_err(|e| anyhow!(e))?
+            .to_string())
+    }
+
+    #[test]
+    fn test_invalid_hash() {
+        assert!(AuthConfig::new("not-a-hash", None).is_err());
+    }
+
+    #[test]
+    fn test_verify_password() -> Result<(), Error> {
+        let auth = AuthConfig::new(hash()?.as_str(), None)?;
+        assert!(auth.verify_password("password"));
+        assert!(!auth.verify_password("wrong"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sessions() -> Result<(), Error> {
+        let auth = AuthConfig::new(hash()?.as_str(), Some("key"))?;
+        let (session, raw) = auth.new_session();
+        assert_eq!(auth.verify_session(raw.as_str()), Some(session.clone()));
+        assert_eq!(
+            AuthConfig::new(hash()?.as_str(), Some("key"))?.verify_session(raw.as_str()),
+            Some(session)
+        );
+        assert_eq!(AuthConfig::new(hash()?.as_str(), Some("other"))?.verify_session(raw.as_str()), None);
+        assert_eq!(auth.verify_session(raw.replace('.', "x").as_str()), None);
+
+        let expired = Session {
+            id: "abc".to_string(),
+            expires: Utc::now() - TimeDelta::seconds(1),
+        };
+        let expired_raw = format!("{}.{}", expired.payload(), auth.key.sign(expired.payload().as_str()));
+        assert_eq!(auth.verify_session(expired_raw.as_str()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_csrf_tokens() -> Result<(), Error> {
+        let auth = AuthConfig::new(hash()?.as_str(), Some("key"))?;
+        let (session, _) = auth.new_session();
+        let (other_session, _) = auth.new_session();
+        let token = auth.csrf_token(&session);
+        assert!(auth.verify_csrf_token(&session, token.as_str()));
+        assert!(!auth.verify_csrf_token(&other_session, token.as_str()));
+        assert!(!auth.verify_csrf_token(&session, ""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sanitize_next() {
+        assert_eq!(sanitize_next(Some("/posts/some-post?x=1".to_string())), "/posts/some-post?x=1");
+        for next in [
+            "https://evil.com",
+            "//evil.com",
+            "/\\evil.com",
+            "/%5Cevil.com",
+            "/%5cevil.com",
+            "/\tevil.com",
+            "/login?next=/images",
+        ] {
+            assert_eq!(sanitize_next(Some(next.to_string())), "/posts", "{}", next);
+        }
+        assert_eq!(sanitize_next(None), "/posts");
+    }
+
+    #[test]
+    fn test_get_cookie() {
+        let mut hm = HeaderMap::new();
+        hm.insert("Cookie", HeaderValue::from_static("a=b; bloog_session=xyz.1.sig; c=d"));
+        assert_eq!(get_cookie(&hm, SESSION_COOKIE), Some("xyz.1.sig"));
+        assert_eq!(get_cookie(&hm, "missing"), None);
+    }
+}
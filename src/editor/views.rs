@@ -1,12 +1,14 @@
+use crate::editor::auth;
 use crate::htmx::HtmxContext;
-use crate::store::{Image, Post};
+use crate::store::{FsckProblem, Image, ImageInUseError, ImageMetadata, Post, Redirect, RevisionInfo};
 use crate::viewhelpers::COMMON_CSS;
 use anyhow::Error;
 use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, Uri};
 use axum::response::{IntoResponse, Response};
-use chrono::Local;
+use chrono::{Local, SecondsFormat, Utc};
 use maud::{html, Markup, PreEscaped, DOCTYPE};
 use object_store::ObjectMeta;
+use std::collections::BTreeMap;
 
 fn render_body_html(title: impl AsRef<str>, inner: Markup) -> Markup {
     html! {
@@ -26,25 +28,61 @@ fn render_body_html(title: impl AsRef<str>, inner: Markup) -> Markup {
                       background: white;
                       font-family: monospace;
                     }
+                    pre.diff { padding: 1rem; white-space: pre-wrap; }
+                    pre.diff span { display: block; }
+                    pre.diff .diff-insert { background: rgb(221, 244, 221); }
+                    pre.diff .diff-delete { background: rgb(250, 221, 221); }
+                    pre.diff .diff-hunk { color: grey; }
                     "##
                 }
                 script src="https://cdnjs.cloudflare.com/ajax/libs/htmx/2.0.4/htmx.min.js" integrity="sha512-2kIcAizYXhIn8TzUvqzEDZNuDZ+aW7yE/+f1HJHXFjQcGNfv1kqzJSTBRBSlOgp6B/KZsz1K0a3ZTqP9dnxioQ==" crossorigin="anonymous" referrerpolicy="no-referrer" {};
             }
-            body hx-boost="true" id="body" {
-                (inner)
+            @if let Some(token) = auth::current_csrf_token() {
+                body hx-boost="true" id="body" hx-headers=(format!(r#"{{"{}": "{}"}}"#, auth::CSRF_HEADER, token)) {
+                    (inner)
+                }
+            } @else {
+                body hx-boost="true" id="body" {
+                    (inner)
+                }
             }
         }
     }
 }
 
+/// The hidden form input carrying the csrf token of the current session. Every state-changing form must include this.
+fn csrf_input() -> Markup {
+    html! {
+        @if let Some(token) = auth::current_csrf_token() {
+            input type="hidden" name=(auth::CSRF_FIELD) value=(token);
+        }
+    }
+}
+
 pub(crate) fn render_body_semantics(header: &str, sections: Vec<Markup>) -> Markup {
+    render_main(header, true, sections)
+}
+
+/// Like [render_body_semantics] but without the nav, for the pages shown before logging in.
+fn render_body_semantics_without_nav(header: &str, sections: Vec<Markup>) -> Markup {
+    render_main(header, false, sections)
+}
+
+fn render_main(header: &str, with_nav: bool, sections: Vec<Markup>) -> Markup {
     html! {
         main class="container" {
             header {
-                nav.row {
-                    a.button.button-clear.column href="/posts" { "Posts" }
-                    a.button.button-clear.column href="/images" { "Images" }
-                    a.button.button-clear.column href="/debug" { "Debug" }
+                @if with_nav {
+                    nav.row {
+                        a.button.button-clear.column href="/posts" { "Posts" }
+                        a.button.button-clear.column href="/images" { "Images" }
+                        a.button.button-clear.column href="/redirects" { "Redirects" }
+                        a.button.button-clear.column href="/debug" { "Debug" }
+                        form.column action="/logout" method="post" {
+                            (csrf_input())
+                            button.button.button-clear type="submit" { "Logout" }
+                        }
+                    }
                 }
                 h1 { (header) }
             }
@@ -109,6 +147,22 @@ pub(crate) fn internal_error_page(err: anyhow::Error, htmx_context: Option<Box<H
     )
 }
 
+pub(crate) fn forbidden_page(reason: &str, htmx_context: Option<Box<HtmxContext>>) -> Response {
+    render_body_html_or_htmx(
+        StatusCode::FORBIDDEN,
+        "Forbidden",
+        render_body_semantics(
+            "Forbidden",
+            vec![html! {
+                p {
+                    (reason)
+                }
+            }],
+        ),
+        htmx_context,
+    )
+}
+
 pub(crate) fn not_found_page(method: Method, uri: Uri, htmx_context: Option<Box<HtmxContext>>) -> Response {
     render_body_html_or_htmx(
         StatusCode::NOT_FOUND,
@@ -128,7 +182,40 @@ pub(crate) fn not_found_page(method: Method, uri: Uri, htmx_context: Option<Box<
     )
 }
 
+pub(crate) fn login_page(next: String, error: Option<String>, htmx_context: Option<Box<HtmxContext>>) -> Response {
+    render_body_html_or_htmx(
+        if error.is_some() {
+            StatusCode::UNAUTHORIZED
+        } else {
+            StatusCode::OK
+        },
+        "Login",
+        render_body_semantics_without_nav(
+            "Login",
+            vec![html! {
+                @if let Some(e) = error {
+                    div {
+                        (e)
+                    }
+                }
+                form action="/login" method="post" hx-disabled-elt="find input, find button" {
+                    input type="hidden" name="next" value=(next);
+                    div.row {
+                        div.column {
+                            label for="password" { "Password" }
+                            input type="password" name="password" required="true" autofocus="true" autocomplete="current-password";
+                        }
+                    }
+                    button type="submit" { "Login" }
+                }
+            }],
+        ),
+        htmx_context,
+    )
+}
+
 pub(crate) fn list_posts_page(posts: Vec<Post>, htmx_context: Option<Box<HtmxContext>>) -> Response {
+    let now = Utc::now();
     render_body_html_or_htmx(
         StatusCode::OK,
         "Posts",
@@ -166,7 +253,13 @@ pub(crate) fn list_posts_page(posts: Vec<Post>, htmx_context: Option<Box<HtmxCon
                                     td { (post.slug) }
                                     td { (post.title) }
                                     td {
-                                        @if post.published { "Yes" } @else { strong { "No" } }
+                                        @if let Some(publish_at) = post.publish_at.filter(|_| post.is_scheduled(now)) {
+                                            em { "Scheduled" } " " small { (publish_at.to_rfc3339_opts(SecondsFormat::Secs, true)) }
+                                        } @else if post.published {
+                                            "Yes"
+                                        } @else {
+                                            strong { "No" }
+                                        }
                                     }
                                     td { (post.labels.join(", ")) }
                                 }
@@ -184,7 +277,7 @@ fn render_post_form(current: Option<(&Post, &str)>, is_new: bool) -> Markup {
     html! {
         div.row {
             div.column {
-                label for="slug" { "URL Slug (immutable)" }
+                label for="slug" { "URL Slug" }
                 @if is_new {
                     input type="text" name="slug" spellcheck="true" required="true" placeholder="the-url-slug-of-this-post" value=[current.as_ref().map(|x| &x.0.slug)];
                 } @else {
@@ -209,6 +302,30 @@ fn render_post_form(current: Option<(&Post, &str)>, is_new: bool) -> Markup {
                 label for="labels" { "Labels" }
                 input type="text" name="labels" placeholder="label,label,label" value=[current.as_ref().map(|x| x.0.labels.join(","))];
             }
+            div.column {
+                label for="publish_at" { "Publish At" }
+                input type="text" name="publish_at" placeholder="2025-01-31T09:00:00+01:00" title="Published posts are only shown once this time has passed" value=[current.as_ref().and_then(|x| x.0.publish_at).map(|t| t.to_rfc3339_opts(SecondsFormat::Secs, true))];
+            }
+        }
+        div.row {
+            div.column {
+                label for="summary" { "Summary" }
+                input type="text" name="summary" spellcheck="true" placeholder="A short description for the index and link previews" value=[current.as_ref().and_then(|x| x.0.summary.as_ref())];
+            }
+        }
+        div.row {
+            div.column {
+                label for="updated" { "Updated Date" }
+                input type="date" name="updated" value=[current.as_ref().and_then(|x| x.0.updated)];
+            }
+            div.column {
+                label for="author" { "Author" }
+                input type="text" name="author" placeholder="Ben Meier" value=[current.as_ref().and_then(|x| x.0.author.as_ref())];
+            }
+            div.column {
+                label for="cover_image" { "Cover Image" }
+                input type="text" name="cover_image" placeholder="/images/(slug).webp" value=[current.as_ref().and_then(|x| x.0.cover_image.as_ref())];
+            }
         }
         div.row {
             div.column {
@@ -223,6 +340,7 @@ fn render_post_form(current: Option<(&Post, &str)>, is_new: bool) -> Markup {
                 @if let Some((c, _)) = current.as_ref() {
                     @if !is_new {
                         form action={"/posts/" (c.slug)} hx-confirm="Are you sure you want to delete this post?" method="delete" style="display: inline" hx-disabled-elt="find input, find button, find textarea" {
+                            (csrf_input())
                             button.button-clear type="submit" { "Delete" }
                         }
                     }
@@ -260,6 +378,7 @@ pub(crate) fn new_posts_page(post: Option<(&Post, &str)>, error: Option<String>,
                     }
                 }
                 form action="/posts/new" method="post" {
+                    (csrf_input())
                     (render_post_form(post, true))
                 }
             }],
@@ -268,14 +387,32 @@ pub(crate) fn new_posts_page(post: Option<(&Post, &str)>, error: Option<String>,
     )
 }
 
+/// The state of the edit form around the post itself.
+#[derive(Default)]
+pub(crate) struct EditPostState {
+    /// The version the form was loaded from, which is submitted back to detect conflicting changes.
+    pub(crate) version: Option<String>,
+    /// The expiring viewer link for a post which is not live yet.
+    pub(crate) preview_link: Option<String>,
+    pub(crate) error: Option<String>,
+    /// The changes saved by someone else since the form was loaded.
+    pub(crate) conflict: Option<Markup>,
+}
+
 pub(crate) fn edit_posts_page(
     post: Post,
     content: String,
     html_content: Markup,
     toc_content: Markup,
-    error: Option<String>,
+    state: EditPostState,
     htmx_context: Option<Box<HtmxContext>>,
 ) -> Response {
+    let EditPostState {
+        version,
+        preview_link,
+        error,
+        conflict,
+    } = state;
     render_body_html_or_htmx(
         StatusCode::OK,
         "Edit post",
@@ -287,9 +424,37 @@ pub(crate) fn edit_posts_page(
                         (e)
                     }
                 }
+                @if let Some(c) = conflict {
+                    (c)
+                }
                 form action={ "/posts/" (post.slug) } method="post" {
+                    (csrf_input())
+                    @if let Some(v) = version {
+                        input type="hidden" name="version" value=(v);
+                    }
                     (render_post_form(Some((&post, content.as_ref())), false))
                 }
+                @if let Some(link) = preview_link {
+                    p {
+                        "This post is not live yet. Share this expiring preview link on the viewer: "
+                        code style="user-select: all" { (link) }
+                    }
+                }
+                p {
+                    a href={ "/posts/" (post.slug) "/revisions" } { "Revision history" }
+                }
+                details {
+                    summary { "Rename" }
+                    form action={ "/posts/" (post.slug) "/rename" } method="post" hx-confirm="Are you sure you want to rename this post? Unsaved changes will be lost." hx-disabled-elt="find input, find button" {
+                        (csrf_input())
+                        p {
+                            "Moves this post and its revisions to a new URL slug. The viewer redirects the old URL to the new one, "
+                            "and links to this post in other posts are rewritten."
+                        }
+                        input type="text" name="slug" required="true" placeholder="the-new-url-slug" value=(post.slug);
+                        button type="submit" { "Rename" }
+                    }
+                }
                 hr;
                 hr;
                 article hx-boost="false" {
@@ -303,13 +468,215 @@ pub(crate) fn edit_posts_page(
     )
 }
 
-pub(crate) fn debug_objects_page(objects: Vec<ObjectMeta>, htmx_context: Option<Box<HtmxContext>>) -> Response {
+/// Renders a line diff between the old and new text, showing only the changed lines along with some context.
+pub(crate) fn render_diff(old: &str, new: &str) -> Markup {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let groups = diff.grouped_ops(3);
+    html! {
+        @if groups.is_empty() {
+            p { "No differences." }
+        } @else {
+            pre.diff {
+                @for (i, group) in groups.iter().enumerate() {
+                    @if i > 0 {
+                        span.diff-hunk { "…" }
+                    }
+                    @for op in group {
+                        @for change in diff.iter_changes(op) {
+                            @match change.tag() {
+                                similar::ChangeTag::Insert => span.diff-insert { "+ " (change.value().trim_end_matches('\n')) },
+                                similar::ChangeTag::Delete => span.diff-delete { "- " (change.value().trim_end_matches('\n')) },
+                                similar::ChangeTag::Equal => span { "  " (change.value().trim_end_matches('\n')) },
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Shows the currently saved content of a post which was changed while it was being edited.
+pub(crate) fn conflict_section(current_content: String, diff: Markup) -> Markup {
+    html! {
+        h3 { "Changes from the saved version to yours" }
+        (diff)
+        details {
+            summary { "Saved Content" }
+            pre { (current_content) }
+        }
+        hr;
+    }
+}
+
+pub(crate) fn revision_label(id: Option<&str>, revisions: &[RevisionInfo]) -> String {
+    match id.and_then(|id| revisions.iter().find(|r| r.id == id)) {
+        Some(r) => r.saved.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => "current".to_string(),
+    }
+}
+
+pub(crate) fn list_revisions_page(post: Post, revisions: Vec<RevisionInfo>, htmx_context: Option<Box<HtmxContext>>) -> Response {
+    render_body_html_or_htmx(
+        StatusCode::OK,
+        "Revisions",
+        render_body_semantics(
+            format!("Revisions of {}", post.title).as_str(),
+            vec![html! {
+                p {
+                    a href={ "/posts/" (post.slug) } { "Back to the post" }
+                }
+                @if revisions.len() > 1 {
+                    form action={ "/posts/" (post.slug) "/diff" } method="get" {
+                        div.row {
+                            div.column {
+                                label for="from" { "From" }
+                                select name="from" {
+                                    @for (i, r) in revisions.iter().enumerate() {
+                                        option value=(r.id) selected[i == 1] { (revision_label(Some(r.id.as_str()), &revisions)) }
+                                    }
+                                }
+                            }
+                            div.column {
+                                label for="to" { "To" }
+                                select name="to" {
+                                    option value="" { "current" }
+                                    @for r in revisions.iter() {
+                                        option value=(r.id) { (revision_label(Some(r.id.as_str()), &revisions)) }
+                                    }
+                                }
+                            }
+                            div.column {
+                                button type="submit" { "Compare" }
+                            }
+                        }
+                    }
+                }
+                table {
+                    thead {
+                        tr {
+                            th { "Saved" }
+                            th { "Size" }
+                            th { "Actions" }
+                        }
+                    }
+                    tbody {
+                        @if revisions.is_empty() {
+                            tr {
+                                td colspan="3" { "No revisions have been saved for this post yet" }
+                            }
+                        } @else {
+                            @for (i, r) in revisions.iter().enumerate() {
+                                tr {
+                                    td {
+                                        a href={ "/posts/" (post.slug) "/revisions/" (r.id) } { (revision_label(Some(r.id.as_str()), &revisions)) }
+                                    }
+                                    td { (r.size) }
+                                    td {
+                                        @if let Some(previous) = revisions.get(i + 1) {
+                                            a href={ "/posts/" (post.slug) "/diff?from=" (previous.id) "&to=" (r.id) } { "Changes" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }],
+        ),
+        htmx_context,
+    )
+}
+
+pub(crate) fn revision_page(
+    post: Post,
+    revision: RevisionInfo,
+    content: String,
+    diff: Markup,
+    error: Option<String>,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> Response {
+    render_body_html_or_htmx(
+        StatusCode::OK,
+        "Revision",
+        render_body_semantics(
+            format!("Revision of {}", post.title).as_str(),
+            vec![html! {
+                @if let Some(e) = error {
+                    div {
+                        (e)
+                    }
+                }
+                p {
+                    a href={ "/posts/" (post.slug) "/revisions" } { "Back to the revisions" }
+                }
+                p {
+                    "Saved at " (revision.saved.format("%Y-%m-%d %H:%M:%S UTC").to_string()) "."
+                }
+                form action={ "/posts/" (post.slug) "/revisions/" (revision.id) "/restore" } method="post" hx-confirm="Are you sure you want to restore this revision?" hx-disabled-elt="find button" {
+                    (csrf_input())
+                    button type="submit" { "Restore this revision" }
+                }
+                h3 { "Changes from this revision to the current post" }
+                (diff)
+                details {
+                    summary { "Raw Content" }
+                    pre { (content) }
+                }
+            }],
+        ),
+        htmx_context,
+    )
+}
+
+pub(crate) fn diff_page(slug: &str, from: String, to: String, diff: Markup, htmx_context: Option<Box<HtmxContext>>) -> Response {
+    render_body_html_or_htmx(
+        StatusCode::OK,
+        "Changes",
+        render_body_semantics(
+            "Changes",
+            vec![html! {
+                p {
+                    a href={ "/posts/" (slug) "/revisions" } { "Back to the revisions" }
+                }
+                p { "Changes from " strong { (from) } " to " strong { (to) } "." }
+                (diff)
+            }],
+        ),
+        htmx_context,
+    )
+}
+
+pub(crate) fn debug_objects_page(objects: Vec<ObjectMeta>, message: Option<String>, htmx_context: Option<Box<HtmxContext>>) -> Response {
     render_body_html_or_htmx(
         StatusCode::OK,
         "Debug",
         render_body_semantics(
             "Debug",
             vec![html! {
+                form action="/debug/rebuild-index" method="post" hx-disabled-elt="find button" {
+                    (csrf_input())
+                    p {
+                        "The post index lists the metadata of every post so that the viewer does not need to list all objects, "
+                        "and the image index does the same for the metadata of every image. "
+                        "Rebuild them from the post and image objects if they have drifted."
+                    }
+                    button type="submit" { "Rebuild indexes" }
+                }
+                form action="/debug/repair" method="post" hx-disabled-elt="find button" {
+                    (csrf_input())
+                    p {
+                        "Posts are written in a transaction which is only visible once committed. "
+                        "Repair removes anything left behind by interrupted writes, and then rebuilds the post index."
+                    }
+                    button type="submit" { "Repair posts" }
+                }
+                p {
+                    a href="/debug/fsck" { "Check the store for consistency problems" }
+                }
+                @if let Some(m) = message {
+                    p { (m) }
+                }
                 table {
                     thead {
                         tr {
@@ -340,7 +707,70 @@ pub(crate) fn debug_objects_page(objects: Vec<ObjectMeta>, htmx_context: Option<
     )
 }
 
-pub(crate) fn list_images_page(images: Vec<Image>, error: Option<Error>, htmx_context: Option<Box<HtmxContext>>) -> Response {
+pub(crate) fn fsck_page(problems: Vec<FsckProblem>, htmx_context: Option<Box<HtmxContext>>) -> Response {
+    let repairable = problems.iter().filter(|p| !p.repaired && p.kind.is_repairable()).count();
+    render_body_html_or_htmx(
+        StatusCode::OK,
+        "Consistency Check",
+        render_body_semantics(
+            "Consistency Check",
+            vec![html! {
+                p {
+                    "Lists the objects in the store which readers ignore or misread. "
+                    "Repairing deletes ignored objects and commits legacy posts with broken props as unpublished drafts. "
+                    "Unknown paths and undecodable commits are left for you to inspect."
+                }
+                @if repairable > 0 {
+                    form action="/debug/fsck" method="post" hx-confirm="Are you sure you want to repair these problems?" hx-disabled-elt="find button" {
+                        (csrf_input())
+                        button type="submit" { "Repair " (repairable) " problems" }
+                    }
+                }
+                table {
+                    thead {
+                        tr {
+                            th { "Location" }
+                            th { "Problem" }
+                            th { "Status" }
+                        }
+                    }
+                    tbody {
+                        @if problems.is_empty() {
+                            tr {
+                                td colspan="3" { "No problems found" }
+                            }
+                        } @else {
+                            @for problem in problems {
+                                tr {
+                                    td { code { (problem.path) } }
+                                    td { (problem.kind) }
+                                    td {
+                                        @if problem.repaired {
+                                            "Repaired"
+                                        } @else if problem.kind.is_repairable() {
+                                            "Repairable"
+                                        } @else {
+                                            strong { "Needs attention" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }],
+        ),
+        htmx_context,
+    )
+}
+
+pub(crate) fn list_images_page(
+    images: Vec<Image>,
+    metadata: BTreeMap<Image, ImageMetadata>,
+    references: BTreeMap<Image, Vec<String>>,
+    error: Option<Error>,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> Response {
     render_body_html_or_htmx(
         StatusCode::OK,
         "Images",
@@ -355,9 +785,17 @@ pub(crate) fn list_images_page(images: Vec<Image>, error: Option<Error>, htmx_co
                                 br;
                             }
                         }
+                        @if let Some(in_use) = e.downcast_ref::<ImageInUseError>() {
+                            form action={"/images/" (in_use.image.to_path_part().as_ref()) } hx-confirm="Deleting this image will break the posts which use it. Are you sure?" method="delete" hx-disabled-elt="find button" {
+                                (csrf_input())
+                                input type="hidden" name="force" value="true";
+                                button.button.button-clear type="submit" { "Delete anyway" }
+                            }
+                        }
                     }
                 }
                 form action="/images" method="post" enctype="multipart/form-data" hx-disabled-elt="find input[type='text'], find button" {
+                    (csrf_input())
                     div.row {
                         div.column {
                             label for="slug" { "URL Slug" }
@@ -377,13 +815,15 @@ pub(crate) fn list_images_page(images: Vec<Image>, error: Option<Error>, htmx_co
                         tr {
                             th { "Image" }
                             th { "Link" }
+                            th { "Details" }
+                            th { "Used By" }
                             th { "Actions" }
                         }
                     }
                     tbody {
                         @if images.is_empty() {
                             tr {
-                                td colspan="3" { "No images" }
+                                td colspan="5" { "No images" }
                             }
                         } @else {
                             @for img in images {
@@ -394,12 +834,125 @@ pub(crate) fn list_images_page(images: Vec<Image>, error: Option<Error>, htmx_co
                                         }
                                     }
                                     td {
+                                        // The alt text is left out so that the image's metadata provides it when rendered.
                                         code style="user-select: all" {
-                                            "[![missing alt text](/images/" (img.to_medium().to_path_part().as_ref()) ")](/images/" (img.to_original().to_path_part().as_ref()) ")"
+                                            "[![](/images/" (img.to_medium().to_path_part().as_ref()) ")](/images/" (img.to_original().to_path_part().as_ref()) ")"
+                                        }
+                                    }
+                                    td {
+                                        @match metadata.get(&img.to_original()) {
+                                            Some(m) => {
+                                                @if m.width > 0 {
+                                                    small { (m.width) "×" (m.height) }
+                                                    br;
+                                                }
+                                                @if m.alt.is_empty() {
+                                                    em { "No alt text" }
+                                                } @else {
+                                                    (m.alt)
+                                                }
+                                            },
+                                            None => em { "No metadata" },
+                                        }
+                                    }
+                                    td {
+                                        @for (i, slug) in references.get(&img.to_original()).into_iter().flatten().enumerate() {
+                                            @if i > 0 { ", " }
+                                            a href={ "/posts/" (slug) } { (slug) }
                                         }
                                     }
                                     td {
                                         form action={"/images/" (img.to_original().to_path_part().as_ref()) } hx-confirm="Are you sure you want to delete this image?" method="delete" hx-disabled-elt="find input[type='text'], find button" {
+                                            (csrf_input())
+                                            button.button.button-clear type="submit" { "Delete" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }],
+        ),
+        htmx_context,
+    )
+}
+
+pub(crate) fn list_redirects_page(
+    redirects: Vec<Redirect>,
+    current: Option<Redirect>,
+    error: Option<String>,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> Response {
+    render_body_html_or_htmx(
+        StatusCode::OK,
+        "Redirects",
+        render_body_semantics(
+            "Redirects",
+            vec![html! {
+                p {
+                    "The viewer redirects requests for these paths when there is no post at the path. "
+                    "A prefix redirect matches any path starting with the source, and appends the rest of the path to the target. "
+                    "Submitting a redirect with an existing source replaces it."
+                }
+                @if let Some(e) = error {
+                    div {
+                        (e)
+                    }
+                }
+                form action="/redirects" method="post" hx-disabled-elt="find input, find button" {
+                    (csrf_input())
+                    div.row {
+                        div.column {
+                            label for="from" { "Source Path" }
+                            input type="text" name="from" required="true" placeholder="/old/path" value=[current.as_ref().map(|r| &r.from)];
+                        }
+                        div.column {
+                            label for="to" { "Target" }
+                            input type="text" name="to" required="true" placeholder="/posts/new-path or https://..." value=[current.as_ref().map(|r| &r.to)];
+                        }
+                    }
+                    div.row {
+                        div.column {
+                            label for="prefix" { "Prefix Match" }
+                            input type="checkbox" name="prefix" value="true" checked[current.as_ref().is_some_and(|r| r.prefix)];
+                        }
+                        div.column {
+                            label for="permanent" { "Permanent (301)" }
+                            input type="checkbox" name="permanent" value="true" checked[current.as_ref().is_none_or(|r| r.permanent)];
+                        }
+                        div.column {
+                            button type="submit" { "Submit" }
+                        }
+                    }
+                }
+                table {
+                    thead {
+                        tr {
+                            th { "Source" }
+                            th { "Target" }
+                            th { "Match" }
+                            th { "Status" }
+                            th { "Actions" }
+                        }
+                    }
+                    tbody {
+                        @if redirects.is_empty() {
+                            tr {
+                                td colspan="5" { "No redirects" }
+                            }
+                        } @else {
+                            @for r in redirects {
+                                tr {
+                                    td { code { (r.from) } }
+                                    td { code { (r.to) } }
+                                    td { @if r.prefix { "Prefix" } @else { "Exact" } }
+                                    td { @if r.permanent { "301" } @else { "302" } }
+                                    td {
+                                        a.button.button-clear href={ "/redirects?" (url::form_urlencoded::Serializer::new(String::new()).append_pair("from", r.from.as_str()).finish()) } { "Edit" }
+                                        form action="/redirects/delete" method="post" hx-confirm="Are you sure you want to delete this redirect?" style="display: inline" hx-disabled-elt="find button" {
+                                            (csrf_input())
+                                            input type="hidden" name="from" value=(r.from);
                                             button.button.button-clear type="submit" { "Delete" }
                                         }
                                     }
@@ -414,16 +967,49 @@ pub(crate) fn list_images_page(images: Vec<Image>, error: Option<Error>, htmx_co
     )
 }
 
-pub(crate) fn get_image_page(image: impl AsRef<Image>, htmx_context: Option<Box<HtmxContext>>) -> Response {
+pub(crate) fn get_image_page(
+    image: impl AsRef<Image>,
+    metadata: Option<ImageMetadata>,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> Response {
     let original_path = image.as_ref().to_path_part();
+    let metadata = metadata.unwrap_or_default();
+    let dimensions = metadata.variant_dimensions(image.as_ref());
     render_body_html_or_htmx(
         StatusCode::OK,
         "Image",
         render_body_semantics(
             "Image",
             vec![html! {
-                img src={ "/images/" (original_path.as_ref()) };
+                img src={ "/images/" (original_path.as_ref()) } alt=(metadata.alt) width=[dimensions.map(|(w, _)| w)] height=[dimensions.map(|(_, h)| h)];
+                table {
+                    tbody {
+                        tr {
+                            th { "Dimensions" }
+                            td {
+                                @match dimensions {
+                                    Some((w, h)) => { (w) "×" (h) }
+                                    None => { "Unknown" }
+                                }
+                            }
+                        }
+                        tr { th { "Size" } td { (metadata.size) " bytes" } }
+                        tr { th { "Uploaded" } td { (metadata.uploaded.format("%Y-%m-%d %H:%M:%S UTC").to_string()) } }
+                        @if let Some(captured) = metadata.captured {
+                            tr { th { "Captured" } td { (captured.format("%Y-%m-%d %H:%M:%S").to_string()) } }
+                        }
+                    }
+                }
+                form action={"/images/" (original_path.as_ref()) } method="post" hx-disabled-elt="find input[type='text'], find button" {
+                    (csrf_input())
+                    label for="alt" { "Alt Text" }
+                    input type="text" name="alt" spellcheck="true" placeholder="A description of the image for screen readers" value=(metadata.alt);
+                    label for="caption" { "Caption" }
+                    input type="text" name="caption" spellcheck="true" placeholder="An optional caption" value=(metadata.caption);
+                    button.button type="submit" { "Save" }
+                }
                 form action={"/images/" (original_path.as_ref()) } hx-confirm="Are you sure you want to delete this image?" method="delete" hx-disabled-elt="find input[type='text'], find button" {
+                    (csrf_input())
                     button.button type="submit" { "Delete" }
                 }
             }],
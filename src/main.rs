@@ -1,14 +1,15 @@
 // Apply the rule to the whole module.
 #![deny(clippy::unwrap_used, clippy::expect_used, clippy::panic)]
 
-use clap::{crate_name, crate_version, Parser, Subcommand};
-use log::info;
+use clap::{crate_name, crate_version, Parser, Subcommand, ValueEnum};
+use log::{info, warn};
 use opentelemetry::trace::TracerProvider;
 use opentelemetry::KeyValue;
 use opentelemetry_otlp::{WithExportConfig, WithHttpConfig};
 use opentelemetry_sdk::resource::{EnvResourceDetector, SdkProvidedResourceDetector, TelemetryResourceDetector};
 use opentelemetry_sdk::Resource;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use tokio::task::spawn_blocking;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
@@ -16,11 +17,13 @@ use tracing_subscriber::{fmt, registry, EnvFilter, Layer};
 use url::Url;
 
 // Define that crate htmx exists. The code can be found in the htmx file.
+mod backup;
 mod conversion;
 mod customhttptrace;
 pub(crate) mod editor;
 pub(crate) mod htmx;
 pub(crate) mod path_utils;
+pub(crate) mod signing;
 mod statics;
 pub(crate) mod store;
 mod viewer;
@@ -40,14 +43,27 @@ struct Args {
         short,
         long,
         env = "BLOOG_STORE_URL",
-        required = true,
-        help = "The arrow/object_store url schema with config options as query args."
+        help = "The arrow/object_store url schema with config options as query args. Required by every command except mirror."
     )]
-    store_url: Url,
+    store_url: Option<Url>,
 
     #[arg(short, long, env = "BLOOG_PORT", default_value = "8080", help = "The HTTP port to listen on.")]
     port: usize,
 
+    #[arg(
+        long,
+        env = "BLOOG_BASE_URL",
+        help = "The public absolute base url of the viewer, used for feeds and links shared outside the blog."
+    )]
+    base_url: Option<Url>,
+
+    #[arg(
+        long,
+        env = "BLOOG_PREVIEW_KEY",
+        help = "The secret used to sign draft preview links. Must be the same for the viewer and editor."
+    )]
+    preview_key: Option<Redacted>,
+
     #[arg(env = "BLOOG_HONEYCOMB_KEY")]
     honeycomb_key: Option<String>,
 
@@ -58,9 +74,266 @@ struct Args {
 #[derive(Subcommand, Debug, Clone)]
 enum Command {
     /// Launch the read-only viewer process.
-    Viewer,
+    Viewer {
+        #[arg(
+            long,
+            env = "BLOOG_VIEWER_REFRESH_INTERVAL_SECONDS",
+            default_value = "60",
+            help = "How often the viewer checks the store for changed posts."
+        )]
+        refresh_interval_seconds: u64,
+
+        #[arg(long, env = "BLOOG_TITLE", default_value = viewer::DEFAULT_TITLE, help = "The title of the blog and its feeds.")]
+        title: String,
+
+        #[arg(
+            long,
+            env = "BLOOG_AUTHOR",
+            default_value = viewer::DEFAULT_AUTHOR,
+            help = "The author of the blog and of any post which does not name its own."
+        )]
+        author: String,
+
+        #[arg(
+            long,
+            env = "BLOOG_DESCRIPTION",
+            default_value = viewer::DEFAULT_DESCRIPTION,
+            help = "The description of the blog in its feeds."
+        )]
+        description: String,
+    },
     /// Launch the read-write editor process.
-    Editor,
+    Editor {
+        #[arg(
+            long,
+            env = "BLOOG_EDITOR_PASSWORD_HASH",
+            help = "The argon2 PHC-format hash of the editor password. Generate with `echo -n <password> | argon2 <salt> -id -e`."
+        )]
+        password_hash: Redacted,
+
+        #[arg(
+            long,
+            env = "BLOOG_EDITOR_SESSION_KEY",
+            help = "The secret used to sign session cookies. If not set, a random key is used and sessions do not survive restarts."
+        )]
+        session_key: Option<Redacted>,
+
+        #[command(flatten)]
+        images: ImageArgs,
+    },
+    /// Rewrite the posts and post index in the store into the newest format.
+    Migrate {
+        #[arg(long, help = "Print the changes which would be made without writing anything.")]
+        dry_run: bool,
+
+        #[command(flatten)]
+        images: ImageArgs,
+    },
+    /// Write every post, image, and redirect in the store to a tar archive.
+    Export {
+        #[arg(help = "The path of the tar archive to write.")]
+        file: PathBuf,
+    },
+    /// Import the posts, images, and redirects from a tar archive written by export. Existing items are not overwritten.
+    Import {
+        #[arg(help = "The path of the tar archive to read.")]
+        file: PathBuf,
+
+        #[command(flatten)]
+        images: ImageArgs,
+    },
+    /// Check the store for objects which are ignored or misread, such as posts without props and orphan image variants.
+    Fsck {
+        #[arg(long, help = "Repair the problems which can be fixed safely, such as by deleting ignored objects.")]
+        repair: bool,
+    },
+    /// Copy every object from one store to another, skipping those which have not changed since the last mirror.
+    Mirror {
+        #[arg(long, help = "The store url to copy from. Defaults to --store-url.")]
+        from: Option<Url>,
+        #[arg(long, help = "The store url to copy to.")]
+        to: Url,
+        #[arg(long, help = "Delete objects from the target which are not in the source.")]
+        delete: bool,
+        #[arg(long, help = "Compare the checksums of the objects in both stores without copying anything.")]
+        verify: bool,
+        #[arg(long, default_value = "8", help = "How many objects to copy or compare at once.")]
+        concurrency: usize,
+    },
+}
+
+/// The options of the commands which upload or generate images, see [store::ImageConfig].
+#[derive(clap::Args, Debug, Clone)]
+struct ImageArgs {
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_MEDIUM_WIDTH",
+        default_value = "800",
+        help = "The maximum width of the medium variant."
+    )]
+    image_medium_width: u32,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_MEDIUM_HEIGHT",
+        default_value = "550",
+        help = "The maximum height of the medium variant."
+    )]
+    image_medium_height: u32,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_MEDIUM_QUALITY",
+        default_value = "90",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "The JPEG quality of the medium variant."
+    )]
+    image_medium_quality: u8,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_THUMBNAIL_WIDTH",
+        default_value = "200",
+        help = "The maximum width of the thumbnail."
+    )]
+    image_thumbnail_width: u32,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_THUMBNAIL_HEIGHT",
+        default_value = "200",
+        help = "The maximum height of the thumbnail."
+    )]
+    image_thumbnail_height: u32,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_THUMBNAIL_QUALITY",
+        default_value = "85",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "The JPEG quality of the thumbnail."
+    )]
+    image_thumbnail_quality: u8,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_LOSSY_ORIGINAL_QUALITY",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "Store originals as a JPEG of this quality rather than a lossless WebP. Images with transparency stay lossless."
+    )]
+    image_lossy_original_quality: Option<u8>,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_MAX_ORIGINAL_DIMENSION",
+        help = "Scale down originals which are wider or taller than this before storing them."
+    )]
+    image_max_original_dimension: Option<u32>,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_RESIZE_FILTER",
+        value_enum,
+        default_value = "triangle",
+        help = "The filter used to resize the original, the medium variant, and the responsive variants."
+    )]
+    image_resize_filter: ResizeFilter,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_WIDTHS",
+        value_delimiter = ',',
+        num_args = 1..,
+        default_value = "400,800,1200,1600",
+        help = "The comma separated widths of the responsive variants. Images are never scaled up past their own width."
+    )]
+    image_widths: Vec<u32>,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_WIDTH_QUALITY",
+        default_value = "60",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "The quality of the WebP responsive variants for browsers without AVIF support, where 100 is lossless."
+    )]
+    image_width_quality: u8,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_AVIF_SPEED",
+        default_value = "8",
+        value_parser = clap::value_parser!(u8).range(1..=10),
+        help = "The speed of the AVIF encoder, from 1 for the smallest files to 10 for the fastest encoding."
+    )]
+    image_avif_speed: u8,
+    #[arg(
+        long,
+        env = "BLOOG_IMAGE_AVIF_QUALITY",
+        default_value = "70",
+        value_parser = clap::value_parser!(u8).range(1..=100),
+        help = "The quality of the AVIF responsive variants."
+    )]
+    image_avif_quality: u8,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<ImageArgs> for store::ImageConfig {
+    fn from(args: ImageArgs) -> Self {
+        Self {
+            medium_bounds: (args.image_medium_width.max(1), args.image_medium_height.max(1)),
+            medium_quality: args.image_medium_quality,
+            thumbnail_bounds: (args.image_thumbnail_width.max(1), args.image_thumbnail_height.max(1)),
+            thumbnail_quality: args.image_thumbnail_quality,
+            lossy_original_quality: args.image_lossy_original_quality,
+            max_original_dimension: args.image_max_original_dimension.map(|d| d.max(1)),
+            filter: match args.image_resize_filter {
+                ResizeFilter::Nearest => image::imageops::FilterType::Nearest,
+                ResizeFilter::Triangle => image::imageops::FilterType::Triangle,
+                ResizeFilter::CatmullRom => image::imageops::FilterType::CatmullRom,
+                ResizeFilter::Gaussian => image::imageops::FilterType::Gaussian,
+                ResizeFilter::Lanczos3 => image::imageops::FilterType::Lanczos3,
+            },
+            width_ladder: args.image_widths.into_iter().map(|w| w.max(1)).collect(),
+            width_quality: args.image_width_quality,
+            avif_speed: args.image_avif_speed,
+            avif_quality: args.image_avif_quality,
+        }
+    }
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Viewer { .. } => "Viewer",
+            Command::Editor { .. } => "Editor",
+            Command::Migrate { .. } => "Migrate",
+            Command::Export { .. } => "Export",
+            Command::Import { .. } => "Import",
+            Command::Fsck { .. } => "Fsck",
+            Command::Mirror { .. } => "Mirror",
+        }
+    }
+}
+
+/// A string argument which must not be written to the logs.
+#[derive(Clone)]
+struct Redacted(String);
+
+impl std::fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "***")
+    }
+}
+
+/// Strip the credentials and config options from a store url so that it can be logged.
+fn anonymous_url(url: &Url) -> Url {
+    let mut anonymous_url = url.clone();
+    anonymous_url.set_query(None);
+    let _ = anonymous_url.set_password(None);
+    anonymous_url
+}
+
+impl From<String> for Redacted {
+    fn from(value: String) -> Self {
+        Redacted(value)
+    }
 }
 
 async fn main_err() -> Result<(), anyhow::Error> {
@@ -84,14 +357,14 @@ async fn main_err() -> Result<(), anyhow::Error> {
                         .with_detector(Box::new(TelemetryResourceDetector {}))
                         .with_detector(Box::new(SdkProvidedResourceDetector {}))
                         .with_detector(Box::new(EnvResourceDetector::new()))
-                        .with_service_name(format!("bloog-{:?}", &args.command))
+                        .with_service_name(format!("bloog-{}", args.command.name()))
                         .build(),
                 )
                 .build();
             registry()
                 .with(EnvFilter::from_default_env())
                 .with(fmt::Layer::default().with_filter(EnvFilter::from_default_env()))
-                .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(format!("bloog-{:?}", &args.command))))
+                .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(format!("bloog-{}", args.command.name()))))
                 .init();
 
             Some(tracer_provider)
@@ -102,22 +375,196 @@ async fn main_err() -> Result<(), anyhow::Error> {
         }
     };
 
-    let mut anonymous_url = args.store_url.clone();
-    anonymous_url.set_query(None);
-    let _ = anonymous_url.set_password(None);
+    let command = match args.command.clone() {
+        Command::Mirror {
+            from,
+            to,
+            delete,
+            verify,
+            concurrency,
+        } => Command::Mirror {
+            from: from.as_ref().map(anonymous_url),
+            to: anonymous_url(&to),
+            delete,
+            verify,
+            concurrency,
+        },
+        command => command,
+    };
     info!(
         "Parsed args {:?}, creating store..",
         Args {
-            store_url: anonymous_url,
+            store_url: args.store_url.as_ref().map(anonymous_url),
+            command,
             ..args.clone()
         }
     );
-    let store = store::Store::from_url(&args.store_url)?;
+    let store_url = args.store_url.as_ref();
+    let open_store = || match store_url {
+        Some(url) => store::Store::from_url(url),
+        None => Err(anyhow::anyhow!("the --store-url argument is required")),
+    };
 
-    info!("Starting {:?}..", args.command);
+    let preview_key = args
+        .preview_key
+        .as_ref()
+        .map(|k| signing::SigningKey::new(k.0.as_bytes()))
+        .transpose()?;
+    info!("Starting {}..", args.command.name());
     match args.command {
-        Command::Viewer => viewer::run(viewer::Config { port: args.port as u16 }, store).await?,
-        Command::Editor => editor::run(editor::Config { port: args.port as u16 }, store).await?,
+        Command::Viewer {
+            refresh_interval_seconds,
+            title,
+            author,
+            description,
+        } => {
+            let store = open_store()?;
+            viewer::run(
+                viewer::Config {
+                    port: args.port as u16,
+                    preview_key,
+                    base_url: args.base_url,
+                    refresh_interval: std::time::Duration::from_secs(refresh_interval_seconds.max(1)),
+                    title,
+                    author,
+                    description,
+                },
+                store,
+            )
+            .await?
+        }
+        Command::Editor {
+            password_hash,
+            session_key,
+            images,
+        } => {
+            let auth = editor::auth::AuthConfig::new(password_hash.0.as_str(), session_key.as_ref().map(|k| k.0.as_str()))?;
+            if session_key.is_none() {
+                warn!("No session key configured, sessions will not survive a restart");
+            }
+            let store = open_store()?.with_image_config(images.into());
+            editor::run(
+                editor::Config {
+                    port: args.port as u16,
+                    auth,
+                    preview_key,
+                    base_url: args.base_url,
+                },
+                store,
+            )
+            .await?
+        }
+        Command::Migrate { dry_run, images } => {
+            let store = open_store()?.with_image_config(images.into());
+            let changes = store.migrate(dry_run).await?;
+            if changes.is_empty() {
+                println!(
+                    "Nothing to migrate, the store is already at schema version {}.",
+                    store::Store::SCHEMA_VERSION
+                );
+            }
+            for change in changes {
+                match dry_run {
+                    true => println!("would {}", change),
+                    false => println!("did {}", change),
+                }
+            }
+        }
+        Command::Export { file } => {
+            let store = open_store()?;
+            let mut w = std::io::BufWriter::new(std::fs::File::create(&file)?);
+            let manifest = backup::export(&store, &mut w).await?;
+            println!(
+                "Exported {} posts, {} images, and {} redirects to {}.",
+                manifest.posts.len(),
+                manifest.images.len(),
+                manifest.redirects.len(),
+                file.display()
+            );
+        }
+        Command::Import { file, images } => {
+            let store = open_store()?.with_image_config(images.into());
+            let report = backup::import(&store, std::io::BufReader::new(std::fs::File::open(&file)?)).await?;
+            for item in report.imported.iter() {
+                println!("imported {}", item);
+            }
+            for item in report.conflicts.iter() {
+                println!("conflict: {}", item);
+            }
+            for item in report.failed.iter() {
+                println!("failed: {}", item);
+            }
+            if !report.conflicts.is_empty() || !report.failed.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} items were not imported",
+                    report.conflicts.len() + report.failed.len()
+                ));
+            }
+        }
+        Command::Fsck { repair } => {
+            let store = open_store()?;
+            let problems = store.fsck(repair).await?;
+            for problem in problems.iter() {
+                match problem.repaired {
+                    true => println!("repaired {}: {}", problem.kind, problem.path),
+                    false => println!("{}: {}", problem.kind, problem.path),
+                }
+            }
+            let remaining = problems.iter().filter(|p| !p.repaired).count();
+            println!("Found {} problems, {} remaining.", problems.len(), remaining);
+            if remaining > 0 {
+                return Err(anyhow::anyhow!("the store has {} unrepaired problems", remaining));
+            }
+        }
+        Command::Mirror {
+            from,
+            to,
+            delete,
+            verify,
+            concurrency,
+        } => {
+            let source = match from.as_ref() {
+                Some(url) => store::Store::from_url(url)?,
+                None => open_store()?,
+            };
+            let target = store::Store::from_url(&to)?;
+            let opts = store::MirrorOptions {
+                delete,
+                verify,
+                concurrency,
+            };
+            let summary = source.mirror_to(&target, &opts).await?;
+            for location in summary.missing.iter() {
+                println!("missing: {}", location);
+            }
+            for location in summary.mismatched.iter() {
+                println!("mismatched: {}", location);
+            }
+            for location in summary.extra.iter() {
+                println!("extra: {}", location);
+            }
+            if verify {
+                println!(
+                    "Verified {} objects: {} missing, {} mismatched, and {} extra.",
+                    summary.unchanged + summary.missing.len() + summary.mismatched.len(),
+                    summary.missing.len(),
+                    summary.mismatched.len(),
+                    summary.extra.len()
+                );
+                if !summary.missing.is_empty() || !summary.mismatched.is_empty() {
+                    return Err(anyhow::anyhow!("the target does not match the source"));
+                }
+            } else {
+                println!(
+                    "Copied {} objects ({} bytes), {} unchanged, {} deleted, and {} extra.",
+                    summary.copied,
+                    summary.copied_bytes,
+                    summary.unchanged,
+                    summary.deleted,
+                    summary.extra.len()
+                );
+            }
+        }
     }
 
     if let Some(tracer_provider) = optional_tracer_provider {
@@ -10,6 +10,13 @@ pub(crate) fn path_tail(source: &Path, prefix: &Path) -> Path {
     }
 }
 
+/// Strips the prefix off a URL path only where it ends on a segment boundary, so that `/blog` matches `/blog` and
+/// `/blog/x` but not `/blogroll`. Returns the rest of the path.
+pub(crate) fn strip_path_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.strip_prefix(prefix)
+        .filter(|rest| prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -26,4 +33,14 @@ mod tests {
         assert_eq!(path_tail(&Path::from("x/y/z"), &Path::from("x/y/z")).to_string(), "");
         assert_eq!(path_tail(&Path::from("x/y/z"), &Path::from("a/b/c")).to_string(), "x/y/z");
     }
+
+    #[test]
+    fn test_strip_path_prefix() {
+        assert_eq!(strip_path_prefix("/blog", "/blog"), Some(""));
+        assert_eq!(strip_path_prefix("/blog/x", "/blog"), Some("/x"));
+        assert_eq!(strip_path_prefix("/blog/x", "/blog/"), Some("x"));
+        assert_eq!(strip_path_prefix("/blogroll", "/blog"), None);
+        assert_eq!(strip_path_prefix("/blog-archive/x", "/blog"), None);
+        assert_eq!(strip_path_prefix("/blog", "/blog/"), None);
+    }
 }
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Error};
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::fmt::{Debug, Formatter};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A secret key used to produce and verify HMAC-SHA256 signatures over short string payloads. This is
+/// used for session cookies and other tokens that we hand out to browsers and expect back later.
+/// The MAC is keyed once on construction and cloned for each signature.
+#[derive(Clone)]
+pub(crate) struct SigningKey(HmacSha256);
+
+impl Debug for SigningKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SigningKey(***)")
+    }
+}
+
+impl SigningKey {
+    pub(crate) fn new(raw: &[u8]) -> Result<Self, Error> {
+        <HmacSha256 as KeyInit>::new_from_slice(raw)
+            .map(Self)
+            .map_err(|e| anyhow!("invalid signing key: {}", e))
+    }
+
+    /// Generate a new random key. Anything signed with this key will become invalid when the process restarts.
+    pub(crate) fn generate() -> Result<Self, Error> {
+        Self::new(&random_bytes(32))
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac = self.0.clone();
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    /// Returns the url-safe base64 encoded signature of the payload.
+    pub(crate) fn sign(&self, payload: &str) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(self.mac(payload).finalize().into_bytes())
+    }
+
+    /// Verifies the url-safe base64 encoded signature of the payload in constant time.
+    pub(crate) fn verify(&self, payload: &str, signature: &str) -> bool {
+        BASE64_URL_SAFE_NO_PAD
+            .decode(signature.as_bytes())
+            .is_ok_and(|raw| self.mac(payload).verify_slice(&raw).is_ok())
+    }
+
+    /// Produce a token which proves that we issued it for the given purpose and which is only valid until the expiry.
+    /// The token looks like `(expiry unix seconds).(signature)`.
+    pub(crate) fn sign_expiring(&self, purpose: &str, expires: DateTime<Utc>) -> String {
+        let ts = expires.timestamp();
+        format!("{}.{}", ts, self.sign(format!("{}:{}", purpose, ts).as_str()))
+    }
+
+    /// Verify a token produced by [SigningKey::sign_expiring] for the same purpose and check that it has not expired.
+    pub(crate) fn verify_expiring(&self, purpose: &str, token: &str) -> bool {
+        token.split_once('.').is_some_and(|(ts, signature)| {
+            ts.parse::<i64>().is_ok_and(|t| t > Utc::now().timestamp()) && self.verify(format!("{}:{}", purpose, ts).as_str(), signature)
+        })
+    }
+}
+
+/// The purpose string for draft preview tokens. These are minted by the editor and checked by the viewer, so both
+/// processes must be configured with the same key.
+pub(crate) fn preview_purpose(slug: &str) -> String {
+    format!("preview:{}", slug)
+}
+
+/// Returns a url-safe base64 encoded random token with the given number of bytes of entropy.
+pub(crate) fn random_token(len: usize) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(random_bytes(len))
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut raw = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut raw);
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_sign_verify() -> Result<(), Error> {
+        let key = SigningKey::new(b"secret")?;
+        let sig = key.sign("payload");
+        assert!(key.verify("payload", sig.as_str()));
+        assert!(!key.verify("other-payload", sig.as_str()));
+        assert!(!key.verify("payload", "not-a-signature"));
+        assert!(!SigningKey::new(b"other")?.verify("payload", sig.as_str()));
+        assert!(!SigningKey::generate()?.verify("payload", sig.as_str()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiring() -> Result<(), Error> {
+        let key = SigningKey::new(b"secret")?;
+        let token = key.sign_expiring("preview:a", Utc::now() + TimeDelta::minutes(1));
+        assert!(key.verify_expiring("preview:a", token.as_str()));
+        assert!(!key.verify_expiring("preview:b", token.as_str()));
+        assert!(!key.verify_expiring("preview:a", "123.abc"));
+        let expired = key.sign_expiring("preview:a", Utc::now() - TimeDelta::minutes(1));
+        assert!(!key.verify_expiring("preview:a", expired.as_str()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_token() {
+        assert_eq!(random_token(16).len(), 22);
+        assert_ne!(random_token(16), random_token(16));
+    }
+}
@@ -1,30 +1,41 @@
+mod exif;
+mod fsck;
+mod mirror;
+
 use crate::conversion;
-use crate::path_utils::path_tail;
+use crate::path_utils::{path_tail, strip_path_prefix};
 use anyhow::{anyhow, Context, Error};
 use axum::http::HeaderValue;
 use base64::prelude::BASE64_STANDARD_NO_PAD;
 use base64::Engine;
 use bytes::Bytes;
-use chrono::NaiveDate;
-use futures::future::ready;
-use futures::stream::FuturesUnordered;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeDelta, Utc};
 use futures::{StreamExt, TryFutureExt, TryStreamExt};
+use image::codecs::avif::AvifEncoder;
 use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
 use image::codecs::webp::WebPEncoder;
-use image::{DynamicImage, ImageReader};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader};
 use itertools::Itertools;
 use object_store::local::LocalFileSystem;
 use object_store::path::{Path, PathPart, DELIMITER};
-use object_store::{ObjectMeta, ObjectStore, PutOptions, PutPayload};
+use object_store::{ObjectMeta, ObjectStore, PutMode, PutOptions, PutPayload, PutResult, UpdateVersion};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Display;
 use std::io::Cursor;
 use std::slice::Iter;
 use std::str::from_utf8;
 use std::sync::Arc;
-use tracing::{info_span, instrument, Instrument};
+use tokio::task::spawn_blocking;
+use tracing::{info_span, instrument, warn, Instrument, Span};
 use url::Url;
-use xmlparser::Token;
+use xmlparser::{ElementEnd, Token};
+
+pub use fsck::FsckProblem;
+pub use mirror::MirrorOptions;
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct Post {
@@ -33,14 +44,222 @@ pub struct Post {
     pub title: String,
     pub published: bool,
     pub labels: Vec<String>,
+    /// A short description of the post, shown in the index and used for the meta tags.
+    pub summary: Option<String>,
+    /// The date of the last significant update to the post, if any.
+    pub updated: Option<NaiveDate>,
+    /// The author of the post, when it is not the owner of the blog.
+    pub author: Option<String>,
+    /// The link to an existing image shown at the top of the post, for example `/images/(slug).webp`.
+    pub cover_image: Option<String>,
+    /// When set, a published post is only shown by the viewer once this time has passed. The offset it was written with
+    /// is kept so that the editor can show it back in the same timezone.
+    pub publish_at: Option<DateTime<FixedOffset>>,
+}
+
+impl Post {
+    /// Whether the viewer should show the post at the given time.
+    pub fn is_live(&self, now: DateTime<Utc>) -> bool {
+        self.published && self.publish_at.is_none_or(|t| t <= now)
+    }
+
+    /// Whether the post is published but waiting for its publish-at time.
+    pub fn is_scheduled(&self, now: DateTime<Utc>) -> bool {
+        self.published && !self.is_live(now)
+    }
+}
+
+/// A post along with the last modified time of its content object.
+pub type PostWithLastModified = (Post, Option<DateTime<Utc>>);
+
+/// Identifies the version of a post, taken from its commit object or the content object of a legacy post, so that
+/// readers can tell when it has changed. Either field may be missing if the backend does not provide it or the post
+/// index predates it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ContentVersion {
+    pub last_modified: Option<DateTime<Utc>>,
+    pub e_tag: Option<String>,
+}
+
+/// A post along with the version of its content object.
+pub type PostWithVersion = (Post, ContentVersion);
+
+/// The version of the post content which a write expects to replace, checked with a conditional put.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpectedVersion {
+    /// Overwrite the post whatever its current version is.
+    Any,
+    /// The post must not exist yet.
+    Absent,
+    /// The content must still have this etag.
+    ETag(String),
+}
+
+/// Returned by [Store::upsert_post] when the post has been changed or created by someone else since it was read. The
+/// caller can find this with [Error::downcast_ref].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictError {
+    pub slug: String,
+}
+
+impl Display for ConflictError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "post '{}' has been modified since it was loaded", self.slug)
+    }
+}
+
+impl std::error::Error for ConflictError {}
+
+/// Returned by [Store::delete_image] when posts still reference the image, listing the slugs of those posts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageInUseError {
+    pub image: Image,
+    pub slugs: Vec<String>,
+}
+
+impl Display for ImageInUseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "image '{}' is still used by the posts {}",
+            self.image,
+            self.slugs.iter().map(|s| format!("'{}'", s)).join(", ")
+        )
+    }
+}
+
+impl std::error::Error for ImageInUseError {}
+
+/// The result of a successful [Store::upsert_post].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UpsertedPost {
+    pub html_content: String,
+    pub toc: String,
+    /// The etag of the new content, to be used as the expected version of the next write.
+    pub e_tag: Option<String>,
+}
+
+/// A redirect from a path which no longer exists, such as the old slug of a renamed post, to its new location.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Redirect {
+    pub from: String,
+    pub to: String,
+    /// Whether any path below `from` matches, rather than only `from` itself. The rest of the path is appended to `to`.
+    /// Only whole path segments match, so `/blog` does not catch `/blogroll`.
+    pub prefix: bool,
+    /// Whether the redirect is served as a 301 rather than a 302.
+    pub permanent: bool,
+}
+
+impl Redirect {
+    /// Returns the location to redirect the given path to, if it matches.
+    pub fn location(&self, path: &str) -> Option<String> {
+        match self.prefix {
+            true => strip_path_prefix(path, self.from.as_str()).map(|rest| format!("{}{}", self.to, rest)),
+            false => Some(self.to.clone()).filter(|_| self.from == path),
+        }
+    }
+}
+
+/// Find the redirect for a request path along with the location to redirect to. An exact match wins over a prefix
+/// match, and the longest prefix wins over shorter ones.
+pub fn find_redirect<'a>(redirects: &'a [Redirect], path: &str) -> Option<(&'a Redirect, String)> {
+    redirects
+        .iter()
+        .filter_map(|r| r.location(path).map(|location| (r, location)))
+        .max_by_key(|(r, _)| (!r.prefix, r.from.len()))
+}
+
+/// A saved version of a post. The id is the sortable timestamp at which it was saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RevisionInfo {
+    pub id: String,
+    pub saved: DateTime<Utc>,
+    pub size: u64,
+}
+
+/// The format of the original which a raster variant was generated from. It is part of the variant's name, so that the
+/// variant can be resolved to the directory of its original.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ImageFamily {
+    /// A lossless WebP original. Its variants use the names from before other originals were supported.
+    Webp,
+    /// A lossy JPEG original, see [ImageConfig::lossy_original_quality].
+    Jpg,
+    /// A lossless PNG original of an image with transparency, whose medium and thumbnail variants are PNGs too.
+    Png,
+}
+
+impl ImageFamily {
+    /// The marker added to the names of the variants, such as `photo.medium.jpg.jpg`.
+    fn marker(&self) -> Option<&'static str> {
+        match self {
+            ImageFamily::Webp => None,
+            ImageFamily::Jpg => Some("jpg"),
+            ImageFamily::Png => Some("png"),
+        }
+    }
+
+    fn from_marker(marker: &str) -> Option<Self> {
+        match marker {
+            "jpg" => Some(ImageFamily::Jpg),
+            "png" => Some(ImageFamily::Png),
+            _ => None,
+        }
+    }
+
+    fn to_original(self, slug: Arc<str>) -> Image {
+        match self {
+            ImageFamily::Webp => Image::Webp { slug },
+            ImageFamily::Jpg => Image::Jpg { slug },
+            ImageFamily::Png => Image::Png { slug },
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Image {
-    Svg { slug: Arc<str> },
-    Webp { slug: Arc<str> },
-    JpgMedium { slug: Arc<str> },
-    JpgThumbnail { slug: Arc<str> },
+    Svg {
+        slug: Arc<str>,
+    },
+    Webp {
+        slug: Arc<str>,
+    },
+    JpgMedium {
+        slug: Arc<str>,
+        family: ImageFamily,
+    },
+    JpgThumbnail {
+        slug: Arc<str>,
+        family: ImageFamily,
+    },
+    /// A WebP resized to the given width with its colours rounded as set by [ImageConfig::width_quality], as one of the
+    /// responsive variants.
+    WebpWidth {
+        slug: Arc<str>,
+        width: u32,
+        family: ImageFamily,
+    },
+    /// A lossy AVIF resized to the given width, as one of the responsive variants.
+    AvifWidth {
+        slug: Arc<str>,
+        width: u32,
+        family: ImageFamily,
+    },
+    /// A lossy JPEG original.
+    Jpg {
+        slug: Arc<str>,
+    },
+    /// A lossless PNG original of an image with transparency.
+    Png {
+        slug: Arc<str>,
+    },
+    PngMedium {
+        slug: Arc<str>,
+    },
+    PngThumbnail {
+        slug: Arc<str>,
+    },
 }
 
 impl AsRef<Image> for Image {
@@ -50,30 +269,85 @@ impl AsRef<Image> for Image {
 }
 
 impl Image {
-    pub fn to_original(&self) -> Image {
+    pub fn slug(&self) -> &Arc<str> {
         match self {
-            Image::Svg { slug } => Image::Svg { slug: slug.clone() },
-            Image::Webp { slug } => Image::Webp { slug: slug.clone() },
-            Image::JpgMedium { slug } => Image::Webp { slug: slug.clone() },
-            Image::JpgThumbnail { slug } => Image::Webp { slug: slug.clone() },
+            Image::Svg { slug }
+            | Image::Webp { slug }
+            | Image::Jpg { slug }
+            | Image::Png { slug }
+            | Image::PngMedium { slug }
+            | Image::PngThumbnail { slug }
+            | Image::JpgMedium { slug, .. }
+            | Image::JpgThumbnail { slug, .. }
+            | Image::WebpWidth { slug, .. }
+            | Image::AvifWidth { slug, .. } => slug,
         }
     }
 
-    pub fn to_medium(&self) -> Image {
+    /// Returns the family of a raster image, or None for an SVG.
+    pub fn family(&self) -> Option<ImageFamily> {
         match self {
-            Image::Svg { slug } => Image::Svg { slug: slug.clone() },
-            Image::Webp { slug } => Image::JpgMedium { slug: slug.clone() },
-            Image::JpgMedium { slug } => Image::JpgMedium { slug: slug.clone() },
-            Image::JpgThumbnail { slug } => Image::JpgMedium { slug: slug.clone() },
+            Image::Svg { .. } => None,
+            Image::Webp { .. } => Some(ImageFamily::Webp),
+            Image::Jpg { .. } => Some(ImageFamily::Jpg),
+            Image::Png { .. } | Image::PngMedium { .. } | Image::PngThumbnail { .. } => Some(ImageFamily::Png),
+            Image::JpgMedium { family, .. }
+            | Image::JpgThumbnail { family, .. }
+            | Image::WebpWidth { family, .. }
+            | Image::AvifWidth { family, .. } => Some(*family),
+        }
+    }
+
+    pub fn to_original(&self) -> Image {
+        match self.family() {
+            None => Image::Svg { slug: self.slug().clone() },
+            Some(family) => family.to_original(self.slug().clone()),
+        }
+    }
+
+    pub fn to_medium(&self) -> Image {
+        match self.family() {
+            None => Image::Svg { slug: self.slug().clone() },
+            Some(ImageFamily::Png) => Image::PngMedium { slug: self.slug().clone() },
+            Some(family) => Image::JpgMedium {
+                slug: self.slug().clone(),
+                family,
+            },
         }
     }
 
     pub fn to_thumbnail(&self) -> Image {
-        match self {
-            Image::Svg { slug } => Image::Svg { slug: slug.clone() },
-            Image::Webp { slug } => Image::JpgThumbnail { slug: slug.clone() },
-            Image::JpgMedium { slug } => Image::JpgThumbnail { slug: slug.clone() },
-            Image::JpgThumbnail { slug } => Image::JpgThumbnail { slug: slug.clone() },
+        match self.family() {
+            None => Image::Svg { slug: self.slug().clone() },
+            Some(ImageFamily::Png) => Image::PngThumbnail { slug: self.slug().clone() },
+            Some(family) => Image::JpgThumbnail {
+                slug: self.slug().clone(),
+                family,
+            },
+        }
+    }
+
+    /// Returns the WebP variant of the given width, or the SVG itself since it scales to any width.
+    pub fn to_webp_width(&self, width: u32) -> Image {
+        match self.family() {
+            None => Image::Svg { slug: self.slug().clone() },
+            Some(family) => Image::WebpWidth {
+                slug: self.slug().clone(),
+                width,
+                family,
+            },
+        }
+    }
+
+    /// Returns the AVIF variant of the given width, or the SVG itself since it scales to any width.
+    pub fn to_avif_width(&self, width: u32) -> Image {
+        match self.family() {
+            None => Image::Svg { slug: self.slug().clone() },
+            Some(family) => Image::AvifWidth {
+                slug: self.slug().clone(),
+                width,
+                family,
+            },
         }
     }
 
@@ -81,38 +355,53 @@ impl Image {
         match self {
             Image::Svg { .. } => HeaderValue::from_static("image/svg+xml"),
             Image::Webp { .. } => HeaderValue::from_static("image/webp"),
+            Image::Jpg { .. } => HeaderValue::from_static("image/jpeg"),
+            Image::Png { .. } | Image::PngMedium { .. } | Image::PngThumbnail { .. } => HeaderValue::from_static("image/png"),
             Image::JpgMedium { .. } => HeaderValue::from_static("image/jpg"),
             Image::JpgThumbnail { .. } => HeaderValue::from_static("image/jpg"),
+            Image::WebpWidth { .. } => HeaderValue::from_static("image/webp"),
+            Image::AvifWidth { .. } => HeaderValue::from_static("image/avif"),
         }
     }
 
     pub fn to_path_part(&self) -> PathPart<'_> {
-        match self {
-            Image::Svg { slug } => PathPart::from(format!("{}.svg", slug)),
-            Image::Webp { slug } => PathPart::from(format!("{}.webp", slug)),
-            Image::JpgMedium { slug } => PathPart::from(format!("{}.medium.jpg", slug)),
-            Image::JpgThumbnail { slug } => PathPart::from(format!("{}.thumb.jpg", slug)),
+        let (variant, ext) = match self {
+            Image::Svg { slug } => return PathPart::from(format!("{}.svg", slug)),
+            Image::Webp { slug } => return PathPart::from(format!("{}.webp", slug)),
+            Image::Jpg { slug } => return PathPart::from(format!("{}.jpg", slug)),
+            Image::Png { slug } => return PathPart::from(format!("{}.png", slug)),
+            Image::PngMedium { .. } => ("medium".to_string(), "png"),
+            Image::PngThumbnail { .. } => ("thumb".to_string(), "png"),
+            Image::JpgMedium { .. } => ("medium".to_string(), "jpg"),
+            Image::JpgThumbnail { .. } => ("thumb".to_string(), "jpg"),
+            Image::WebpWidth { width, .. } => (format!("w{}", width), "webp"),
+            Image::AvifWidth { width, .. } => (format!("w{}", width), "avif"),
+        };
+        match self.family().and_then(|f| f.marker()) {
+            Some(marker) => PathPart::from(format!("{}.{}.{}.{}", self.slug(), variant, marker, ext)),
+            None => PathPart::from(format!("{}.{}.{}", self.slug(), variant, ext)),
         }
     }
 
     pub fn try_from_path_part(p: PathPart) -> Result<Self, Error> {
-        let mut parts = p.as_ref().split('.').rev();
-        match parts.next() {
-            Some("svg") => Ok(Image::Svg {
-                slug: Arc::from(parts.rev().join(".")),
-            }),
-            Some("webp") => Ok(Image::Webp {
-                slug: Arc::from(parts.rev().join(".")),
-            }),
-            Some("jpg") => {
-                let variant = parts.next();
-                let rem = parts.rev().join(".");
-                match variant {
-                    Some("medium") => Ok(Image::JpgMedium { slug: Arc::from(rem) }),
-                    Some("thumb") => Ok(Image::JpgThumbnail { slug: Arc::from(rem) }),
-                    _ => Err(anyhow!("invalid image variant")),
-                }
+        let parts = p.as_ref().split('.').collect_vec();
+        let (ext, rest) = parts.split_last().ok_or_else(|| anyhow!("invalid image variant"))?;
+        // Variants of the other families carry a marker before the extension. A legacy slug may contain dots, so this
+        // falls back to reading the name without a marker when the rest is not a variant.
+        if let Some((marker, rest)) = rest.split_last() {
+            if let Some(image) = ImageFamily::from_marker(marker).and_then(|family| parse_variant(ext, rest, family)) {
+                return Ok(image);
             }
+        }
+        if let Some(image) = parse_variant(ext, rest, ImageFamily::Webp) {
+            return Ok(image);
+        }
+        let slug = Arc::from(rest.join("."));
+        match *ext {
+            "svg" => Ok(Image::Svg { slug }),
+            "webp" => Ok(Image::Webp { slug }),
+            "jpg" => Ok(Image::Jpg { slug }),
+            "png" => Ok(Image::Png { slug }),
             _ => Err(anyhow!("invalid image variant")),
         }
     }
@@ -123,6 +412,27 @@ impl Image {
     }
 }
 
+/// Read the variant of the given family from the extension and the other dot separated parts of its name, such as
+/// `"jpg"` and `["slug", "medium"]`.
+fn parse_variant(ext: &str, parts: &[&str], family: ImageFamily) -> Option<Image> {
+    let (variant, rest) = parts.split_last().filter(|(_, rest)| !rest.is_empty())?;
+    let slug = Arc::from(rest.join("."));
+    match (ext, *variant) {
+        ("jpg", "medium") => Some(Image::JpgMedium { slug, family }),
+        ("jpg", "thumb") => Some(Image::JpgThumbnail { slug, family }),
+        ("png", "medium") if family == ImageFamily::Png => Some(Image::PngMedium { slug }),
+        ("png", "thumb") if family == ImageFamily::Png => Some(Image::PngThumbnail { slug }),
+        ("webp" | "avif", _) => {
+            let width = variant.strip_prefix('w')?.parse::<u32>().ok()?;
+            match ext {
+                "webp" => Some(Image::WebpWidth { slug, width, family }),
+                _ => Some(Image::AvifWidth { slug, width, family }),
+            }
+        }
+        _ => None,
+    }
+}
+
 impl Display for Image {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.to_path_part().as_ref())
@@ -135,32 +445,463 @@ impl Default for Image {
     }
 }
 
+/// The name of the object holding the [ImageMetadata] of an image, next to its variants.
+const IMAGE_METADATA_NAME: &str = "meta";
+const IMAGE_INDEX_NAME: &str = "image-index";
+
+/// The bounds of the medium and thumbnail variants, and the widths of the responsive variants, of images uploaded
+/// before these were configurable.
+const DEFAULT_MEDIUM_BOUNDS: (u32, u32) = (800, 550);
+const DEFAULT_THUMBNAIL_BOUNDS: (u32, u32) = (200, 200);
+const DEFAULT_WIDTH_LADDER: [u32; 4] = [400, 800, 1200, 1600];
+
+/// How raster images are encoded and resized on upload. The defaults match the variants written before this was
+/// configurable. Changing it only affects images uploaded afterwards, since the bounds used are recorded in the
+/// [ImageMetadata] of each image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageConfig {
+    /// The bounding box of the medium variant, which images smaller than it are not resized to.
+    pub medium_bounds: (u32, u32),
+    /// The JPEG qualities of the medium and thumbnail variants. These do not apply to the PNG variants of images with
+    /// transparency.
+    pub medium_quality: u8,
+    /// The bounding box of the thumbnail variant.
+    pub thumbnail_bounds: (u32, u32),
+    pub thumbnail_quality: u8,
+    /// When set, originals are stored as a JPEG of this quality rather than a lossless WebP. Images with transparency
+    /// are always stored as a lossless PNG, see [ImageFamily::Png].
+    pub lossy_original_quality: Option<u8>,
+    /// When set, originals which are larger than this in either dimension are scaled down to fit before being stored.
+    pub max_original_dimension: Option<u32>,
+    /// The filter used when scaling down the original and when resizing the medium and responsive variants.
+    pub filter: FilterType,
+    /// The widths of the responsive variants. Images are never scaled up, so narrower images get a variant at their
+    /// own width instead of the wider ones.
+    pub width_ladder: Vec<u32>,
+    /// The quality of the WebP responsive variants offered to browsers without AVIF support, from 1 to 100. The
+    /// encoder is lossless, so lower qualities round the colours to fewer levels beforehand, see [reduce_levels], and
+    /// 100 keeps them exact.
+    pub width_quality: u8,
+    /// The speed of the AVIF encoder from 1 to 10, where slower speeds give smaller files, and its quality.
+    pub avif_speed: u8,
+    pub avif_quality: u8,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            medium_bounds: DEFAULT_MEDIUM_BOUNDS,
+            medium_quality: 90,
+            thumbnail_bounds: DEFAULT_THUMBNAIL_BOUNDS,
+            thumbnail_quality: 85,
+            lossy_original_quality: None,
+            max_original_dimension: None,
+            filter: FilterType::Triangle,
+            width_ladder: DEFAULT_WIDTH_LADDER.to_vec(),
+            width_quality: 60,
+            avif_speed: 8,
+            avif_quality: 70,
+        }
+    }
+}
+
+/// The metadata stored alongside the variants of an image.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ImageMetadata {
+    pub alt: String,
+    pub caption: String,
+    /// The dimensions of the original image in pixels, or of an SVG's root element. Zero when they are not known.
+    pub width: u32,
+    pub height: u32,
+    /// The size of the uploaded file in bytes.
+    pub size: u64,
+    pub uploaded: DateTime<Utc>,
+    /// The widths of the responsive variants in ascending order. This is empty for SVGs, and for images uploaded
+    /// before the variants were generated.
+    #[serde(default)]
+    pub widths: Vec<u32>,
+    /// The [ImageConfig::width_ladder] which the widths of the responsive variants were picked from.
+    #[serde(default = "default_width_ladder")]
+    pub width_ladder: Vec<u32>,
+    /// The [ImageConfig::medium_bounds] and [ImageConfig::thumbnail_bounds] which the variants were resized to.
+    #[serde(default = "default_medium_bounds")]
+    pub medium_bounds: (u32, u32),
+    #[serde(default = "default_thumbnail_bounds")]
+    pub thumbnail_bounds: (u32, u32),
+    /// The time the photo was taken from the EXIF metadata of the upload, in the local time of the camera. The rest of
+    /// the EXIF metadata is not kept.
+    #[serde(default)]
+    pub captured: Option<NaiveDateTime>,
+}
+
+fn default_medium_bounds() -> (u32, u32) {
+    DEFAULT_MEDIUM_BOUNDS
+}
+
+fn default_thumbnail_bounds() -> (u32, u32) {
+    DEFAULT_THUMBNAIL_BOUNDS
+}
+
+fn default_width_ladder() -> Vec<u32> {
+    DEFAULT_WIDTH_LADDER.to_vec()
+}
+
+impl Default for ImageMetadata {
+    fn default() -> Self {
+        Self {
+            alt: String::new(),
+            caption: String::new(),
+            width: 0,
+            height: 0,
+            size: 0,
+            uploaded: DateTime::default(),
+            widths: vec![],
+            width_ladder: DEFAULT_WIDTH_LADDER.to_vec(),
+            medium_bounds: DEFAULT_MEDIUM_BOUNDS,
+            thumbnail_bounds: DEFAULT_THUMBNAIL_BOUNDS,
+            captured: None,
+        }
+    }
+}
+
+impl ImageMetadata {
+    /// Returns the dimensions of the given variant of the image, which are derived from those of the original in the
+    /// same way as the variants are resized.
+    pub fn variant_dimensions(&self, img: &Image) -> Option<(u32, u32)> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+        let (medium_width, medium_height) = self.medium_bounds;
+        match img {
+            Image::Svg { .. } | Image::Webp { .. } | Image::Jpg { .. } | Image::Png { .. } => Some((self.width, self.height)),
+            Image::JpgMedium { .. } | Image::PngMedium { .. } if self.width <= medium_width && self.height <= medium_height => {
+                Some((self.width, self.height))
+            }
+            Image::JpgMedium { .. } | Image::PngMedium { .. } => Some(fit_within((self.width, self.height), self.medium_bounds)),
+            Image::JpgThumbnail { .. } | Image::PngThumbnail { .. } => Some(fit_within((self.width, self.height), self.thumbnail_bounds)),
+            Image::WebpWidth { width, .. } | Image::AvifWidth { width, .. } => {
+                Some(fit_within((self.width, self.height), (*width, u32::MAX)))
+            }
+        }
+    }
+}
+
+/// Decode an uploaded image, rotating and flipping it as set by its EXIF orientation. The raw EXIF chunk is returned
+/// separately, since none of the metadata of the upload is written to the stored variants.
+fn decode_oriented(raw: &[u8]) -> Result<(DynamicImage, Option<Vec<u8>>), Error> {
+    let mut decoder = ImageReader::new(Cursor::new(raw)).with_guessed_format()?.into_decoder()?;
+    // Broken metadata is ignored rather than failing the upload, since the pixels may still be fine.
+    let exif = decoder.exif_metadata().ok().flatten();
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok((image, exif))
+}
+
+/// Returns whether any pixel of the image is not fully opaque.
+fn has_transparency(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().any(|p| p[3] < u8::MAX)
+}
+
+/// Encode a medium or thumbnail variant, as a PNG for the [ImageFamily::Png] family so that the transparency is kept,
+/// and as a JPEG of the given quality otherwise.
+fn encode_variant(image: DynamicImage, family: ImageFamily, quality: u8) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    match family {
+        ImageFamily::Png => {
+            let _span = info_span!("encode", format = "png", width = image.width(), height = image.height());
+            image.into_rgba8().write_with_encoder(PngEncoder::new(&mut data))?
+        }
+        ImageFamily::Webp | ImageFamily::Jpg => {
+            let _span = info_span!("encode", format = "jpeg", width = image.width(), height = image.height());
+            image
+                .into_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut data, quality))?
+        }
+    }
+    Ok(data)
+}
+
+/// The encoded original and variants of a raster image, see [encode_raster_image].
+struct EncodedImage {
+    family: ImageFamily,
+    width: u32,
+    height: u32,
+    original: Vec<u8>,
+    medium: Vec<u8>,
+    thumbnail: Vec<u8>,
+    widths: Vec<EncodedWidth>,
+}
+
+/// The encoded responsive variants of an image at one width of the ladder.
+struct EncodedWidth {
+    width: u32,
+    webp: Vec<u8>,
+    avif: Vec<u8>,
+}
+
+/// Resize and encode the original and all the variants of a raster image as set by the [ImageConfig]. This is CPU
+/// bound, so it is run on a blocking thread by [Store::create_raster_image] which then writes the results.
+fn encode_raster_image(image: DynamicImage, config: &ImageConfig) -> Result<EncodedImage, Error> {
+    let image = match config.max_original_dimension {
+        Some(max) if image.width() > max || image.height() > max => {
+            let _span = info_span!("resize_original", width = image.width(), height = image.height());
+            image.resize(max, max, config.filter)
+        }
+        _ => image,
+    };
+    // An alpha channel which is fully opaque is dropped, so that such images are treated like any other.
+    let transparent = has_transparency(&image);
+    let image = match image.color().has_alpha() && !transparent {
+        true => DynamicImage::ImageRgb8(image.into_rgb8()),
+        false => image,
+    };
+    let (medium_width, medium_height) = config.medium_bounds;
+    let medium = if image.width() > medium_width || image.height() > medium_height {
+        let _span = info_span!("resize_medium", width = image.width(), height = image.height());
+        image.resize(medium_width, medium_height, config.filter)
+    } else {
+        image.clone()
+    };
+    let thumbnail = {
+        let _span = info_span!("resize_thumbnail", width = image.width(), height = image.height());
+        image.thumbnail(config.thumbnail_bounds.0, config.thumbnail_bounds.1)
+    };
+
+    let mut original = vec![];
+    let family = match (transparent, config.lossy_original_quality) {
+        (true, _) => {
+            let _span = info_span!("encode", format = "png", width = image.width(), height = image.height());
+            image.write_with_encoder(PngEncoder::new(&mut original))?;
+            ImageFamily::Png
+        }
+        (false, Some(quality)) => {
+            let _span = info_span!("encode", format = "jpeg", width = image.width(), height = image.height());
+            image
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut original, quality))?;
+            ImageFamily::Jpg
+        }
+        (false, None) => {
+            let _span = info_span!("encode", format = "webp", width = image.width(), height = image.height());
+            image.write_with_encoder(WebPEncoder::new_lossless(&mut original))?;
+            ImageFamily::Webp
+        }
+    };
+    Ok(EncodedImage {
+        family,
+        width: image.width(),
+        height: image.height(),
+        original,
+        medium: encode_variant(medium, family, config.medium_quality)?,
+        thumbnail: encode_variant(thumbnail, family, config.thumbnail_quality)?,
+        widths: encode_width_variants(&image, config)?,
+    })
+}
+
+/// Encode the WebP and AVIF responsive variants of the image at each width of the ladder.
+fn encode_width_variants(image: &DynamicImage, config: &ImageConfig) -> Result<Vec<EncodedWidth>, Error> {
+    let mut variants = vec![];
+    for width in Store::ladder_widths(&config.width_ladder, image.width()) {
+        let resized = match width == image.width() {
+            true => image.clone(),
+            false => {
+                let _span = info_span!("resize_width", width = width);
+                image.resize(width, u32::MAX, config.filter)
+            }
+        };
+        // Both encoders want 8 bit channels, and the alpha channel is only kept if there is one.
+        let resized = match resized.color().has_alpha() {
+            true => DynamicImage::ImageRgba8(resized.into_rgba8()),
+            false => DynamicImage::ImageRgb8(resized.into_rgb8()),
+        };
+        let mut webp = vec![];
+        {
+            let _span = info_span!("encode", format = "webp", width = resized.width(), height = resized.height());
+            reduce_levels(resized.clone(), config.width_quality).write_with_encoder(WebPEncoder::new_lossless(&mut webp))?;
+        }
+        let mut avif = vec![];
+        {
+            let _span = info_span!("encode", format = "avif", width = resized.width(), height = resized.height());
+            resized.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                &mut avif,
+                config.avif_speed,
+                config.avif_quality,
+            ))?;
+        }
+        variants.push(EncodedWidth { width, webp, avif });
+    }
+    Ok(variants)
+}
+
+/// Round the colour channels of an 8 bit image to fewer levels for the given quality, so that its lossless WebP
+/// encoding is smaller. Each 20 points below 100 drops one bit, and the alpha channel is kept exact.
+fn reduce_levels(image: DynamicImage, quality: u8) -> DynamicImage {
+    let bits = (100 - quality.min(100)) / 20;
+    if bits == 0 {
+        return image;
+    }
+    let step = 1u16 << bits;
+    let round = |v: &mut u8| *v = ((u16::from(*v) + step / 2) / step * step).min(u16::from(u8::MAX)) as u8;
+    match image {
+        DynamicImage::ImageRgba8(mut buffer) => {
+            buffer.pixels_mut().for_each(|p| p.0[..3].iter_mut().for_each(round));
+            DynamicImage::ImageRgba8(buffer)
+        }
+        image => {
+            let mut buffer = image.into_rgb8();
+            buffer.iter_mut().for_each(round);
+            DynamicImage::ImageRgb8(buffer)
+        }
+    }
+}
+
+/// Scale the dimensions to fit within the bounds while keeping the aspect ratio, as the image crate does on resize.
+fn fit_within((width, height): (u32, u32), (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    let ratio = f64::min(max_width as f64 / width as f64, max_height as f64 / height as f64);
+    (
+        ((width as f64 * ratio).round() as u32).max(1),
+        ((height as f64 * ratio).round() as u32).max(1),
+    )
+}
+
+/// Read the dimensions of an SVG from the width and height of its root element, falling back to its view box.
+fn svg_dimensions(raw: &str) -> Option<(u32, u32)> {
+    let (mut width, mut height, mut view_box) = (None, None, None);
+    for token in xmlparser::Tokenizer::from(raw) {
+        match token.ok()? {
+            Token::Attribute { local, value, .. } => match local.as_str() {
+                "width" => width = parse_svg_length(value.as_str()),
+                "height" => height = parse_svg_length(value.as_str()),
+                "viewBox" => {
+                    view_box = value
+                        .as_str()
+                        .split(|c: char| c.is_whitespace() || c == ',')
+                        .filter(|v| !v.is_empty())
+                        .skip(2)
+                        .filter_map(parse_svg_length)
+                        .next_tuple::<(u32, u32)>()
+                }
+                _ => {}
+            },
+            Token::ElementEnd { .. } => break,
+            _ => {}
+        }
+    }
+    width.zip(height).or(view_box).filter(|(w, h)| *w > 0 && *h > 0)
+}
+
+/// Remove the metadata elements and XMP packets of an SVG, which can hold details such as the author and location.
+fn strip_svg_metadata(raw: &str) -> Result<String, Error> {
+    let mut removed = vec![];
+    // The start of the metadata element being skipped, and how many elements deep within it the tokenizer is.
+    let mut skipping: Option<(usize, usize)> = None;
+    for token in xmlparser::Tokenizer::from(raw) {
+        match (token?, skipping) {
+            (Token::ElementStart { local, span, .. }, None) if local.as_str() == "metadata" => skipping = Some((span.start(), 1)),
+            (Token::ElementStart { .. }, Some((start, depth))) => skipping = Some((start, depth + 1)),
+            (Token::ElementEnd { end, span }, Some((start, depth))) if end != ElementEnd::Open => match depth {
+                1 => {
+                    removed.push(start..span.end());
+                    skipping = None;
+                }
+                _ => skipping = Some((start, depth - 1)),
+            },
+            (Token::ProcessingInstruction { target, span, .. }, None) if target.as_str() == "xpacket" => removed.push(span.range()),
+            _ => {}
+        }
+    }
+    let mut stripped = String::with_capacity(raw.len());
+    let mut last = 0;
+    for range in removed {
+        stripped.push_str(&raw[last..range.start]);
+        last = range.end;
+    }
+    stripped.push_str(&raw[last..]);
+    Ok(stripped)
+}
+
+fn parse_svg_length(value: &str) -> Option<u32> {
+    value
+        .trim()
+        .trim_end_matches("px")
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite() && *v > 0.0)
+        .map(|v| v.round() as u32)
+}
+
 /// The [Store] holds images and posts under a given sub path within a target object storage
 /// provider. The schema looks like:
 ///
 /// <pre>
-/// (sub_path)/images/(slug).(svg|webp)/(slug).(svg|webp)
-/// (sub_path)/images/(slug).(svg|webp)/(slug).(variant).(jpg)
+/// (sub_path)/images/(slug).(svg|webp|jpg|png)/(slug).(svg|webp|jpg|png)
+/// (sub_path)/images/(slug).webp/(slug).(variant).(jpg|webp|avif)
+/// (sub_path)/images/(slug).(jpg|png)/(slug).(variant).(jpg|png).(jpg|png|webp|avif)
+/// (sub_path)/images/(slug).(svg|webp|jpg|png)/meta
+/// (sub_path)/posts/(slug)/commit
+/// (sub_path)/posts/(slug)/tx/(txid)/content
+/// (sub_path)/posts/(slug)/revisions/(timestamp)
+/// (sub_path)/index
+/// (sub_path)/image-index
+/// (sub_path)/redirects
+/// (sub_path)/schema
+/// (sub_path)/mirror
+/// <pre>
+///
+/// Therefore, we use apis to list by delimiter and prefix where possible to reduce traversals. A post is written by
+/// staging its content under a new transaction id and then writing the commit object, a postcard encoded [Commit]
+/// holding the metadata, labels, and the transaction id of the content. The commit is the only object which makes a
+/// write visible, so an interrupted write leaves behind an uncommitted transaction which readers ignore and
+/// [Store::repair_posts] cleans up.
+///
+/// Older posts may still use the legacy layout below, which is read when there is no commit object and replaced by the
+/// next write to the post.
+///
+/// <pre>
 /// (sub_path)/posts/(slug)/props/(encoded props)
 /// (sub_path)/posts/(slug)/content
 /// (sub_path)/posts/(slug)/label/(key)
 /// <pre>
 ///
-/// Therefore, we use apis to list by delimiter and prefix where possible to reduce traversals.
+/// The index object is a postcard encoded [PostIndex] holding the metadata of every post and the images it references,
+/// so that listing posts is a single get rather than a list of every object under the posts prefix. It is maintained by
+/// [Store::upsert_post] and [Store::delete_post] and can be rebuilt from the per-post objects with
+/// [Store::rebuild_post_index]. The image index object is a postcard encoded [ImageIndex] holding the metadata of every
+/// image in the same way, so that rendering posts does not need a get per image. Each revision object is a postcard
+/// encoded [Revision] holding a full copy of the post as it was saved, so that older versions can be restored. The redirects object is a postcard encoded [RedirectMap] of the paths which the viewer redirects, such
+/// as the old slugs of renamed posts.
 #[derive(Debug)]
 pub struct Store {
     os: Box<dyn ObjectStore>,
     sub_path: Path,
+    image_config: ImageConfig,
+    /// How long the cleanup after a write leaves uncommitted and superseded transactions alone, which is
+    /// [Store::TX_GRACE_PERIOD] outside of tests.
+    tx_grace_period: TimeDelta,
 }
 
 impl Store {
-    const MEDIUM_VARIANT_WIDTH: u32 = 800;
-    const MEDIUM_VARIANT_HEIGHT: u32 = 550;
-    const THUMB_VARIANT_WIDTH: u32 = 200;
-    const THUMB_VARIANT_HEIGHT: u32 = 200;
+    const INDEX_UPDATE_ATTEMPTS: usize = 5;
+    const REVISION_ID_FORMAT: &'static str = "%Y%m%dT%H%M%S%.6fZ";
+    /// Uncommitted transactions younger than this may belong to a write which is still in progress, so they are left
+    /// alone by the cleanup after a write and by [Store::repair_posts].
+    pub const TX_GRACE_PERIOD: TimeDelta = TimeDelta::minutes(10);
+    /// The version of the layout written by this binary, recorded in the schema marker by [Store::migrate].
+    pub const SCHEMA_VERSION: u32 = 4;
 
     pub fn new(os: Box<dyn ObjectStore>, sub_path: Path) -> Self {
-        Self { os, sub_path }
+        Self {
+            os,
+            sub_path,
+            image_config: ImageConfig::default(),
+            tx_grace_period: Self::TX_GRACE_PERIOD,
+        }
+    }
+
+    /// Set how raster images are encoded and resized when they are uploaded or migrated.
+    pub fn with_image_config(self, image_config: ImageConfig) -> Self {
+        Self { image_config, ..self }
     }
 
     pub fn from_url(url: &Url) -> Result<Self, Error> {
@@ -190,163 +931,849 @@ impl Store {
 
     #[instrument(skip_all, err)]
     pub async fn convert_html_with_validation(&self, content: &str) -> Result<(String, String), Error> {
-        let valid_links = conversion::build_valid_links(&self.list_posts().await?, &self.list_images().await?);
-        conversion::convert(content, &valid_links)
+        let valid_links =
+            conversion::build_valid_links(&self.list_posts().await?, &self.list_images().await?, &self.list_redirects().await?);
+        conversion::convert(content, &valid_links, &self.list_image_metadata().await?)
     }
 
     #[instrument(skip_all, fields(slug = post.slug), err)]
-    pub async fn upsert_post(&self, post: &Post, content: &str) -> Result<(String, String), Error> {
-        PathPart::parse(post.slug.as_str())?;
-        if !(3..100).contains(&post.slug.len()) {
+    pub async fn upsert_post(&self, post: &Post, content: &str, expected: &ExpectedVersion) -> Result<UpsertedPost, Error> {
+        self.upsert_post_with_links(post, content, expected, &HashSet::new()).await
+    }
+
+    /// Like [Store::upsert_post], but links in `pending_links` are also treated as valid. This allows a batch of posts
+    /// which link to each other to be written one at a time, such as during an import.
+    pub async fn upsert_post_with_links(
+        &self,
+        post: &Post,
+        content: &str,
+        expected: &ExpectedVersion,
+        pending_links: &HashSet<String>,
+    ) -> Result<UpsertedPost, Error> {
+        Self::validate_post_slug(post.slug.as_str())?;
+
+        if post.updated.is_some_and(|updated| updated < post.date) {
+            return Err(anyhow!("invalid updated date - must not be before the post date"));
+        }
+
+        let mut valid_links =
+            conversion::build_valid_links(&self.list_posts().await?, &self.list_images().await?, &self.list_redirects().await?);
+        valid_links.extend(pending_links.iter().cloned());
+        if let Some(cover_image) = &post.cover_image {
+            if !cover_image.starts_with("/images/") || !valid_links.contains(cover_image) {
+                return Err(anyhow!("invalid cover image - '{}' is not an existing image", cover_image));
+            }
+        }
+        let (html_content, toc) = conversion::convert(content, &valid_links, &self.list_image_metadata().await?)?;
+        let e_tag = self.save_post(post, content, expected).await?;
+        Ok(UpsertedPost { html_content, toc, e_tag })
+    }
+
+    fn validate_post_slug(slug: &str) -> Result<(), Error> {
+        PathPart::parse(slug)?;
+        if !(3..100).contains(&slug.len()) {
             return Err(anyhow!("invalid post slug - too short"));
-        } else if post.slug.split_whitespace().count() != 1 {
+        } else if slug.split_whitespace().count() != 1 {
             return Err(anyhow!("invalid post slug - no spaces allowed"));
         }
+        Ok(())
+    }
 
-        let (html_content, toc) = self.convert_html_with_validation(content).await?;
-
+    /// Commit the post along with a revision, and update the post index, without validating the content. Returns the
+    /// etag of the commit.
+    async fn save_post(&self, post: &Post, content: &str, expected: &ExpectedVersion) -> Result<Option<String>, Error> {
         let post_path = self.sub_path.child("posts").child(post.slug.clone());
-        let post_meta = PostMetadata::V1((post.date, post.title.clone(), IsPublished(post.published)));
-        let post_meta_bytes = postcard::to_allocvec(&post_meta)?;
-        let post_meta_raw = BASE64_STANDARD_NO_PAD.encode(&post_meta_bytes);
+        let (txid, commit_put) = self.commit_post(&post_path, post, content, expected).await?;
 
+        // The revision is written as soon as the content has been committed, so that it can be restored later.
+        let labels = post.labels.iter().cloned().sorted().dedup().collect_vec();
+        let revision = Revision::V1((PostMetadata::from(post), labels, content.to_string()));
         self.os
             .put_opts(
-                &post_path.child("content"),
-                PutPayload::from(content.to_string()),
+                &post_path
+                    .child("revisions")
+                    .child(Utc::now().format(Self::REVISION_ID_FORMAT).to_string()),
+                PutPayload::from(postcard::to_allocvec(&revision)?),
                 PutOptions::default(),
             )
+            .instrument(info_span!("put"))
             .await?;
+
+        // And now clean up the legacy objects and any older transactions. This is best-effort since readers ignore them.
+        if let Err(e) = self
+            .cleanup_post(&post_path, Some(txid.as_str()), Utc::now() - self.tx_grace_period)
+            .await
+        {
+            warn!("failed to clean up post {}: {}", post.slug, e);
+        }
+
+        let e_tag = commit_put.e_tag;
+        let entry = PostIndexEntry::from_post(
+            post,
+            &ContentVersion {
+                last_modified: Some(Utc::now()),
+                e_tag: e_tag.clone(),
+            },
+            referenced_images(post, content),
+        );
+        self.update_post_index(|entries| {
+            entries.retain(|e| e.slug != post.slug);
+            entries.push(entry.clone());
+        })
+        .await?;
+        Ok(e_tag)
+    }
+
+    /// Stage the content under a new transaction, and then make it visible with the commit. Returns the transaction id
+    /// along with the result of the commit put.
+    async fn commit_post(
+        &self,
+        post_path: &Path,
+        post: &Post,
+        content: &str,
+        expected: &ExpectedVersion,
+    ) -> Result<(String, PutResult), Error> {
+        let txid = format!("{}-{:08x}", Utc::now().format(Self::REVISION_ID_FORMAT), rand::random::<u32>());
+        let tx_path = post_path.child("tx").child(txid.as_str());
         self.os
             .put_opts(
-                &post_path.child("props").child(post_meta_raw.clone()),
-                PutPayload::default(),
+                &tx_path.child("content"),
+                PutPayload::from(content.to_string()),
                 PutOptions::default(),
             )
+            .instrument(info_span!("put", bytes = content.len()))
             .await?;
-        // write the tags concurrently
-        FuturesUnordered::from_iter(post.labels.iter().map(|lbl| {
-            let label_path = post_path.child("labels").child(lbl.clone()).to_owned();
-            async move {
-                self.os
-                    .put_opts(&label_path, PutPayload::default(), PutOptions::default())
-                    .instrument(info_span!("put", bytes = 0))
-                    .await
-            }
-        }))
-        .boxed()
-        .try_collect::<Vec<_>>()
-        .await?;
-        // and now clean up anything extra that we don't need
-        let cleanup_paths = self
-            .os
-            .list(Some(&post_path))
-            .map_ok(|m| m.location)
-            .boxed()
-            .try_filter(|p| {
-                let tail = path_tail(p, &post_path);
-                if let Some((sec, k)) = tail.parts().next_tuple::<(PathPart, PathPart)>() {
-                    return ready(
-                        (sec.as_ref() == "props" && k.as_ref() != post_meta_raw.as_str())
-                            || (sec.as_ref() == "labels" && !post.labels.contains(&k.as_ref().to_string())),
-                    );
+        let labels = post.labels.iter().cloned().sorted().dedup().collect_vec();
+        let commit = Commit::V1((txid.clone(), PostMetadata::from(post), labels));
+        match self
+            .put_post_commit(&post.slug, post_path, postcard::to_allocvec(&commit)?, expected)
+            .await
+        {
+            Ok(r) => Ok((txid, r)),
+            Err(e) => {
+                if let Err(cleanup_err) = self.os.delete(&tx_path.child("content")).await {
+                    warn!("failed to clean up uncommitted transaction {}: {}", tx_path, cleanup_err);
                 }
-                ready(false)
-            })
-            .try_collect::<Vec<Path>>()
-            .await?;
-        for p in cleanup_paths {
-            self.os.delete(&p).instrument(info_span!("delete")).await?;
-        }
-        Ok((html_content, toc))
-    }
-
-    #[instrument(skip_all, fields(prefix = %prefix), err)]
-    async fn delete_paths_by_prefix(&self, prefix: &Path) -> Result<usize, Error> {
-        let paths = self
-            .os
-            .list(Some(prefix))
-            .map_ok(|m| m.location)
-            .try_collect::<Vec<Path>>()
-            .instrument(info_span!("list"))
-            .await?;
-        if paths.is_empty() {
-            Err(Error::msg("not found"))
-        } else {
-            for p in &paths {
-                self.os.delete(p).instrument(info_span!("delete")).await?;
+                Err(e)
             }
-            Ok(paths.len())
         }
     }
 
-    #[instrument(skip_all, fields(slug = slug), err)]
-    pub async fn delete_post(&self, slug: &str) -> Result<(), Error> {
-        self.delete_paths_by_prefix(&self.sub_path.child("posts").child(slug))
-            .await
-            .map(|_| ())
+    async fn head_object(&self, path: &Path) -> Result<Option<ObjectMeta>, Error> {
+        match self.os.head(path).instrument(info_span!("head")).await {
+            Ok(meta) => Ok(Some(meta)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
 
+    /// Write the commit of a post, failing with a [ConflictError] if the post does not match the expected version. If
+    /// the backend does not support conditional puts, we fall back to comparing the current etag before overwriting.
+    /// That still catches an edit from a stale form, though it can't catch two writes racing each other. A legacy
+    /// post has no commit yet, so its version is the etag of its content object.
     #[instrument(skip_all, fields(slug = slug), err)]
-    async fn create_webp_image(&self, slug: &str, image: DynamicImage) -> Result<Image, Error> {
-        let original_image = Image::Webp { slug: Arc::from(slug) };
-        if self.check_image_exists(&original_image).await? {
-            return Err(Error::msg("image slug already exists"));
-        }
-        let medium = if image.width() > Self::MEDIUM_VARIANT_WIDTH || image.height() > Self::MEDIUM_VARIANT_HEIGHT {
-            let _span = info_span!("resize_medium", width = image.width(), height = image.height());
-            image
-                .resize(
-                    Self::MEDIUM_VARIANT_WIDTH,
-                    Self::MEDIUM_VARIANT_HEIGHT,
-                    image::imageops::FilterType::Triangle,
-                )
-                .into_rgb8()
-        } else {
-            let _span = info_span!("clone_medium", width = image.width(), height = image.height());
-            image.clone().into_rgb8()
+    async fn put_post_commit(&self, slug: &str, post_path: &Path, commit: Vec<u8>, expected: &ExpectedVersion) -> Result<PutResult, Error> {
+        let commit_path = post_path.child("commit");
+        let conflict = || Error::new(ConflictError { slug: slug.to_string() });
+        let put = |mode: PutMode| {
+            self.os
+                .put_opts(&commit_path, PutPayload::from(commit.clone()), PutOptions::from(mode))
+                .instrument(info_span!("put"))
         };
-        let thumbnail = {
-            let _span = info_span!("resize_thumbnail", width = image.width(), height = image.height());
-            image.thumbnail(Self::THUMB_VARIANT_WIDTH, Self::THUMB_VARIANT_HEIGHT).into_rgb8()
+        let mode = match expected {
+            ExpectedVersion::Any => PutMode::Overwrite,
+            ExpectedVersion::Absent if self.head_object(&post_path.child("content")).await?.is_some() => return Err(conflict()),
+            ExpectedVersion::Absent => PutMode::Create,
+            ExpectedVersion::ETag(e_tag) => PutMode::Update(UpdateVersion {
+                e_tag: Some(e_tag.clone()),
+                version: None,
+            }),
         };
-
-        let mut original_data = vec![];
-        {
-            let _span = info_span!("encode", format = "webp", width = image.width(), height = image.height());
-            image.write_with_encoder(WebPEncoder::new_lossless(&mut original_data))?;
+        match put(mode).await {
+            Ok(r) => return Ok(r),
+            Err(object_store::Error::AlreadyExists { .. }) => return Err(conflict()),
+            // Either the versions did not match, the commit does not exist yet, or the backend can't tell us.
+            Err(object_store::Error::Precondition { .. })
+            | Err(object_store::Error::NotFound { .. })
+            | Err(object_store::Error::NotImplemented) => {}
+            Err(e) => return Err(e.into()),
         }
-        self.os
-            .put(&original_image.resolve_full_path(&self.sub_path), PutPayload::from(original_data))
-            .instrument(info_span!("put"))
+        let committed = self.head_object(&commit_path).await?;
+        let current = match committed.as_ref() {
+            Some(meta) => Some(meta.e_tag.clone()),
+            None => self.head_object(&post_path.child("content")).await?.map(|meta| meta.e_tag),
+        };
+        match (expected, current) {
+            (ExpectedVersion::Absent, Some(_)) => return Err(conflict()),
+            (ExpectedVersion::ETag(_), None) => return Err(conflict()),
+            (ExpectedVersion::ETag(e_tag), Some(current)) if current.as_ref() != Some(e_tag) => return Err(conflict()),
+            _ => {}
+        }
+        // If there is no commit yet, we still must not overwrite one which appeared in the meantime.
+        let mode = match committed {
+            Some(_) => PutMode::Overwrite,
+            None => PutMode::Create,
+        };
+        match put(mode).await {
+            Ok(r) => Ok(r),
+            Err(object_store::Error::AlreadyExists { .. }) => Err(conflict()),
+            Err(object_store::Error::NotImplemented) => Ok(put(PutMode::Overwrite).await?),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Read the commit object of a post along with its version, if it exists.
+    async fn get_post_commit(&self, post_path: &Path) -> Result<Option<(Commit, ContentVersion)>, Error> {
+        let gr = match self.os.get(&post_path.child("commit")).instrument(info_span!("get")).await {
+            Ok(gr) => gr,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let version = ContentVersion {
+            last_modified: Some(gr.meta.last_modified),
+            e_tag: gr.meta.e_tag.clone(),
+        };
+        let raw = gr.bytes().await?;
+        let commit = postcard::from_bytes::<Commit>(&raw).with_context(|| format!("failed to decode commit of {}", post_path))?;
+        Ok(Some((commit, version)))
+    }
+
+    /// Delete the objects of a post which are not visible to readers. When the post is committed, this is the legacy
+    /// objects and any transaction other than the committed one. Otherwise, it is any transaction, and all but the
+    /// newest props object of a legacy post. Transactions modified after the cutoff are kept since they may still be
+    /// about to be committed, or may have been committed until just now and still be read by someone who loaded that
+    /// commit. Returns the deleted paths.
+    async fn cleanup_post(&self, post_path: &Path, committed_txid: Option<&str>, cutoff: DateTime<Utc>) -> Result<Vec<Path>, Error> {
+        let objects = self
+            .os
+            .list(Some(post_path))
+            .try_collect::<Vec<ObjectMeta>>()
+            .instrument(info_span!("list"))
             .await?;
-        let mut medium_data = vec![];
+        let is_committed = committed_txid.is_some();
+        let newest_props = objects
+            .iter()
+            .filter(|m| {
+                path_tail(&m.location, post_path)
+                    .parts()
+                    .next()
+                    .is_some_and(|p| p.as_ref() == "props")
+            })
+            .max_by_key(|m| m.last_modified)
+            .map(|m| m.location.clone());
+        let mut deleted = vec![];
+        for meta in objects {
+            let tail = path_tail(&meta.location, post_path);
+            let mut parts = tail.parts();
+            let section = parts.next();
+            let key = parts.next();
+            let delete = match section.as_ref().map(|p| p.as_ref()) {
+                Some("content") | Some("labels") => is_committed,
+                Some("props") => is_committed || newest_props.as_ref() != Some(&meta.location),
+                Some("tx") => key.is_some_and(|k| Some(k.as_ref()) != committed_txid) && meta.last_modified < cutoff,
+                _ => false,
+            };
+            if delete {
+                self.os.delete(&meta.location).instrument(info_span!("delete")).await?;
+                deleted.push(meta.location);
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Clean up after interrupted writes to any post, by removing objects which are not visible to readers, and then
+    /// rebuild the post index. Transactions modified within the grace period are kept. Returns the deleted paths.
+    #[instrument(skip_all, err)]
+    pub async fn repair_posts(&self, grace_period: TimeDelta) -> Result<Vec<Path>, Error> {
+        let cutoff = Utc::now() - grace_period;
+        let slugs = self
+            .os
+            .list_with_delimiter(Some(&self.sub_path.child("posts")))
+            .instrument(info_span!("list"))
+            .await?
+            .common_prefixes;
+        let mut deleted = vec![];
+        for post_path in slugs {
+            let committed_txid = self.get_post_commit(&post_path).await?.map(|(Commit::V1((txid, _, _)), _)| txid);
+            deleted.extend(self.cleanup_post(&post_path, committed_txid.as_deref(), cutoff).await?);
+        }
+        self.rebuild_post_index().await?;
+        Ok(deleted)
+    }
+
+    fn schema_path(&self) -> Path {
+        self.sub_path.child("schema")
+    }
+
+    /// Read the schema version recorded by [Store::migrate], if the marker exists.
+    #[instrument(skip_all, err)]
+    pub async fn get_schema_version(&self) -> Result<Option<u32>, Error> {
+        match self
+            .os
+            .get(&self.schema_path())
+            .and_then(|gr| gr.bytes())
+            .instrument(info_span!("get"))
+            .await
         {
-            let _span = info_span!("encode", format = "jpeg", width = medium.width(), height = medium.height());
-            medium.write_with_encoder(JpegEncoder::new_with_quality(&mut medium_data, 90))?;
+            Ok(raw) => match postcard::from_bytes::<SchemaMarker>(&raw).context("failed to decode schema marker")? {
+                SchemaMarker::V1(version) => Ok(Some(version)),
+            },
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
         }
-        self.os
-            .put(
-                &original_image.to_medium().resolve_full_path(&self.sub_path),
-                PutPayload::from(medium_data),
-            )
-            .instrument(info_span!("put"))
+    }
+
+    /// Check that the store can be served by this binary. A store migrated by a newer binary may use a layout which we
+    /// can't read, so that is an error, while a store which has not been migrated yet is still readable.
+    #[instrument(skip_all, err)]
+    pub async fn check_schema_version(&self) -> Result<(), Error> {
+        match self.get_schema_version().await? {
+            Some(version) if version > Self::SCHEMA_VERSION => Err(anyhow!(
+                "the store is at schema version {} but this binary only supports up to {}, please upgrade",
+                version,
+                Self::SCHEMA_VERSION
+            )),
+            Some(version) if version == Self::SCHEMA_VERSION => Ok(()),
+            version => {
+                warn!(
+                    "the store is at schema version {} rather than {}, run the migrate command to upgrade it",
+                    version.unwrap_or_default(),
+                    Self::SCHEMA_VERSION
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Rewrite every post, the post index, and the schema marker into the newest format, and bring older images up to
+    /// date with their metadata, responsive variants, and the image index. Each change is described in
+    /// the returned list, and when `dry_run` is set nothing is written. Posts which are already in the newest format are
+    /// left alone, so running this again after it succeeds does nothing.
+    #[instrument(skip_all, fields(dry_run = dry_run), err)]
+    pub async fn migrate(&self, dry_run: bool) -> Result<Vec<String>, Error> {
+        let current = self.get_schema_version().await?;
+        if current.is_some_and(|v| v > Self::SCHEMA_VERSION) {
+            return Err(anyhow!(
+                "the store is at schema version {} which is newer than this binary",
+                current.unwrap_or_default()
+            ));
+        }
+
+        let mut changes = vec![];
+        for post in self.list_posts().await?.into_iter().sorted_by(|a, b| a.slug.cmp(&b.slug)) {
+            if let Some(change) = self.migrate_post(post.slug.as_str(), dry_run).await? {
+                changes.push(change);
+            }
+        }
+        let post_changes = changes.len();
+        // The index is built before the images are brought up to date, so that each of them only updates its entry.
+        if self.get_image_index().await?.is_none() {
+            changes.push("build the image index".to_string());
+            if !dry_run {
+                self.rebuild_image_index().await?;
+            }
+        }
+        for image in self.list_images().await?.into_iter().sorted() {
+            if let Some(change) = self.migrate_image(&image, dry_run).await? {
+                changes.push(change);
+            }
+        }
+        if post_changes > 0 || !self.is_post_index_current().await? {
+            changes.push("rebuild the post index in the newest format".to_string());
+            if !dry_run {
+                self.rebuild_post_index().await?;
+            }
+        }
+        if current != Some(Self::SCHEMA_VERSION) {
+            changes.push(format!(
+                "set the schema version from {} to {}",
+                current.unwrap_or_default(),
+                Self::SCHEMA_VERSION
+            ));
+            if !dry_run {
+                self.os
+                    .put(
+                        &self.schema_path(),
+                        PutPayload::from(postcard::to_allocvec(&SchemaMarker::V1(Self::SCHEMA_VERSION))?),
+                    )
+                    .instrument(info_span!("put"))
+                    .await?;
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Rewrite a single post into the newest layout if it needs it, returning a description of the change. The write is
+    /// conditional on the version which was read, so a post edited during the migration fails with a [ConflictError]
+    /// rather than losing the edit.
+    async fn migrate_post(&self, slug: &str, dry_run: bool) -> Result<Option<String>, Error> {
+        let post_path = self.sub_path.child("posts").child(slug);
+        let change = match self.get_post_commit(&post_path).await? {
+            None => format!("rewrite post '{}' from the legacy layout into a commit", slug),
+            Some((Commit::V1((_, PostMetadata::V1(_), _)), _)) => format!("rewrite the V1 metadata of post '{}'", slug),
+            Some((Commit::V1((_, PostMetadata::V2(_), _)), _)) => format!("rewrite the V2 metadata of post '{}'", slug),
+            Some(_) => return Ok(None),
+        };
+        if !dry_run {
+            let Some((post, content, e_tag)) = self.get_post_raw_with_e_tag(slug).await? else {
+                return Ok(None);
+            };
+            let expected = e_tag.map(ExpectedVersion::ETag).unwrap_or(ExpectedVersion::Any);
+            let (txid, _) = self.commit_post(&post_path, &post, content.as_str(), &expected).await?;
+            self.cleanup_post(&post_path, Some(txid.as_str()), Utc::now() - self.tx_grace_period)
+                .await?;
+        }
+        Ok(Some(change))
+    }
+
+    #[instrument(skip_all, fields(prefix = %prefix), err)]
+    async fn delete_paths_by_prefix(&self, prefix: &Path) -> Result<usize, Error> {
+        let paths = self
+            .os
+            .list(Some(prefix))
+            .map_ok(|m| m.location)
+            .try_collect::<Vec<Path>>()
+            .instrument(info_span!("list"))
+            .await?;
+        if paths.is_empty() {
+            Err(Error::msg("not found"))
+        } else {
+            for p in &paths {
+                self.os.delete(p).instrument(info_span!("delete")).await?;
+            }
+            Ok(paths.len())
+        }
+    }
+
+    #[instrument(skip_all, fields(slug = slug), err)]
+    pub async fn delete_post(&self, slug: &str) -> Result<(), Error> {
+        let post_path = self.sub_path.child("posts").child(slug);
+        // Removing the commit first hides the post from readers even if the rest of the delete is interrupted.
+        match self.os.delete(&post_path.child("commit")).instrument(info_span!("delete")).await {
+            Ok(_) | Err(object_store::Error::NotFound { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.delete_paths_by_prefix(&post_path).await?;
+        self.update_post_index(|entries| entries.retain(|e| e.slug != slug)).await
+    }
+
+    /// Move a post to a new slug along with its revisions. A redirect is recorded from the old path to the new one, and
+    /// the links to the old path in all posts are rewritten. Returns the slugs of the posts whose links were rewritten.
+    ///
+    /// The old post is only deleted once everything else has been done, so an interrupted rename leaves both posts in
+    /// place and is finished by renaming again. Each step can be repeated, and the new post is accepted as the result
+    /// of the earlier attempt when it holds the same post with its links rewritten.
+    #[instrument(skip_all, fields(from = from, to = to), err)]
+    pub async fn rename_post(&self, from: &str, to: &str) -> Result<Vec<String>, Error> {
+        Self::validate_post_slug(to)?;
+        if from == to {
+            return Err(anyhow!("the new slug is the same as the current one"));
+        }
+        let (post, content, _) = self
+            .get_post_raw_with_e_tag(from)
+            .await?
+            .ok_or_else(|| anyhow!("post '{}' does not exist", from))?;
+        let renamed = Post {
+            slug: to.to_string(),
+            ..post
+        };
+        // Creating the new post first means a rename onto an existing post fails before anything is changed.
+        if let Err(e) = self.save_post(&renamed, content.as_str(), &ExpectedVersion::Absent).await {
+            let rewritten_content = conversion::rewrite_post_links(content.as_str(), from, to).unwrap_or(content);
+            let resumed = e.is::<ConflictError>() && self.get_post_raw(to).await? == Some((renamed, rewritten_content));
+            if !resumed {
+                return Err(e);
+            }
+        }
+
+        let from_revisions = self.sub_path.child("posts").child(from).child("revisions");
+        let to_revisions = self.sub_path.child("posts").child(to).child("revisions");
+        let revisions = self
+            .os
+            .list(Some(&from_revisions))
+            .try_collect::<Vec<ObjectMeta>>()
+            .instrument(info_span!("list"))
             .await?;
+        for meta in revisions {
+            if let Some(id) = meta.location.filename() {
+                self.os
+                    .copy(&meta.location, &to_revisions.child(id))
+                    .instrument(info_span!("copy"))
+                    .await?;
+            }
+        }
+
+        let mut rewritten = vec![];
+        for listed in self.list_posts().await? {
+            if listed.slug == from {
+                continue;
+            }
+            let Some((post, content, e_tag)) = self.get_post_raw_with_e_tag(listed.slug.as_str()).await? else {
+                continue;
+            };
+            if let Some(content) = conversion::rewrite_post_links(content.as_str(), from, to) {
+                let expected = e_tag.map(ExpectedVersion::ETag).unwrap_or(ExpectedVersion::Any);
+                self.save_post(&post, content.as_str(), &expected).await?;
+                rewritten.push(post.slug);
+            }
+        }
+
+        let (from_path, to_path) = (format!("/posts/{}", from), format!("/posts/{}", to));
+        self.update_redirects(|redirects| {
+            // The new path is a post again, and older redirects to the old path now go straight to the new one.
+            redirects.retain(|r| r.prefix || (r.from != to_path && r.from != from_path));
+            redirects
+                .iter_mut()
+                .filter(|r| r.to == from_path)
+                .for_each(|r| r.to = to_path.clone());
+            redirects.push(Redirect {
+                from: from_path.clone(),
+                to: to_path.clone(),
+                prefix: false,
+                permanent: true,
+            });
+        })
+        .await?;
+        self.delete_post(from).await?;
+        Ok(rewritten)
+    }
+
+    fn redirects_path(&self) -> Path {
+        self.sub_path.child("redirects")
+    }
+
+    async fn get_redirects_with_version(&self) -> Result<(Vec<Redirect>, Option<UpdateVersion>), Error> {
+        let gr = match self.os.get(&self.redirects_path()).instrument(info_span!("get")).await {
+            Ok(gr) => gr,
+            Err(object_store::Error::NotFound { .. }) => return Ok((vec![], None)),
+            Err(e) => return Err(e.into()),
+        };
+        let version = UpdateVersion {
+            e_tag: gr.meta.e_tag.clone(),
+            version: gr.meta.version.clone(),
+        };
+        let raw = gr.bytes().await?;
+        let redirects = match postcard::from_bytes::<RedirectMap>(&raw).context("failed to decode redirects")? {
+            RedirectMap::V1(entries) => entries
+                .into_iter()
+                .map(|(from, to)| Redirect {
+                    from,
+                    to,
+                    prefix: false,
+                    permanent: true,
+                })
+                .collect(),
+            RedirectMap::V2(entries) => entries.into_iter().map(Redirect::from).collect(),
+        };
+        Ok((redirects, Some(version)))
+    }
+
+    /// Lists the redirects served by the viewer for paths which no longer exist.
+    #[instrument(skip_all, err)]
+    pub async fn list_redirects(&self) -> Result<Vec<Redirect>, Error> {
+        Ok(self.get_redirects_with_version().await?.0)
+    }
+
+    /// Add a redirect, replacing any existing redirect from the same path.
+    #[instrument(skip_all, fields(from = redirect.from), err)]
+    pub async fn upsert_redirect(&self, redirect: &Redirect) -> Result<(), Error> {
+        if !redirect.from.starts_with('/') || redirect.from.contains(char::is_whitespace) {
+            return Err(anyhow!("invalid redirect source - must be a path starting with /"));
+        } else if !["/", "http://", "https://"].iter().any(|p| redirect.to.starts_with(p)) || redirect.to.contains(char::is_whitespace) {
+            return Err(anyhow!(
+                "invalid redirect target - must be a path starting with / or an http(s) url"
+            ));
+        } else if redirect.location(redirect.to.as_str()).is_some() {
+            return Err(anyhow!("invalid redirect - the target would be redirected again"));
+        }
+        // The viewer follows a single redirect, so the new one must neither lead into another nor be led into by one.
+        let mut others = self.list_redirects().await?;
+        others.retain(|r| r.from != redirect.from);
+        if let Some((existing, _)) = find_redirect(&others, redirect.to.as_str()) {
+            return Err(anyhow!(
+                "invalid redirect - the target would be redirected again by the redirect from '{}'",
+                existing.from
+            ));
+        } else if let Some(existing) = others.iter().find(|r| redirect.location(r.to.as_str()).is_some()) {
+            return Err(anyhow!(
+                "invalid redirect - the redirect from '{}' leads to the source and would be redirected again",
+                existing.from
+            ));
+        }
+        self.update_redirects(|redirects| {
+            redirects.retain(|r| r.from != redirect.from);
+            redirects.push(redirect.clone());
+        })
+        .await
+    }
+
+    /// Remove the redirect from the given path, returning whether it existed.
+    #[instrument(skip_all, fields(from = from), err)]
+    pub async fn delete_redirect(&self, from: &str) -> Result<bool, Error> {
+        if !self.list_redirects().await?.iter().any(|r| r.from == from) {
+            return Ok(false);
+        }
+        self.update_redirects(|redirects| redirects.retain(|r| r.from != from)).await?;
+        Ok(true)
+    }
+
+    /// Apply a modification to the redirects, conditional on them not having changed since we read them where the
+    /// backend supports it, in the same way as [Store::update_post_index].
+    async fn update_redirects(&self, modify: impl Fn(&mut Vec<Redirect>)) -> Result<(), Error> {
+        let redirects_path = self.redirects_path();
+        for _ in 0..Self::INDEX_UPDATE_ATTEMPTS {
+            let (mut redirects, version) = self.get_redirects_with_version().await?;
+            modify(&mut redirects);
+            redirects.sort_by(|a, b| a.from.cmp(&b.from));
+            let raw = postcard::to_allocvec(&RedirectMap::V2(redirects.into_iter().map(RedirectEntry::from).collect()))?;
+            let mode = match version {
+                Some(version) => PutMode::Update(version),
+                None => PutMode::Create,
+            };
+            let put = |mode: PutMode| {
+                self.os
+                    .put_opts(&redirects_path, PutPayload::from(raw.clone()), PutOptions::from(mode))
+                    .instrument(info_span!("put"))
+            };
+            match put(mode).await {
+                Ok(_) => return Ok(()),
+                Err(object_store::Error::Precondition { .. }) | Err(object_store::Error::AlreadyExists { .. }) => continue,
+                Err(object_store::Error::NotImplemented) => return put(PutMode::Overwrite).await.map(|_| ()).map_err(Error::from),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(anyhow!("the redirects were modified concurrently, please try again"))
+    }
+
+    /// Lists the saved revisions of a post, newest first.
+    #[instrument(skip_all, fields(slug = slug), err)]
+    pub async fn list_revisions(&self, slug: &str) -> Result<Vec<RevisionInfo>, Error> {
+        let prefix = self.sub_path.child("posts").child(slug).child("revisions");
+        Ok(self
+            .os
+            .list(Some(&prefix))
+            .try_collect::<Vec<ObjectMeta>>()
+            .instrument(info_span!("list"))
+            .await?
+            .into_iter()
+            .filter_map(|meta| {
+                let id = meta.location.filename()?.to_string();
+                let saved = NaiveDateTime::parse_from_str(id.as_str(), Self::REVISION_ID_FORMAT).ok()?.and_utc();
+                Some(RevisionInfo {
+                    id,
+                    saved,
+                    size: meta.size as u64,
+                })
+            })
+            .sorted_by(|a, b| b.id.cmp(&a.id))
+            .collect())
+    }
+
+    /// Returns the post and its content as they were saved in the given revision.
+    #[instrument(skip_all, fields(slug = slug, revision = id), err)]
+    pub async fn get_revision(&self, slug: &str, id: &str) -> Result<Option<(Post, String)>, Error> {
+        let path = self
+            .sub_path
+            .child("posts")
+            .child(slug)
+            .child("revisions")
+            .child(PathPart::parse(id)?);
+        let raw = match self.os.get(&path).and_then(|gr| gr.bytes()).instrument(info_span!("get")).await {
+            Ok(b) => b,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match postcard::from_bytes::<Revision>(&raw).context("failed to decode revision")? {
+            Revision::V1((meta, labels, content)) => Ok(Some((meta.into_post(slug.to_string(), labels), content))),
+        }
+    }
+
+    fn post_index_path(&self) -> Path {
+        self.sub_path.child("index")
+    }
 
-        let mut thumbnail_data = vec![];
+    /// Returns whether the post index exists and only holds metadata in the newest format.
+    async fn is_post_index_current(&self) -> Result<bool, Error> {
+        let raw = match self
+            .os
+            .get(&self.post_index_path())
+            .and_then(|gr| gr.bytes())
+            .instrument(info_span!("get"))
+            .await
         {
-            let _span = info_span!("encode", format = "jpeg", width = thumbnail.width(), height = thumbnail.height());
-            thumbnail.write_with_encoder(JpegEncoder::new_with_quality(&mut thumbnail_data, 85))?;
+            Ok(raw) => raw,
+            Err(object_store::Error::NotFound { .. }) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(match postcard::from_bytes::<PostIndex>(&raw) {
+            Ok(PostIndex::V2(entries)) => entries.iter().all(|e| matches!(e.meta, PostMetadata::V3(_))),
+            _ => false,
+        })
+    }
+
+    /// Read the post index along with its version. Returns None if the index does not exist or cannot be decoded.
+    #[instrument(skip_all, err)]
+    async fn get_post_index(&self) -> Result<Option<(Vec<PostIndexEntry>, UpdateVersion)>, Error> {
+        let gr = match self.os.get(&self.post_index_path()).instrument(info_span!("get")).await {
+            Ok(gr) => gr,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let version = UpdateVersion {
+            e_tag: gr.meta.e_tag.clone(),
+            version: gr.meta.version.clone(),
+        };
+        let raw = gr.bytes().await?;
+        match postcard::from_bytes::<PostIndex>(&raw) {
+            Ok(PostIndex::V1(entries)) => Ok(Some((entries.into_iter().map(PostIndexEntry::from).collect(), version))),
+            Ok(PostIndex::V2(entries)) => Ok(Some((entries, version))),
+            Err(e) => {
+                warn!("failed to decode post index, ignoring it: {}", e);
+                Ok(None)
+            }
         }
+    }
+
+    // Errors are not recorded here since failed preconditions and unsupported put modes are handled by the caller.
+    #[instrument(skip_all, fields(entries = entries.len()))]
+    async fn put_post_index(&self, entries: Vec<PostIndexEntry>, mode: PutMode) -> Result<(), object_store::Error> {
+        let mut entries = entries;
+        entries.sort_by(|a, b| a.slug.cmp(&b.slug));
+        let raw = postcard::to_allocvec(&PostIndex::V2(entries)).map_err(|e| object_store::Error::Generic {
+            store: "index",
+            source: Box::new(e),
+        })?;
         self.os
-            .put(
-                &original_image.to_thumbnail().resolve_full_path(&self.sub_path),
-                PutPayload::from(thumbnail_data),
-            )
+            .put_opts(&self.post_index_path(), PutPayload::from(raw), PutOptions::from(mode))
             .instrument(info_span!("put"))
-            .await?;
+            .await
+            .map(|_| ())
+    }
 
-        Ok(original_image)
+    /// Apply a modification to the post index. The write is conditional on the index not having changed since we read
+    /// it, where the backend supports it, and retried a few times if it has. If the index does not exist yet, it is
+    /// rebuilt from the post objects instead since those have already been written.
+    async fn update_post_index(&self, modify: impl Fn(&mut Vec<PostIndexEntry>)) -> Result<(), Error> {
+        for _ in 0..Self::INDEX_UPDATE_ATTEMPTS {
+            let Some((mut entries, version)) = self.get_post_index().await? else {
+                return self.rebuild_post_index().await.map(|_| ());
+            };
+            modify(&mut entries);
+            match self.put_post_index(entries.clone(), PutMode::Update(version)).await {
+                Ok(_) => return Ok(()),
+                Err(object_store::Error::Precondition { .. }) => continue,
+                Err(object_store::Error::NotImplemented) => return Ok(self.put_post_index(entries, PutMode::Overwrite).await?),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(anyhow!("the post index was modified concurrently, please try again"))
+    }
+
+    /// Build the post index if it does not exist yet, returning the number of posts indexed if it was built.
+    #[instrument(skip_all, err)]
+    pub async fn ensure_post_index(&self) -> Result<Option<usize>, Error> {
+        match self.get_post_index().await? {
+            Some(_) => Ok(None),
+            None => self.rebuild_post_index().await.map(Some),
+        }
+    }
+
+    /// Rebuild the post index by listing all the objects under the posts prefix. This is used when the index does not
+    /// exist yet, and for recovery if it has drifted from the post objects. Returns the number of posts indexed.
+    #[instrument(skip_all, err)]
+    pub async fn rebuild_post_index(&self) -> Result<usize, Error> {
+        let entries = self.scan_post_index_entries().await?;
+        let count = entries.len();
+        self.put_post_index(entries, PutMode::Overwrite).await?;
+        Ok(count)
+    }
+
+    /// Build the entries of the post index from the post objects, reading the content of each post for the images it
+    /// references.
+    async fn scan_post_index_entries(&self) -> Result<Vec<PostIndexEntry>, Error> {
+        let mut entries = vec![];
+        for (post, version) in self.scan_posts_with_versions().await? {
+            let images = match self.get_post_raw(&post.slug).await? {
+                Some((post, content)) => referenced_images(&post, content.as_str()),
+                None => vec![],
+            };
+            entries.push(PostIndexEntry::from_post(&post, &version, images));
+        }
+        Ok(entries)
+    }
+
+    /// Write the original and the variants of a raster image as set by the [ImageConfig], returning the original along
+    /// with the metadata of what was written.
+    #[instrument(skip_all, fields(slug = slug), err)]
+    async fn create_raster_image(&self, slug: &str, image: DynamicImage) -> Result<(Image, ImageMetadata), Error> {
+        let slug: Arc<str> = Arc::from(slug);
+        for family in [ImageFamily::Webp, ImageFamily::Jpg, ImageFamily::Png] {
+            if self.check_image_exists(family.to_original(slug.clone())).await? {
+                return Err(Error::msg("image slug already exists"));
+            }
+        }
+        let config = self.image_config.clone();
+        let span = Span::current();
+        let encoded = spawn_blocking(move || span.in_scope(|| encode_raster_image(image, &config))).await??;
+
+        let original_image = encoded.family.to_original(slug);
+        for (img, data) in [
+            (original_image.clone(), encoded.original),
+            (original_image.to_medium(), encoded.medium),
+            (original_image.to_thumbnail(), encoded.thumbnail),
+        ] {
+            self.os
+                .put(&img.resolve_full_path(&self.sub_path), PutPayload::from(data))
+                .instrument(info_span!("put"))
+                .await?;
+        }
+        let widths = self.put_width_variants(&original_image, encoded.widths).await?;
+        let metadata = ImageMetadata {
+            width: encoded.width,
+            height: encoded.height,
+            widths,
+            width_ladder: self.image_config.width_ladder.clone(),
+            medium_bounds: self.image_config.medium_bounds,
+            thumbnail_bounds: self.image_config.thumbnail_bounds,
+            ..ImageMetadata::default()
+        };
+        Ok((original_image, metadata))
+    }
+
+    /// Returns the widths of the responsive variants from the ladder for an image of the given width, in ascending order.
+    fn ladder_widths(ladder: &[u32], width: u32) -> Vec<u32> {
+        let Some(widest) = ladder.iter().max() else {
+            return vec![];
+        };
+        let largest = width.min(*widest);
+        ladder
+            .iter()
+            .copied()
+            .filter(|w| *w < largest)
+            .sorted()
+            .dedup()
+            .chain([largest])
+            .filter(|w| *w > 0)
+            .collect_vec()
+    }
+
+    /// Write the encoded responsive variants of the image, returning their widths.
+    #[instrument(skip_all, fields(img = %original), err)]
+    async fn put_width_variants(&self, original: &Image, variants: Vec<EncodedWidth>) -> Result<Vec<u32>, Error> {
+        let mut widths = vec![];
+        for variant in variants {
+            for (img, data) in [
+                (original.to_webp_width(variant.width), variant.webp),
+                (original.to_avif_width(variant.width), variant.avif),
+            ] {
+                self.os
+                    .put(&img.resolve_full_path(&self.sub_path), PutPayload::from(data))
+                    .instrument(info_span!("put"))
+                    .await?;
+            }
+            widths.push(variant.width);
+        }
+        Ok(widths)
     }
 
     #[instrument(skip_all, fields(slug = slug))]
@@ -366,8 +1793,12 @@ impl Store {
             Some(Err(e)) => return Err(anyhow!(e)).context("failed to read svg"),
             None => return Err(Error::msg("empty svg content")),
         }
+        let stripped = strip_svg_metadata(raw_str).context("failed to read svg")?;
         self.os
-            .put(&original_image.resolve_full_path(&self.sub_path), PutPayload::from(raw.to_vec()))
+            .put(
+                &original_image.resolve_full_path(&self.sub_path),
+                PutPayload::from(stripped.into_bytes()),
+            )
             .instrument(info_span!("put", bytes = raw.len()))
             .await?;
         Ok(original_image)
@@ -380,21 +1811,316 @@ impl Store {
             return Err(anyhow!("invalid image slug - too short"));
         } else if slug.split_whitespace().count() != 1 {
             return Err(anyhow!("invalid image slug - no spaces allowed"));
+        } else if slug.contains('.') {
+            // The variants are told apart by the dot separated parts of their names.
+            return Err(anyhow!("invalid image slug - no dots allowed"));
         }
 
-        match ImageReader::new(Cursor::new(raw)).with_guessed_format()?.decode() {
-            Ok(dimg) => self.create_webp_image(slug, dimg).await.context("failed to create webp image"),
-            Err(_) => self.create_svg_image(slug, raw).await.context("failed to create SVG"),
+        let owned = raw.to_vec();
+        let span = Span::current();
+        let decoded = spawn_blocking(move || span.in_scope(|| decode_oriented(&owned))).await?;
+        let (image, metadata) = match decoded {
+            Ok((dimg, exif)) => {
+                let (image, metadata) = self
+                    .create_raster_image(slug, dimg)
+                    .await
+                    .context("failed to create raster image")?;
+                let captured = exif.as_deref().and_then(exif::capture_time);
+                (image, ImageMetadata { captured, ..metadata })
+            }
+            Err(_) => {
+                let image = self.create_svg_image(slug, raw).await.context("failed to create SVG")?;
+                let (width, height) = from_utf8(raw).ok().and_then(svg_dimensions).unwrap_or_default();
+                (
+                    image,
+                    ImageMetadata {
+                        width,
+                        height,
+                        ..ImageMetadata::default()
+                    },
+                )
+            }
+        };
+        let metadata = ImageMetadata {
+            size: raw.len() as u64,
+            uploaded: Utc::now(),
+            ..metadata
+        };
+        self.write_image_metadata(&image, &metadata).await?;
+        Ok(image)
+    }
+
+    fn image_metadata_path(&self, img: &Image) -> Path {
+        self.sub_path
+            .child("images")
+            .child(img.to_original().to_path_part())
+            .child(IMAGE_METADATA_NAME)
+    }
+
+    /// Write the metadata object of the image, and then its entry in the image index.
+    async fn write_image_metadata(&self, img: &Image, metadata: &ImageMetadata) -> Result<(), Error> {
+        let raw = postcard::to_allocvec(&ImageMetadataObject::from(metadata.clone()))?;
+        self.os
+            .put(&self.image_metadata_path(img), PutPayload::from(raw))
+            .instrument(info_span!("put"))
+            .await?;
+        self.update_image_index(|entries| {
+            entries.insert(img.to_original(), metadata.clone());
+        })
+        .await
+    }
+
+    /// Returns the metadata of the image, or None if the image was uploaded before metadata was recorded and the store
+    /// has not been migrated since.
+    #[instrument(skip_all, fields(img = %img.as_ref()), err)]
+    pub async fn get_image_metadata(&self, img: impl AsRef<Image>) -> Result<Option<ImageMetadata>, Error> {
+        match self
+            .os
+            .get(&self.image_metadata_path(img.as_ref()))
+            .and_then(|gr| gr.bytes())
+            .instrument(info_span!("get"))
+            .await
+        {
+            Ok(raw) => Ok(Some(
+                postcard::from_bytes::<ImageMetadataObject>(&raw)
+                    .context("failed to decode image metadata")?
+                    .into(),
+            )),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the metadata of an existing image.
+    #[instrument(skip_all, fields(img = %img.as_ref()), err)]
+    pub async fn put_image_metadata(&self, img: impl AsRef<Image>, metadata: &ImageMetadata) -> Result<(), Error> {
+        if !self.check_image_exists(img.as_ref().to_original()).await? {
+            return Err(anyhow!("image '{}' does not exist", img.as_ref().to_original()));
+        }
+        self.write_image_metadata(img.as_ref(), metadata).await
+    }
+
+    /// Returns the metadata of every image which has it, keyed by the original image. This reads the image index and
+    /// only falls back to getting the metadata of each image if the index is missing.
+    #[instrument(skip_all, err)]
+    pub async fn list_image_metadata(&self) -> Result<BTreeMap<Image, ImageMetadata>, Error> {
+        match self.get_image_index().await? {
+            Some((entries, _)) => Ok(entries),
+            None => {
+                warn!("image index is missing, getting the metadata of every image instead");
+                self.scan_image_metadata().await
+            }
+        }
+    }
+
+    /// Gets the metadata object of every image.
+    #[instrument(skip_all, err)]
+    async fn scan_image_metadata(&self) -> Result<BTreeMap<Image, ImageMetadata>, Error> {
+        let images = self.list_images().await?;
+        futures::stream::iter(images)
+            .map(|img| async move { Ok::<_, Error>(self.get_image_metadata(&img).await?.map(|m| (img, m))) })
+            .buffer_unordered(8)
+            .try_filter_map(|entry| async move { Ok(entry) })
+            .try_collect()
+            .await
+    }
+
+    fn image_index_path(&self) -> Path {
+        self.sub_path.child(IMAGE_INDEX_NAME)
+    }
+
+    /// Read the image index along with its version. Returns None if the index does not exist or cannot be decoded.
+    #[instrument(skip_all, err)]
+    async fn get_image_index(&self) -> Result<Option<(ImageMetadataMap, UpdateVersion)>, Error> {
+        let gr = match self.os.get(&self.image_index_path()).instrument(info_span!("get")).await {
+            Ok(gr) => gr,
+            Err(object_store::Error::NotFound { .. }) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let version = UpdateVersion {
+            e_tag: gr.meta.e_tag.clone(),
+            version: gr.meta.version.clone(),
+        };
+        let raw = gr.bytes().await?;
+        match postcard::from_bytes::<ImageIndex>(&raw) {
+            Ok(ImageIndex::V1(entries)) => Ok(Some((
+                entries
+                    .into_iter()
+                    .filter_map(|e| {
+                        let image = Image::try_from_path_part(PathPart::from(e.original.as_str())).ok()?;
+                        Some((image, ImageMetadata::from(e.meta)))
+                    })
+                    .collect(),
+                version,
+            ))),
+            Err(e) => {
+                warn!("failed to decode image index, ignoring it: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    // Errors are not recorded here since failed preconditions and unsupported put modes are handled by the caller.
+    #[instrument(skip_all, fields(entries = entries.len()))]
+    async fn put_image_index(&self, entries: &BTreeMap<Image, ImageMetadata>, mode: PutMode) -> Result<(), object_store::Error> {
+        let entries = entries
+            .iter()
+            .map(|(image, metadata)| ImageIndexEntry {
+                original: image.to_path_part().as_ref().to_string(),
+                meta: ImageMetadataObject::from(metadata.clone()),
+            })
+            .collect();
+        let raw = postcard::to_allocvec(&ImageIndex::V1(entries)).map_err(|e| object_store::Error::Generic {
+            store: IMAGE_INDEX_NAME,
+            source: Box::new(e),
+        })?;
+        self.os
+            .put_opts(&self.image_index_path(), PutPayload::from(raw), PutOptions::from(mode))
+            .instrument(info_span!("put"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Apply a modification to the image index, in the same way as [Store::update_post_index]. If the index does not
+    /// exist yet, it is rebuilt from the metadata objects instead since those have already been written.
+    async fn update_image_index(&self, modify: impl Fn(&mut BTreeMap<Image, ImageMetadata>)) -> Result<(), Error> {
+        for _ in 0..Self::INDEX_UPDATE_ATTEMPTS {
+            let Some((mut entries, version)) = self.get_image_index().await? else {
+                return self.rebuild_image_index().await.map(|_| ());
+            };
+            modify(&mut entries);
+            match self.put_image_index(&entries, PutMode::Update(version)).await {
+                Ok(_) => return Ok(()),
+                Err(object_store::Error::Precondition { .. }) => continue,
+                Err(object_store::Error::NotImplemented) => return Ok(self.put_image_index(&entries, PutMode::Overwrite).await?),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(anyhow!("the image index was modified concurrently, please try again"))
+    }
+
+    /// Build the image index if it does not exist yet, returning the number of images indexed if it was built.
+    #[instrument(skip_all, err)]
+    pub async fn ensure_image_index(&self) -> Result<Option<usize>, Error> {
+        match self.get_image_index().await? {
+            Some(_) => Ok(None),
+            None => self.rebuild_image_index().await.map(Some),
         }
     }
 
+    /// Rebuild the image index from the metadata object of every image. Returns the number of images indexed.
+    #[instrument(skip_all, err)]
+    pub async fn rebuild_image_index(&self) -> Result<usize, Error> {
+        let entries = self.scan_image_metadata().await?;
+        self.put_image_index(&entries, PutMode::Overwrite).await?;
+        Ok(entries.len())
+    }
+
+    /// Record the metadata and generate the responsive variants of an image uploaded before they were kept. Returns a
+    /// description of the change, if one is needed.
+    async fn migrate_image(&self, image: &Image, dry_run: bool) -> Result<Option<String>, Error> {
+        let existing = self.get_image_metadata(image).await?;
+        let needs_widths = image.family().is_some() && existing.as_ref().is_none_or(|m| m.widths.is_empty());
+        let change = match (&existing, needs_widths) {
+            (Some(_), false) => return Ok(None),
+            (_, true) => format!("record the metadata and responsive variants of image '{}'", image),
+            (None, false) => format!("record the metadata of image '{}'", image),
+        };
+        if dry_run {
+            return Ok(Some(change));
+        }
+        let mut metadata = match existing {
+            Some(metadata) => metadata,
+            None => self.derive_image_metadata(image).await?,
+        };
+        if needs_widths {
+            let raw = self
+                .get_image_raw(image)
+                .await?
+                .ok_or_else(|| anyhow!("image '{}' does not exist", image))?;
+            let config = self.image_config.clone();
+            let span = Span::current();
+            let variants = spawn_blocking(move || {
+                span.in_scope(|| -> Result<_, Error> {
+                    let decoded = ImageReader::new(Cursor::new(raw)).with_guessed_format()?.decode()?;
+                    encode_width_variants(&decoded, &config)
+                })
+            })
+            .await??;
+            metadata.widths = self.put_width_variants(image, variants).await?;
+            metadata.width_ladder = self.image_config.width_ladder.clone();
+        }
+        self.write_image_metadata(image, &metadata).await?;
+        Ok(Some(change))
+    }
+
+    /// Build the metadata of an image which was uploaded before metadata was recorded, from its stored original.
+    async fn derive_image_metadata(&self, img: &Image) -> Result<ImageMetadata, Error> {
+        let gr = self
+            .os
+            .get(&img.to_original().resolve_full_path(&self.sub_path))
+            .instrument(info_span!("get"))
+            .await?;
+        let uploaded = gr.meta.last_modified;
+        let raw = gr.bytes().await?;
+        let (width, height) = match img {
+            Image::Svg { .. } => from_utf8(&raw).ok().and_then(svg_dimensions),
+            _ => ImageReader::new(Cursor::new(&raw))
+                .with_guessed_format()
+                .ok()
+                .and_then(|r| r.into_dimensions().ok()),
+        }
+        .unwrap_or_default();
+        Ok(ImageMetadata {
+            width,
+            height,
+            size: raw.len() as u64,
+            uploaded,
+            ..ImageMetadata::default()
+        })
+    }
+
+    /// Delete the image along with all of its variants. Unless forced, this fails with an [ImageInUseError] if any post
+    /// still references the image.
     #[instrument(skip_all, fields(img = %img.as_ref()), err)]
-    pub async fn delete_image(&self, img: impl AsRef<Image>) -> Result<(), Error> {
-        let prefix_path = &self.sub_path.child("images").child(img.as_ref().to_original().to_path_part());
-        self.delete_paths_by_prefix(prefix_path).await.and_then(|i| match i {
-            0 => Err(Error::msg("not found")),
-            _ => Ok(()),
+    pub async fn delete_image(&self, img: impl AsRef<Image>, force: bool) -> Result<(), Error> {
+        let original = img.as_ref().to_original();
+        if !force {
+            if let Some(slugs) = self.list_image_references().await?.remove(&original) {
+                return Err(Error::new(ImageInUseError { image: original, slugs }));
+            }
+        }
+        let prefix_path = &self.sub_path.child("images").child(original.to_path_part());
+        self.delete_paths_by_prefix(prefix_path).await?;
+        self.update_image_index(|entries| {
+            entries.remove(&original);
         })
+        .await
+    }
+
+    /// Returns the sorted slugs of the posts which reference each original image, from the references recorded in the
+    /// post index when each post was saved. This only falls back to reading every post if the index is missing.
+    #[instrument(skip_all, err)]
+    pub async fn list_image_references(&self) -> Result<BTreeMap<Image, Vec<String>>, Error> {
+        let mut entries = match self.get_post_index().await? {
+            Some((entries, _)) => entries,
+            None => {
+                warn!("post index is missing, reading all posts for image references instead");
+                self.scan_post_index_entries().await?
+            }
+        };
+        entries.sort_by(|a, b| a.slug.cmp(&b.slug));
+        let mut references: BTreeMap<Image, Vec<String>> = BTreeMap::new();
+        for entry in entries {
+            for image in entry
+                .images
+                .iter()
+                .filter_map(|i| Image::try_from_path_part(PathPart::from(i.as_str())).ok())
+            {
+                references.entry(image).or_default().push(entry.slug.clone());
+            }
+        }
+        Ok(references)
     }
 
     fn labels_from_paths(i: Iter<&Path>, offset: usize) -> Vec<String> {
@@ -421,49 +2147,120 @@ impl Store {
 
     #[instrument(skip_all, err)]
     pub async fn list_posts(&self) -> Result<Vec<Post>, Error> {
-        let objects_paths: Vec<Path> = self
+        Ok(self.list_posts_with_last_modified().await?.into_iter().map(|(p, _)| p).collect())
+    }
+
+    /// Lists the posts along with the last modified time of their content, if known.
+    #[instrument(skip_all, err)]
+    pub async fn list_posts_with_last_modified(&self) -> Result<Vec<PostWithLastModified>, Error> {
+        Ok(self
+            .list_posts_with_versions()
+            .await?
+            .into_iter()
+            .map(|(p, v)| (p, v.last_modified))
+            .collect())
+    }
+
+    /// Lists the posts along with the version of their content. This reads the post index and only falls back to
+    /// listing the post objects if the index is missing.
+    #[instrument(skip_all, err)]
+    pub async fn list_posts_with_versions(&self) -> Result<Vec<PostWithVersion>, Error> {
+        match self.get_post_index().await? {
+            Some((entries, _)) => Ok(entries.into_iter().map(PostIndexEntry::into_post).collect()),
+            None => {
+                warn!("post index is missing, listing all post objects instead");
+                self.scan_posts_with_versions().await
+            }
+        }
+    }
+
+    /// Lists the posts by listing every object under the posts prefix, along with the version of their content
+    /// object, if it exists.
+    #[instrument(skip_all, err)]
+    async fn scan_posts_with_versions(&self) -> Result<Vec<PostWithVersion>, Error> {
+        let objects: Vec<ObjectMeta> = self
             .os
             .list(Some(&self.sub_path.child("posts")))
-            .map_ok(|i| path_tail(&i.location, &self.sub_path))
+            .map_ok(|i| ObjectMeta {
+                location: path_tail(&i.location, &self.sub_path),
+                ..i
+            })
             .boxed()
-            .try_collect::<Vec<Path>>()
+            .try_collect::<Vec<ObjectMeta>>()
             .instrument(info_span!("list"))
             .await?;
 
-        // each path looks like images/... since we've removed the prefix path already
-        Ok(objects_paths
-            .iter()
-            .into_group_map_by(|f| f.parts().nth(1))
+        // each path looks like posts/... since we've removed the prefix path already
+        let groups = objects.iter().into_group_map_by(|f| f.location.parts().nth(1));
+        let mut posts = Vec::with_capacity(groups.len());
+        for (slug, metas) in groups
             .iter()
-            .flat_map(|(slug, paths)| slug.as_ref().map(|p| (p.as_ref(), paths)))
-            .map(|(slug, paths)| {
-                let slug = slug.to_string();
-                let labels = Self::labels_from_paths(paths.iter(), 0);
-                match Self::props_part_from_paths(paths.iter(), 0) {
-                    Some(PostMetadata::V1((date, title, published))) => Post {
-                        date,
-                        slug,
-                        title,
-                        published: published.into(),
-                        labels,
-                    },
-                    None => Post {
-                        slug,
-                        labels,
-                        ..Post::default()
-                    },
+            .flat_map(|(slug, metas)| slug.as_ref().map(|p| (p.as_ref().to_string(), metas)))
+        {
+            let section = |m: &ObjectMeta, name: &str| m.location.parts().nth(2).is_some_and(|pp| pp.as_ref() == name);
+            if metas.iter().any(|m| section(m, "commit")) {
+                let post_path = self.sub_path.child("posts").child(slug.as_str());
+                if let Some((Commit::V1((_, meta, labels)), version)) = self.get_post_commit(&post_path).await? {
+                    posts.push((meta.into_post(slug, labels), version));
                 }
-            })
-            .collect())
+                continue;
+            }
+            // Without a commit or legacy content, these are only the leftovers of an interrupted first write.
+            let Some(content) = metas.iter().find(|m| section(m, "content")) else {
+                continue;
+            };
+            let version = ContentVersion {
+                last_modified: Some(content.last_modified),
+                e_tag: content.e_tag.clone(),
+            };
+            let paths = metas.iter().map(|m| &m.location).collect_vec();
+            let labels = Self::labels_from_paths(paths.iter(), 0);
+            let post = match Self::props_part_from_paths(paths.iter(), 0) {
+                Some(meta) => meta.into_post(slug, labels),
+                None => Post {
+                    slug,
+                    labels,
+                    ..Post::default()
+                },
+            };
+            posts.push((post, version));
+        }
+        Ok(posts)
     }
 
     #[instrument(skip_all, fields(slug = slug), err)]
     pub async fn get_post_raw(&self, slug: &str) -> Result<Option<(Post, String)>, Error> {
+        Ok(self.get_post_raw_with_e_tag(slug).await?.map(|(post, content, _)| (post, content)))
+    }
+
+    /// Returns the post and its content along with the etag of the content, which can be passed back to
+    /// [Store::upsert_post] as the expected version.
+    #[instrument(skip_all, fields(slug = slug), err)]
+    pub async fn get_post_raw_with_e_tag(&self, slug: &str) -> Result<Option<(Post, String, Option<String>)>, Error> {
         let post_path = self.sub_path.child("posts").child(slug);
-        let content_bytes = match self
+        if let Some((Commit::V1((txid, meta, labels)), version)) = self.get_post_commit(&post_path).await? {
+            let content_path = post_path.child("tx").child(txid).child("content");
+            let content_bytes = self
+                .os
+                .get(&content_path)
+                .and_then(|gr| gr.bytes())
+                .instrument(info_span!("get"))
+                .await
+                .with_context(|| format!("failed to read committed content {}", content_path))?;
+            let content = String::from_utf8_lossy(content_bytes.as_ref()).to_string();
+            return Ok(Some((meta.into_post(slug.to_string(), labels), content, version.e_tag)));
+        }
+        self.get_legacy_post_raw(&post_path, slug).await
+    }
+
+    async fn get_legacy_post_raw(&self, post_path: &Path, slug: &str) -> Result<Option<(Post, String, Option<String>)>, Error> {
+        let (content_bytes, e_tag) = match self
             .os
             .get(&post_path.child("content"))
-            .and_then(|gr| gr.bytes())
+            .and_then(|gr| async move {
+                let e_tag = gr.meta.e_tag.clone();
+                Ok((gr.bytes().await?, e_tag))
+            })
             .instrument(info_span!("get"))
             .await
         {
@@ -476,7 +2273,7 @@ impl Store {
         let content = String::from_utf8_lossy(content_bytes.as_ref()).to_string();
         let post_paths: Vec<Path> = self
             .os
-            .list(Some(&post_path))
+            .list(Some(post_path))
             .map_ok(|i| path_tail(&i.location, &self.sub_path))
             .boxed()
             .try_collect::<Vec<Path>>()
@@ -485,20 +2282,14 @@ impl Store {
         let post_paths_refs: Vec<&Path> = post_paths.iter().collect();
         let labels = Self::labels_from_paths(post_paths_refs.iter(), 0);
         let post = match Self::props_part_from_paths(post_paths_refs.iter(), 0) {
-            Some(PostMetadata::V1((date, title, published))) => Post {
-                date,
-                slug: slug.to_string(),
-                title,
-                published: published.into(),
-                labels,
-            },
+            Some(meta) => meta.into_post(slug.to_string(), labels),
             None => Post {
                 slug: slug.to_string(),
                 labels,
                 ..Post::default()
             },
         };
-        Ok(Some((post, content)))
+        Ok(Some((post, content, e_tag)))
     }
 
     #[instrument(skip_all, err)]
@@ -520,6 +2311,23 @@ impl Store {
             .collect_vec())
     }
 
+    /// Lists the original image along with all of the variants stored for it.
+    #[instrument(skip_all, fields(img = %img.as_ref()), err)]
+    pub async fn list_image_variants(&self, img: impl AsRef<Image>) -> Result<Vec<Image>, Error> {
+        let original = img.as_ref().to_original();
+        Ok(self
+            .os
+            .list(Some(&self.sub_path.child("images").child(original.to_path_part())))
+            .try_collect::<Vec<ObjectMeta>>()
+            .instrument(info_span!("list"))
+            .await?
+            .iter()
+            .filter_map(|meta| meta.location.filename())
+            .filter_map(|name| Image::try_from_path_part(PathPart::from(name)).ok())
+            .sorted_by_key(|i| (i.to_original() != *i, i.to_string()))
+            .collect_vec())
+    }
+
     #[instrument(skip_all, fields(img = %img.as_ref()), err)]
     pub async fn check_image_exists(&self, img: impl AsRef<Image>) -> Result<bool, Error> {
         let p = &self.sub_path;
@@ -567,18 +2375,314 @@ impl Default for Store {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct IsPublished(bool);
-
-impl From<IsPublished> for bool {
-    fn from(is_published: IsPublished) -> Self {
-        is_published.0
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IsPublished(bool);
+
+impl From<IsPublished> for bool {
+    fn from(is_published: IsPublished) -> Self {
+        is_published.0
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum PostMetadata {
+    V1((NaiveDate, String, IsPublished)),
+    V2(PostMetadataV2),
+    V3(PostMetadataV3),
+}
+
+/// The metadata written by [PostMetadata::V2]. Variants are only ever appended to [PostMetadata], so that the objects
+/// written with older versions can still be decoded.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PostMetadataV2 {
+    date: NaiveDate,
+    title: String,
+    published: IsPublished,
+    summary: Option<String>,
+    updated: Option<NaiveDate>,
+    author: Option<String>,
+    cover_image: Option<String>,
+}
+
+/// The metadata written by [PostMetadata::V3], which adds the publish-at time to [PostMetadataV2].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct PostMetadataV3 {
+    date: NaiveDate,
+    title: String,
+    published: IsPublished,
+    summary: Option<String>,
+    updated: Option<NaiveDate>,
+    author: Option<String>,
+    cover_image: Option<String>,
+    publish_at: Option<DateTime<FixedOffset>>,
+}
+
+/// Returns the names of the original images which the content or cover image of a post links to, in order. A link to
+/// any variant counts as a reference to its original.
+fn referenced_images(post: &Post, content: &str) -> Vec<String> {
+    conversion::find_links(content)
+        .into_iter()
+        .chain(post.cover_image.clone())
+        .filter_map(|link| {
+            link.strip_prefix("/images/")
+                .and_then(|rest| Image::try_from_path_part(PathPart::from(rest)).ok())
+        })
+        .map(|image| image.to_original().to_string())
+        .sorted()
+        .dedup()
+        .collect()
+}
+
+/// An entry in the [PostIndex]. This is kept separate from [Post] so that the encoded index is not affected by
+/// changes to that struct.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct PostIndexEntry {
+    slug: String,
+    meta: PostMetadata,
+    labels: Vec<String>,
+    last_modified: Option<DateTime<Utc>>,
+    content_e_tag: Option<String>,
+    /// The originals of the images which the post references, see [referenced_images].
+    images: Vec<String>,
+}
+
+/// The entries of a [PostIndex::V1] which did not record the etag of the content.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct PostIndexEntryV1 {
+    slug: String,
+    meta: PostMetadata,
+    labels: Vec<String>,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+impl From<PostIndexEntryV1> for PostIndexEntry {
+    fn from(v1: PostIndexEntryV1) -> Self {
+        Self {
+            slug: v1.slug,
+            meta: v1.meta,
+            labels: v1.labels,
+            last_modified: v1.last_modified,
+            content_e_tag: None,
+            images: vec![],
+        }
+    }
+}
+
+impl PostIndexEntry {
+    fn from_post(post: &Post, version: &ContentVersion, images: Vec<String>) -> Self {
+        Self {
+            slug: post.slug.clone(),
+            meta: PostMetadata::from(post),
+            labels: post.labels.iter().cloned().sorted().dedup().collect(),
+            last_modified: version.last_modified,
+            content_e_tag: version.e_tag.clone(),
+            images,
+        }
+    }
+
+    fn into_post(self) -> PostWithVersion {
+        let post = self.meta.into_post(self.slug, self.labels);
+        (
+            post,
+            ContentVersion {
+                last_modified: self.last_modified,
+                e_tag: self.content_e_tag,
+            },
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum PostIndex {
+    V1(Vec<PostIndexEntryV1>),
+    V2(Vec<PostIndexEntry>),
+}
+
+impl PostMetadata {
+    fn into_post(self, slug: String, labels: Vec<String>) -> Post {
+        match self {
+            PostMetadata::V1((date, title, published)) => Post {
+                date,
+                slug,
+                title,
+                published: published.into(),
+                labels,
+                ..Post::default()
+            },
+            PostMetadata::V2(v2) => Post {
+                date: v2.date,
+                slug,
+                title: v2.title,
+                published: v2.published.into(),
+                labels,
+                summary: v2.summary,
+                updated: v2.updated,
+                author: v2.author,
+                cover_image: v2.cover_image,
+                publish_at: None,
+            },
+            PostMetadata::V3(v3) => Post {
+                date: v3.date,
+                slug,
+                title: v3.title,
+                published: v3.published.into(),
+                labels,
+                summary: v3.summary,
+                updated: v3.updated,
+                author: v3.author,
+                cover_image: v3.cover_image,
+                publish_at: v3.publish_at,
+            },
+        }
+    }
+}
+
+impl From<&Post> for PostMetadata {
+    fn from(post: &Post) -> Self {
+        PostMetadata::V3(PostMetadataV3 {
+            date: post.date,
+            title: post.title.clone(),
+            published: IsPublished(post.published),
+            summary: post.summary.clone(),
+            updated: post.updated,
+            author: post.author.clone(),
+            cover_image: post.cover_image.clone(),
+            publish_at: post.publish_at,
+        })
+    }
+}
+
+/// The commit object of a post, identifying the transaction holding its content along with its metadata and labels.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum Commit {
+    V1((String, PostMetadata, Vec<String>)),
+}
+
+/// The redirects object. V1 only held the (from, to) paths of exact permanent redirects.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum RedirectMap {
+    V1(Vec<(String, String)>),
+    V2(Vec<RedirectEntry>),
+}
+
+/// An entry in the [RedirectMap]. This is kept separate from [Redirect] so that the encoding is not affected by
+/// changes to that struct.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct RedirectEntry {
+    from: String,
+    to: String,
+    prefix: bool,
+    permanent: bool,
+}
+
+impl From<Redirect> for RedirectEntry {
+    fn from(r: Redirect) -> Self {
+        Self {
+            from: r.from,
+            to: r.to,
+            prefix: r.prefix,
+            permanent: r.permanent,
+        }
+    }
+}
+
+impl From<RedirectEntry> for Redirect {
+    fn from(e: RedirectEntry) -> Self {
+        Self {
+            from: e.from,
+            to: e.to,
+            prefix: e.prefix,
+            permanent: e.permanent,
+        }
+    }
+}
+
+/// The metadata object of an image.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum ImageMetadataObject {
+    V1(ImageMetadataV1),
+}
+
+/// The metadata of every image, keyed by the original image.
+type ImageMetadataMap = BTreeMap<Image, ImageMetadata>;
+
+/// The image index object, holding the metadata object of every original image.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum ImageIndex {
+    V1(Vec<ImageIndexEntry>),
+}
+
+/// An entry in the [ImageIndex], keyed by the path part of the original image.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct ImageIndexEntry {
+    original: String,
+    meta: ImageMetadataObject,
+}
+
+/// This is kept separate from [ImageMetadata] so that the encoding is not affected by changes to that struct.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct ImageMetadataV1 {
+    alt: String,
+    caption: String,
+    width: u32,
+    height: u32,
+    size: u64,
+    uploaded: DateTime<Utc>,
+    widths: Vec<u32>,
+    width_ladder: Vec<u32>,
+    medium_bounds: (u32, u32),
+    thumbnail_bounds: (u32, u32),
+    captured: Option<NaiveDateTime>,
+}
+
+impl From<ImageMetadata> for ImageMetadataObject {
+    fn from(m: ImageMetadata) -> Self {
+        ImageMetadataObject::V1(ImageMetadataV1 {
+            alt: m.alt,
+            caption: m.caption,
+            width: m.width,
+            height: m.height,
+            size: m.size,
+            uploaded: m.uploaded,
+            widths: m.widths,
+            width_ladder: m.width_ladder,
+            medium_bounds: m.medium_bounds,
+            thumbnail_bounds: m.thumbnail_bounds,
+            captured: m.captured,
+        })
+    }
+}
+
+impl From<ImageMetadataObject> for ImageMetadata {
+    fn from(o: ImageMetadataObject) -> Self {
+        match o {
+            ImageMetadataObject::V1(e) => Self {
+                alt: e.alt,
+                caption: e.caption,
+                width: e.width,
+                height: e.height,
+                size: e.size,
+                uploaded: e.uploaded,
+                widths: e.widths,
+                width_ladder: e.width_ladder,
+                medium_bounds: e.medium_bounds,
+                thumbnail_bounds: e.thumbnail_bounds,
+                captured: e.captured,
+            },
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
-enum PostMetadata {
-    V1((NaiveDate, String, IsPublished)),
+/// The schema marker object, recording the version of the layout the store was last migrated to.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum SchemaMarker {
+    V1(u32),
+}
+
+/// A full copy of a post as it was saved by [Store::upsert_post].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum Revision {
+    V1((PostMetadata, Vec<String>, String)),
 }
 
 impl TryFrom<PathPart<'_>> for PostMetadata {
@@ -666,7 +2770,29 @@ mod tests {
         assert_ne!(store.get_image_raw(img.to_medium()).await?, None);
         assert_ne!(store.get_image_raw(img.to_original()).await?, None);
 
-        store.delete_image(&img).await?;
+        // An image can't be deleted while a post references it, unless forced.
+        let post = Post {
+            slug: "uses-image".to_string(),
+            title: "Uses image".to_string(),
+            ..Post::default()
+        };
+        let content = format!("[![alt](/images/{})](/images/{})", img.to_medium(), img);
+        store.upsert_post(&post, content.as_str(), &ExpectedVersion::Absent).await?;
+        let references = BTreeMap::from([(img.clone(), vec!["uses-image".to_string()])]);
+        assert_eq!(store.list_image_references().await?, references);
+        // The references are recorded in the post index, and found by reading the posts when it is missing.
+        let entries = store.get_post_index().await?.map(|(entries, _)| entries).unwrap_or_default();
+        assert_eq!(entries.iter().map(|e| e.images.clone()).collect_vec(), vec![vec![img.to_string()]]);
+        store.os.delete(&store.post_index_path()).await?;
+        assert_eq!(store.list_image_references().await?, references);
+        store.rebuild_post_index().await?;
+        let err = store.delete_image(&img, false).await.err().ok_or(anyhow!("expected an error"))?;
+        assert_eq!(
+            err.downcast_ref::<ImageInUseError>().map(|e| e.slugs.clone()),
+            Some(vec!["uses-image".to_string()])
+        );
+
+        store.delete_image(&img, true).await?;
         assert_eq!(store.list_images().await?, Vec::<Image>::new());
         assert_eq!(store.get_image_raw(img.to_thumbnail()).await?, None);
         assert_eq!(store.get_image_raw(img.to_medium()).await?, None);
@@ -674,6 +2800,299 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_image_path_parts() {
+        let slug: Arc<str> = Arc::from("photo");
+        let svg = Image::Svg { slug: slug.clone() };
+        assert_eq!(Image::try_from_path_part(svg.to_path_part()).ok(), Some(svg));
+        for original in [
+            Image::Webp { slug: slug.clone() },
+            Image::Jpg { slug: slug.clone() },
+            Image::Png { slug: slug.clone() },
+        ] {
+            let images = [
+                original.clone(),
+                original.to_medium(),
+                original.to_thumbnail(),
+                original.to_webp_width(400),
+                original.to_webp_width(800),
+                original.to_avif_width(1600),
+            ];
+            for image in images {
+                assert_eq!(Image::try_from_path_part(image.to_path_part()).ok(), Some(image.clone()));
+                assert_eq!(image.slug(), &slug);
+                assert_eq!(image.to_original(), original);
+            }
+        }
+        assert_eq!(Image::Jpg { slug: slug.clone() }.to_medium().to_string(), "photo.medium.jpg.jpg");
+        assert_eq!(Image::Jpg { slug: slug.clone() }.to_content_type(), "image/jpeg");
+        assert_eq!(Image::Png { slug: slug.clone() }.to_thumbnail().to_string(), "photo.thumb.png.png");
+        assert_eq!(
+            Image::try_from_path_part(PathPart::from("photo.w800.webp"))
+                .ok()
+                .map(|i| i.to_original()),
+            Some(Image::Webp { slug: slug.clone() })
+        );
+        // Legacy slugs may contain dots, and their variants keep resolving to the WebP original.
+        assert_eq!(
+            Image::try_from_path_part(PathPart::from("photo.jpg.medium.jpg"))
+                .ok()
+                .map(|i| i.to_original()),
+            Some(Image::Webp {
+                slug: Arc::from("photo.jpg")
+            })
+        );
+        assert_eq!(
+            Image::try_from_path_part(PathPart::from("photo.jpg.webp")).ok(),
+            Some(Image::Webp {
+                slug: Arc::from("photo.jpg")
+            })
+        );
+        assert!(Image::try_from_path_part(PathPart::from("photo.avif")).is_err());
+    }
+
+    #[test]
+    fn test_image_variant_dimensions() {
+        let img = Image::Webp { slug: Arc::from("wide") };
+        let metadata = ImageMetadata {
+            width: 2000,
+            height: 1200,
+            ..ImageMetadata::default()
+        };
+        assert_eq!(metadata.variant_dimensions(&img), Some((2000, 1200)));
+        assert_eq!(metadata.variant_dimensions(&img.to_medium()), Some((800, 480)));
+        assert_eq!(metadata.variant_dimensions(&img.to_thumbnail()), Some((200, 120)));
+        assert_eq!(metadata.variant_dimensions(&img.to_avif_width(1200)), Some((1200, 720)));
+        assert_eq!(ImageMetadata::default().variant_dimensions(&img), None);
+        let resized = ImageMetadata {
+            medium_bounds: (1000, 1000),
+            thumbnail_bounds: (100, 100),
+            ..metadata.clone()
+        };
+        assert_eq!(resized.variant_dimensions(&img.to_medium()), Some((1000, 600)));
+        assert_eq!(resized.variant_dimensions(&img.to_thumbnail()), Some((100, 60)));
+
+        assert_eq!(Store::ladder_widths(&DEFAULT_WIDTH_LADDER, 2000), vec![400, 800, 1200, 1600]);
+        assert_eq!(Store::ladder_widths(&DEFAULT_WIDTH_LADDER, 1000), vec![400, 800, 1000]);
+        assert_eq!(Store::ladder_widths(&DEFAULT_WIDTH_LADDER, 800), vec![400, 800]);
+        assert_eq!(Store::ladder_widths(&DEFAULT_WIDTH_LADDER, 120), vec![120]);
+        assert_eq!(Store::ladder_widths(&[900, 300, 300], 500), vec![300, 500]);
+        assert_eq!(Store::ladder_widths(&[], 500), Vec::<u32>::new());
+    }
+
+    #[tokio::test]
+    async fn test_store_image_metadata() -> Result<(), Error> {
+        let store = Store::default();
+        let mut raw: Vec<u8> = vec![];
+        // Small images keep the test fast, since the AVIF encoder is slow in debug builds.
+        DynamicImage::new(120, 60, ColorType::Rgb8).write_with_encoder(JpegEncoder::new(&mut raw))?;
+        let img = store.create_image("wide", raw.as_slice()).await?;
+        let metadata = store.get_image_metadata(&img).await?.unwrap_or_default();
+        assert_eq!((metadata.width, metadata.height, metadata.size), (120, 60, raw.len() as u64));
+        assert_eq!(metadata.widths, vec![120]);
+        assert!(store.check_image_exists(img.to_avif_width(120)).await?);
+        assert!(store.check_image_exists(img.to_webp_width(120)).await?);
+
+        let svg = store
+            .create_image(
+                "drawing",
+                br#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 320 240"></svg>"#,
+            )
+            .await?;
+        let svg_metadata = store.get_image_metadata(&svg).await?.unwrap_or_default();
+        assert_eq!((svg_metadata.width, svg_metadata.height), (320, 240));
+
+        let edited = ImageMetadata {
+            alt: "A black rectangle".to_string(),
+            caption: "Nothing to see here".to_string(),
+            ..metadata.clone()
+        };
+        store.put_image_metadata(img.to_medium(), &edited).await?;
+        assert_eq!(store.get_image_metadata(&img).await?, Some(edited));
+        let missing = Image::Webp {
+            slug: Arc::from("missing"),
+        };
+        assert!(store.put_image_metadata(&missing, &metadata).await.is_err());
+
+        // Images uploaded before metadata or the responsive variants were recorded get them when migrating.
+        store.os.delete(&store.image_metadata_path(&img)).await?;
+        store.os.delete(&img.to_avif_width(120).resolve_full_path(&store.sub_path)).await?;
+        assert_eq!(store.get_image_metadata(&img).await?, None);
+        let changes = store.migrate(false).await?;
+        assert!(changes.contains(&"record the metadata and responsive variants of image 'wide.webp'".to_string()));
+        let derived = store.get_image_metadata(&img).await?.unwrap_or_default();
+        assert_eq!((derived.width, derived.height, derived.widths.clone()), (120, 60, vec![120]));
+        assert!(store.check_image_exists(img.to_avif_width(120)).await?);
+        assert_eq!(store.list_image_metadata().await?.len(), 2);
+        assert!(store.create_image("with.dot", raw.as_slice()).await.is_err());
+
+        // The image index follows every write and delete, and losing it falls back to the metadata objects.
+        assert_eq!(store.list_image_metadata().await?.get(&img), Some(&derived));
+        assert_eq!(store.ensure_image_index().await?, None);
+        store.delete_image(&svg, true).await?;
+        assert_eq!(store.list_image_metadata().await?.into_keys().collect_vec(), vec![img.clone()]);
+        store.os.delete(&store.image_index_path()).await?;
+        assert_eq!(store.list_image_metadata().await?.get(&img), Some(&derived));
+        assert_eq!(store.ensure_image_index().await?, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_reduce_levels() {
+        let pixels = image::RgbaImage::from_fn(2, 1, |x, _| match x {
+            0 => image::Rgba([1, 130, 255, 77]),
+            _ => image::Rgba([6, 127, 250, 255]),
+        });
+        let image = DynamicImage::ImageRgba8(pixels);
+        assert_eq!(reduce_levels(image.clone(), 100), image);
+        let reduced = reduce_levels(image, 60).into_rgba8();
+        assert_eq!(reduced.get_pixel(0, 0).0, [0, 132, 255, 77]);
+        assert_eq!(reduced.get_pixel(1, 0).0, [8, 128, 252, 255]);
+    }
+
+    #[tokio::test]
+    async fn test_store_image_width_sizes() -> Result<(), Error> {
+        let store = Store::default();
+        // A gradient with a little noise compresses about as poorly as a photo does when lossless. It is no wider than the
+        // 800px variant and short, since the AVIF encoder is slow in debug builds.
+        let mut state = 1u32;
+        let photo = image::RgbImage::from_fn(800, 120, |x, y| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let noise = (state >> 24) as u8 % 24;
+            image::Rgb([(x / 4) as u8 ^ noise, y as u8 + noise, ((x + y) / 4) as u8])
+        });
+        let mut raw: Vec<u8> = vec![];
+        DynamicImage::ImageRgb8(photo).write_with_encoder(PngEncoder::new(&mut raw))?;
+        let img = store.create_image("photo", raw.as_slice()).await?;
+        let original = store.get_image_raw(&img).await?.unwrap_or_default().len();
+        for variant in [img.to_webp_width(800), img.to_avif_width(800)] {
+            let size = store.get_image_raw(&variant).await?.unwrap_or_default().len();
+            assert!(
+                size > 0 && size < original,
+                "{} is {} bytes and the original {} bytes",
+                variant,
+                size,
+                original
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_image_config() -> Result<(), Error> {
+        let store = Store::default().with_image_config(ImageConfig {
+            medium_bounds: (60, 60),
+            thumbnail_bounds: (20, 20),
+            lossy_original_quality: Some(70),
+            max_original_dimension: Some(100),
+            width_ladder: vec![80, 40],
+            width_quality: 100,
+            avif_speed: 10,
+            ..ImageConfig::default()
+        });
+        let mut raw: Vec<u8> = vec![];
+        DynamicImage::new(200, 100, ColorType::Rgb8).write_with_encoder(JpegEncoder::new(&mut raw))?;
+        let img = store.create_image("photo", raw.as_slice()).await?;
+        assert_eq!(img, Image::Jpg { slug: Arc::from("photo") });
+        let metadata = store.get_image_metadata(&img).await?.unwrap_or_default();
+        assert_eq!((metadata.width, metadata.height, metadata.widths.clone()), (100, 50, vec![40, 80]));
+        assert_eq!(metadata.width_ladder, vec![80, 40]);
+        assert!(store.check_image_exists(img.to_webp_width(40)).await?);
+        assert_eq!(metadata.variant_dimensions(&img.to_medium()), Some((60, 30)));
+        let original = store.get_image_raw(&img).await?.unwrap_or_default();
+        let decoded = ImageReader::new(Cursor::new(original)).with_guessed_format()?.decode()?;
+        assert_eq!((decoded.width(), decoded.height()), (100, 50));
+        let medium = store.get_image_raw(img.to_medium()).await?.unwrap_or_default();
+        let decoded = ImageReader::new(Cursor::new(medium)).with_guessed_format()?.decode()?;
+        assert_eq!((decoded.width(), decoded.height()), (60, 30));
+        assert_eq!(store.list_image_variants(&img).await?.len(), 7);
+
+        // A slug is taken by any kind of original.
+        assert!(store.create_image("photo", raw.as_slice()).await.is_err());
+        assert_eq!(
+            Store::default().create_image("photo", raw.as_slice()).await?,
+            Image::Webp { slug: Arc::from("photo") }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_transparent_image() -> Result<(), Error> {
+        let store = Store::default().with_image_config(ImageConfig {
+            lossy_original_quality: Some(70),
+            ..ImageConfig::default()
+        });
+        let mut overlay = image::RgbaImage::new(40, 20);
+        overlay.pixels_mut().step_by(2).for_each(|p| *p = image::Rgba([255, 0, 0, 255]));
+        let mut raw: Vec<u8> = vec![];
+        DynamicImage::ImageRgba8(overlay).write_with_encoder(PngEncoder::new(&mut raw))?;
+        let img = store.create_image("overlay", raw.as_slice()).await?;
+        assert_eq!(
+            img,
+            Image::Png {
+                slug: Arc::from("overlay")
+            }
+        );
+        for variant in [img.clone(), img.to_medium(), img.to_thumbnail(), img.to_webp_width(40)] {
+            let raw = store.get_image_raw(&variant).await?.unwrap_or_default();
+            let decoded = ImageReader::new(Cursor::new(raw)).with_guessed_format()?.decode()?;
+            assert!(has_transparency(&decoded), "{} lost its transparency", variant);
+        }
+        assert_eq!(store.get_image_metadata(&img).await?.unwrap_or_default().widths, vec![40]);
+
+        // An alpha channel without any transparent pixels is dropped.
+        let mut raw: Vec<u8> = vec![];
+        DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(40, 20, image::Rgba([0, 0, 255, 255])))
+            .write_with_encoder(PngEncoder::new(&mut raw))?;
+        let img = store.create_image("opaque", raw.as_slice()).await?;
+        assert_eq!(img, Image::Jpg { slug: Arc::from("opaque") });
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_image_exif() -> Result<(), Error> {
+        let store = Store::default();
+        let mut jpeg: Vec<u8> = vec![];
+        DynamicImage::new(40, 20, ColorType::Rgb8).write_with_encoder(JpegEncoder::new(&mut jpeg))?;
+        // Insert an APP1 segment holding the EXIF chunk right after the start of image marker.
+        let chunk = [b"Exif\0\0".as_slice(), exif::tests::example_chunk(6).as_slice()].concat();
+        let segment_length = u16::try_from(chunk.len() + 2)?;
+        let raw = [&jpeg[..2], &[0xff, 0xe1], &segment_length.to_be_bytes(), &chunk, &jpeg[2..]].concat();
+        assert_eq!(decode_oriented(&raw)?.1, Some(exif::tests::example_chunk(6)));
+
+        let img = store.create_image("sideways", raw.as_slice()).await?;
+        let metadata = store.get_image_metadata(&img).await?.unwrap_or_default();
+        assert_eq!((metadata.width, metadata.height), (20, 40));
+        assert_eq!(
+            metadata.captured,
+            NaiveDate::from_ymd_opt(2024, 5, 6).and_then(|d| d.and_hms_opt(7, 8, 9))
+        );
+        // The AVIF variants are skipped since there is no decoder for them, but their encoder has no way to write metadata.
+        for variant in store.list_image_variants(&img).await? {
+            if matches!(variant, Image::AvifWidth { .. }) {
+                continue;
+            }
+            let raw = store.get_image_raw(&variant).await?.unwrap_or_default();
+            let mut decoder = ImageReader::new(Cursor::new(raw)).with_guessed_format()?.into_decoder()?;
+            assert_eq!(decoder.exif_metadata()?, None, "{} kept the exif metadata", variant);
+            let (width, height) = decoder.dimensions();
+            assert!(height > width, "{} was not rotated", variant);
+        }
+
+        let svg = store
+            .create_image(
+                "drawing",
+                br#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?><svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><metadata><rdf:RDF><dc:creator>Someone</dc:creator></rdf:RDF></metadata><rect width="10" height="10"/></svg>"#,
+            )
+            .await?;
+        let stored = store.get_image_raw(&svg).await?.unwrap_or_default();
+        assert_eq!(
+            from_utf8(&stored)?,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="10" height="10"/></svg>"#
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_store_posts() -> Result<(), Error> {
         let store = Store {
@@ -688,8 +3107,10 @@ mod tests {
                     title: "My first post".to_string(),
                     published: true,
                     labels: vec!["blue".to_string(), "green".to_string()],
+                    ..Post::default()
                 },
                 "my-content",
+                &ExpectedVersion::Any,
             )
             .await?;
 
@@ -711,8 +3132,10 @@ mod tests {
                     title: "My updated first post".to_string(),
                     published: false,
                     labels: vec!["red".to_string(), "green".to_string()],
+                    ..Post::default()
                 },
                 "my-updated-content",
+                &ExpectedVersion::Any,
             )
             .await?;
 
@@ -723,8 +3146,488 @@ mod tests {
         assert!(!post.published);
         assert_eq!(post.labels, vec!["green".to_string(), "red".to_string()]);
         assert_eq!(content, "my-updated-content".to_string());
-        assert_eq!(store.list_object_meta().await?.len(), 4);
+        // The previous transaction is within the grace period, so it is kept until a repair.
+        assert_eq!(store.list_object_meta().await?.len(), 6);
+        assert_eq!(store.list_posts().await?, vec![post.clone()]);
+        assert_eq!(store.repair_posts(TimeDelta::zero()).await?.len(), 1);
+        assert_eq!(store.list_object_meta().await?.len(), 5);
+        assert_eq!(
+            store.get_post_raw("my-first-post").await?.unwrap_or_default().1,
+            "my-updated-content"
+        );
+
+        // Both versions are kept as revisions, newest first.
+        let revisions = store.list_revisions("my-first-post").await?;
+        assert_eq!(revisions.len(), 2);
+        assert!(revisions[0].saved > revisions[1].saved);
+        assert_eq!(
+            store.get_revision("my-first-post", revisions[0].id.as_str()).await?,
+            Some((post, "my-updated-content".to_string()))
+        );
+        let (old_post, old_content) = store
+            .get_revision("my-first-post", revisions[1].id.as_str())
+            .await?
+            .unwrap_or_default();
+        assert_eq!(old_post.title, "My first post");
+        assert_eq!(old_post.labels, vec!["blue".to_string(), "green".to_string()]);
+        assert_eq!(old_content, "my-content");
+        assert_eq!(store.get_revision("my-first-post", "20000101T000000.000000Z").await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_post_metadata() -> Result<(), Error> {
+        let store = Store::default();
+        let eg_image = DynamicImage::new(100, 100, ColorType::Rgb8);
+        let mut eg_data: Vec<u8> = vec![];
+        eg_image.write_with_encoder(JpegEncoder::new(&mut eg_data))?;
+        let img = store.create_image("cover", eg_data.deref()).await?;
+
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).ok_or(anyhow!("invalid date"))?;
+        let post = Post {
+            date,
+            slug: "my-post".to_string(),
+            title: "My post".to_string(),
+            published: true,
+            labels: vec![],
+            summary: Some("A short summary".to_string()),
+            updated: NaiveDate::from_ymd_opt(2024, 3, 4),
+            author: Some("Someone Else".to_string()),
+            cover_image: Some(format!("/images/{}", img)),
+            publish_at: Some(DateTime::parse_from_rfc3339("2024-01-02T09:00:00+02:00")?),
+        };
+        store.upsert_post(&post, "content", &ExpectedVersion::Absent).await?;
+        assert_eq!(store.get_post_raw("my-post").await?, Some((post.clone(), "content".to_string())));
+        assert_eq!(store.list_posts().await?, vec![post.clone()]);
+        store.rebuild_post_index().await?;
+        assert_eq!(store.list_posts().await?, vec![post.clone()]);
+
+        let invalid = Post {
+            cover_image: Some("/images/missing.webp".to_string()),
+            ..post.clone()
+        };
+        assert!(store.upsert_post(&invalid, "content", &ExpectedVersion::Any).await.is_err());
+        let invalid = Post {
+            updated: date.pred_opt(),
+            ..post.clone()
+        };
+        assert!(store.upsert_post(&invalid, "content", &ExpectedVersion::Any).await.is_err());
+
+        // The publish-at time keeps its offset, and is compared as an instant.
+        let publish_at = DateTime::parse_from_rfc3339("2024-01-02T07:00:00Z")?.to_utc();
+        assert!(!post.is_live(publish_at - TimeDelta::seconds(1)));
+        assert!(post.is_scheduled(publish_at - TimeDelta::seconds(1)));
+        assert!(post.is_live(publish_at));
+        assert!(!post.is_scheduled(publish_at));
+        let draft = Post {
+            published: false,
+            ..post.clone()
+        };
+        assert!(!draft.is_live(publish_at) && !draft.is_scheduled(publish_at - TimeDelta::seconds(1)));
+
+        // Props written before V2 still decode, with the new fields missing.
+        let v1 = PathPart::from(PostMetadata::V1((date, "Old".to_string(), IsPublished(true))));
+        let v1_path = Path::from_iter(["posts", "old-post", "props", v1.as_ref()]);
+        let meta = Store::props_part_from_paths([&v1_path].iter(), 0).ok_or(anyhow!("failed to decode"))?;
+        assert_eq!(
+            meta.into_post("old-post".to_string(), vec![]),
+            Post {
+                date,
+                slug: "old-post".to_string(),
+                title: "Old".to_string(),
+                published: true,
+                ..Post::default()
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_post_conflicts() -> Result<(), Error> {
+        let store = Store::default();
+        let post = Post {
+            date: NaiveDate::from_ymd_opt(2020, 1, 1).ok_or(anyhow!("invalid date"))?,
+            slug: "my-post".to_string(),
+            title: "My post".to_string(),
+            ..Post::default()
+        };
+        let is_conflict = |r: Result<UpsertedPost, Error>| r.is_err_and(|e| e.is::<ConflictError>());
+
+        let first = store.upsert_post(&post, "one", &ExpectedVersion::Absent).await?;
+        assert!(is_conflict(store.upsert_post(&post, "two", &ExpectedVersion::Absent).await));
+        let (_, _, e_tag) = store.get_post_raw_with_e_tag("my-post").await?.unwrap_or_default();
+        assert_eq!(e_tag, first.e_tag);
+
+        // The second tab was loaded from the first version, so it conflicts once the first tab has saved.
+        let first_tab = ExpectedVersion::ETag(first.e_tag.clone().unwrap_or_default());
+        let second = store.upsert_post(&post, "two", &first_tab).await?;
+        assert!(is_conflict(store.upsert_post(&post, "three", &first_tab).await));
+        assert_eq!(store.get_post_raw("my-post").await?.unwrap_or_default().1, "two");
+
+        store
+            .upsert_post(&post, "three", &ExpectedVersion::ETag(second.e_tag.unwrap_or_default()))
+            .await?;
+        store.upsert_post(&post, "four", &ExpectedVersion::Any).await?;
+        assert_eq!(store.get_post_raw("my-post").await?.unwrap_or_default().1, "four");
+        assert_eq!(store.list_revisions("my-post").await?.len(), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_post_cleanup() -> Result<(), Error> {
+        let post = Post {
+            slug: "my-post".to_string(),
+            title: "My post".to_string(),
+            ..Post::default()
+        };
+        async fn count_txs(store: &Store) -> Result<usize, Error> {
+            let objects = store.list_object_meta().await?;
+            Ok(objects.iter().filter(|m| m.location.as_ref().contains("/tx/")).count())
+        }
+
+        // Each write cleans up the transactions it superseded once they are older than the grace period.
+        let store = Store {
+            tx_grace_period: TimeDelta::zero(),
+            ..Store::default()
+        };
+        for content in ["one", "two", "three"] {
+            store.upsert_post(&post, content, &ExpectedVersion::Any).await?;
+        }
+        assert_eq!(count_txs(&store).await?, 1);
+        assert_eq!(store.get_post_raw("my-post").await?.unwrap_or_default().1, "three");
+
+        // Within the grace period they are kept, until a repair with a shorter one.
+        let store = Store::default();
+        for content in ["one", "two", "three"] {
+            store.upsert_post(&post, content, &ExpectedVersion::Any).await?;
+        }
+        assert_eq!(count_txs(&store).await?, 3);
+        store.repair_posts(TimeDelta::zero()).await?;
+        assert_eq!(count_txs(&store).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_legacy_posts() -> Result<(), Error> {
+        let store = Store::default();
+        let legacy = Post {
+            date: NaiveDate::from_ymd_opt(2020, 1, 1).ok_or(anyhow!("invalid date"))?,
+            slug: "legacy-post".to_string(),
+            title: "Legacy".to_string(),
+            published: true,
+            labels: vec!["blue".to_string()],
+            ..Post::default()
+        };
+        let post_path = Path::from("posts").child("legacy-post");
+        let meta = PostMetadata::V1((legacy.date, legacy.title.clone(), IsPublished(true)));
+        let old_meta = PostMetadata::V1((legacy.date, "Older".to_string(), IsPublished(true)));
+        store.os.put(&post_path.child("content"), PutPayload::from("legacy")).await?;
+        store
+            .os
+            .put(&post_path.child("labels").child("blue"), PutPayload::default())
+            .await?;
+        store
+            .os
+            .put(&post_path.child("props").child(PathPart::from(old_meta)), PutPayload::default())
+            .await?;
+        store
+            .os
+            .put(&post_path.child("props").child(PathPart::from(meta)), PutPayload::default())
+            .await?;
+        // An interrupted first write of another post leaves an uncommitted transaction.
+        let leftover = Path::from("posts").child("new-post").child("tx").child("1").child("content");
+        store.os.put(&leftover, PutPayload::from("never committed")).await?;
+
+        // The repair keeps the newest props of the legacy post and removes the uncommitted transaction.
+        let deleted = store.repair_posts(TimeDelta::zero()).await?;
+        assert_eq!(deleted.len(), 2);
+        assert!(deleted.contains(&leftover));
+        let (post, content, e_tag) = store.get_post_raw_with_e_tag("legacy-post").await?.unwrap_or_default();
+        assert_eq!((&post, content.as_str()), (&legacy, "legacy"));
+        assert_eq!(store.list_posts().await?, vec![legacy.clone()]);
+
+        // Editing the legacy post from its content version moves it to the committed layout.
+        store
+            .upsert_post(&legacy, "committed", &ExpectedVersion::ETag(e_tag.unwrap_or_default()))
+            .await?;
+        assert_eq!(
+            store.get_post_raw("legacy-post").await?,
+            Some((legacy.clone(), "committed".to_string()))
+        );
+        let remaining = store
+            .list_object_meta()
+            .await?
+            .into_iter()
+            .map(|m| m.location.to_string())
+            .collect_vec();
+        assert!(
+            remaining.iter().all(|p| !p.ends_with("/content") || p.contains("/tx/")),
+            "{:?}",
+            remaining
+        );
+        assert!(
+            remaining.iter().all(|p| !p.contains("/props/") && !p.contains("/labels/")),
+            "{:?}",
+            remaining
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_rename_post() -> Result<(), Error> {
+        let store = Store::default();
+        let post = |slug: &str| Post {
+            date: NaiveDate::from_ymd_opt(2020, 1, 1).unwrap_or_default(),
+            slug: slug.to_string(),
+            title: "Post".to_string(),
+            ..Post::default()
+        };
+        store.upsert_post(&post("first"), "one", &ExpectedVersion::Absent).await?;
+        store.upsert_post(&post("first"), "two", &ExpectedVersion::Any).await?;
+        store
+            .upsert_post(&post("linking"), "[first](/posts/first)", &ExpectedVersion::Absent)
+            .await?;
+
+        assert!(store.rename_post("first", "linking").await.is_err_and(|e| e.is::<ConflictError>()));
+        assert!(store.rename_post("first", "bad slug").await.is_err());
+        assert!(store.rename_post("missing", "renamed").await.is_err());
+
+        assert_eq!(store.rename_post("first", "second").await?, vec!["linking".to_string()]);
+        assert_eq!(store.get_post_raw("first").await?, None);
+        assert_eq!(store.get_post_raw("second").await?, Some((post("second"), "two".to_string())));
+        assert_eq!(store.list_revisions("second").await?.len(), 3);
+        assert_eq!(
+            store.get_post_raw("linking").await?.map(|(_, c)| c),
+            Some("[first](/posts/second)".to_string())
+        );
+        assert_eq!(
+            store.list_posts().await?.into_iter().map(|p| p.slug).sorted().collect_vec(),
+            vec!["linking", "second"]
+        );
+
+        // Renaming again keeps the redirects pointing straight at the current slug, and renaming back removes the
+        // redirect from the slug which is a post again.
+        store.rename_post("second", "third").await?;
+        let redirects = store.list_redirects().await?;
+        assert_eq!(
+            find_redirect(&redirects, "/posts/first").map(|(_, location)| location).as_deref(),
+            Some("/posts/third")
+        );
+        assert_eq!(
+            find_redirect(&redirects, "/posts/second").map(|(_, location)| location).as_deref(),
+            Some("/posts/third")
+        );
+        store.rename_post("third", "first").await?;
+        let redirects = store.list_redirects().await?;
+        assert_eq!(find_redirect(&redirects, "/posts/first"), None);
+        assert_eq!(
+            find_redirect(&redirects, "/posts/third").map(|(_, location)| location).as_deref(),
+            Some("/posts/first")
+        );
+        assert_eq!(redirects.len(), 2);
+
+        // A rename interrupted after creating the new post is finished by renaming again.
+        store
+            .upsert_post(&post("first"), "[me](/posts/first)", &ExpectedVersion::Any)
+            .await?;
+        let pending = HashSet::from(["/posts/fourth".to_string()]);
+        store
+            .upsert_post_with_links(&post("fourth"), "[me](/posts/fourth)", &ExpectedVersion::Absent, &pending)
+            .await?;
+        assert_eq!(store.rename_post("first", "fourth").await?, vec!["linking".to_string()]);
+        assert_eq!(store.get_post_raw("first").await?, None);
+        assert_eq!(
+            store.get_post_raw("linking").await?.map(|(_, c)| c),
+            Some("[first](/posts/fourth)".to_string())
+        );
+        assert_eq!(
+            find_redirect(&store.list_redirects().await?, "/posts/first")
+                .map(|(_, location)| location)
+                .as_deref(),
+            Some("/posts/fourth")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_redirects() -> Result<(), Error> {
+        let store = Store::default();
+        // Redirects written by renames before prefix and temporary redirects existed are exact and permanent.
+        let v1 = RedirectMap::V1(vec![("/posts/old".to_string(), "/posts/new".to_string())]);
+        store
+            .os
+            .put(&Path::from("redirects"), PutPayload::from(postcard::to_allocvec(&v1)?))
+            .await?;
+        assert_eq!(
+            store.list_redirects().await?,
+            vec![Redirect {
+                from: "/posts/old".to_string(),
+                to: "/posts/new".to_string(),
+                prefix: false,
+                permanent: true,
+            }]
+        );
+
+        let prefix = Redirect {
+            from: "/blog/".to_string(),
+            to: "/posts/".to_string(),
+            prefix: true,
+            permanent: false,
+        };
+        let exact = Redirect {
+            from: "/blog/special".to_string(),
+            to: "https://example.com".to_string(),
+            ..Redirect::default()
+        };
+        store.upsert_redirect(&prefix).await?;
+        store.upsert_redirect(&exact).await?;
+        let redirects = store.list_redirects().await?;
+        assert_eq!(redirects.len(), 3);
+        assert_eq!(
+            find_redirect(&redirects, "/blog/some-post"),
+            Some((&prefix, "/posts/some-post".to_string()))
+        );
+        assert_eq!(
+            find_redirect(&redirects, "/blog/special"),
+            Some((&exact, "https://example.com".to_string()))
+        );
+        assert_eq!(find_redirect(&redirects, "/blog"), None);
+
+        // Upserting the same source replaces it, and invalid redirects are rejected.
+        store
+            .upsert_redirect(&Redirect {
+                permanent: true,
+                ..prefix.clone()
+            })
+            .await?;
+        assert_eq!(store.list_redirects().await?.len(), 3);
+        for invalid in [("blog", "/posts/"), ("/blog/", "posts"), ("/loop", "/loop"), ("/a b", "/c")] {
+            let r = Redirect {
+                from: invalid.0.to_string(),
+                to: invalid.1.to_string(),
+                ..Redirect::default()
+            };
+            assert!(store.upsert_redirect(&r).await.is_err(), "{:?}", r);
+        }
+
+        // Neither a loop nor a chain through an existing redirect is accepted, in either order.
+        let redirect = |from: &str, to: &str| Redirect {
+            from: from.to_string(),
+            to: to.to_string(),
+            ..Redirect::default()
+        };
+        store.upsert_redirect(&redirect("/a", "/b")).await?;
+        assert!(store.upsert_redirect(&redirect("/b", "/a")).await.is_err());
+        assert!(store.upsert_redirect(&redirect("/b", "/c")).await.is_err());
+        assert!(store.upsert_redirect(&redirect("/c", "/a")).await.is_err());
+        // Replacing a redirect is checked against the others only.
+        store.upsert_redirect(&redirect("/a", "/c")).await?;
+        assert!(store.delete_redirect("/a").await?);
+
+        assert!(store.delete_redirect("/blog/").await?);
+        assert!(!store.delete_redirect("/blog/").await?);
+        assert_eq!(store.list_redirects().await?.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_migrate() -> Result<(), Error> {
+        let store = Store::default();
+        let date = NaiveDate::from_ymd_opt(2020, 1, 1).ok_or(anyhow!("invalid date"))?;
+        let legacy_path = Path::from("posts").child("legacy-post");
+        let meta = PostMetadata::V1((date, "Legacy".to_string(), IsPublished(true)));
+        // Old content may no longer pass validation, which must not stop the migration.
+        store
+            .os
+            .put(&legacy_path.child("content"), PutPayload::from("[broken](/posts/missing)"))
+            .await?;
+        store
+            .os
+            .put(
+                &legacy_path.child("props").child(PathPart::from(meta.clone())),
+                PutPayload::default(),
+            )
+            .await?;
+        let v1_path = Path::from("posts").child("v1-post");
+        store
+            .os
+            .put(&v1_path.child("tx").child("1").child("content"), PutPayload::from("v1"))
+            .await?;
+        let commit = Commit::V1(("1".to_string(), meta, vec!["blue".to_string()]));
+        store
+            .os
+            .put(&v1_path.child("commit"), PutPayload::from(postcard::to_allocvec(&commit)?))
+            .await?;
+        let before = store.list_object_meta().await?.len();
+
+        assert_eq!(store.get_schema_version().await?, None);
+        let planned = store.migrate(true).await?;
+        assert_eq!(planned.len(), 5, "{:?}", planned);
+        assert_eq!(store.list_object_meta().await?.len(), before);
+
+        assert_eq!(store.migrate(false).await?, planned);
+        assert_eq!(store.get_schema_version().await?, Some(Store::SCHEMA_VERSION));
+        assert!(store.is_post_index_current().await?);
+        let (post, content) = store.get_post_raw("legacy-post").await?.unwrap_or_default();
+        assert_eq!((post.title.as_str(), content.as_str()), ("Legacy", "[broken](/posts/missing)"));
+        let (post, content) = store.get_post_raw("v1-post").await?.unwrap_or_default();
+        assert_eq!((post.labels, content.as_str()), (vec!["blue".to_string()], "v1"));
+        assert!(matches!(
+            store.get_post_commit(&legacy_path).await?,
+            Some((Commit::V1((_, PostMetadata::V3(_), _)), _))
+        ));
+
+        // Running it again has nothing left to do.
+        assert_eq!(store.migrate(false).await?, Vec::<String>::new());
+        store.check_schema_version().await?;
+
+        // A store migrated by a newer binary is refused.
+        store
+            .os
+            .put(
+                &Path::from("schema"),
+                PutPayload::from(postcard::to_allocvec(&SchemaMarker::V1(99))?),
+            )
+            .await?;
+        assert!(store.check_schema_version().await.is_err());
+        assert!(store.migrate(true).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_store_post_index() -> Result<(), Error> {
+        let store = Store::default();
+        let mut posts = vec![];
+        for slug in ["first-post", "second-post"] {
+            let post = Post {
+                date: NaiveDate::from_ymd_opt(2020, 1, 1).ok_or(anyhow!("invalid date"))?,
+                slug: slug.to_string(),
+                title: slug.to_string(),
+                published: true,
+                labels: vec!["blue".to_string()],
+                ..Post::default()
+            };
+            store.upsert_post(&post, "content", &ExpectedVersion::Absent).await?;
+            posts.push(post);
+        }
+        assert_eq!(store.list_posts().await?, posts);
+        assert!(store
+            .list_posts_with_versions()
+            .await?
+            .iter()
+            .all(|(_, v)| v.last_modified.is_some() && v.e_tag.is_some()));
+        assert_eq!(store.ensure_post_index().await?, None);
+
+        store.delete_post("first-post").await?;
+        assert_eq!(store.list_posts().await?, posts[1..].to_vec());
 
+        // Losing the index falls back to listing, and the index can be rebuilt from the post objects.
+        store.os.delete(&store.post_index_path()).await?;
+        assert_eq!(store.list_posts().await?, posts[1..].to_vec());
+        assert_eq!(store.ensure_post_index().await?, Some(1));
+        assert_eq!(store.list_posts().await?, posts[1..].to_vec());
         Ok(())
     }
 
@@ -771,6 +3674,7 @@ mod tests {
                     ..Post::default()
                 },
                 "my-content",
+                &ExpectedVersion::Any,
             )
             .await?;
 
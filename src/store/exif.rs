@@ -0,0 +1,92 @@
+use chrono::NaiveDateTime;
+
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TYPE_ASCII: u16 = 2;
+const TYPE_LONG: u16 = 4;
+
+/// A raw EXIF chunk, which is laid out as a TIFF file of image file directories (IFDs) holding tagged entries.
+struct Tiff<'a> {
+    raw: &'a [u8],
+    little_endian: bool,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.raw.get(offset..offset.checked_add(2)?)?.try_into().ok()?;
+        match self.little_endian {
+            true => Some(u16::from_le_bytes(bytes)),
+            false => Some(u16::from_be_bytes(bytes)),
+        }
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.raw.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+        match self.little_endian {
+            true => Some(u32::from_le_bytes(bytes)),
+            false => Some(u32::from_be_bytes(bytes)),
+        }
+    }
+
+    /// Returns the type, count, and value or offset of the entry with the given tag in the IFD at the offset.
+    fn entry(&self, ifd: usize, tag: u16) -> Option<(u16, u32, u32)> {
+        let count = self.u16(ifd)? as usize;
+        let entry = (0..count).map(|i| ifd + 2 + i * 12).find(|e| self.u16(*e) == Some(tag))?;
+        Some((self.u16(entry + 2)?, self.u32(entry + 4)?, self.u32(entry + 8)?))
+    }
+
+    /// Returns the string of an entry, which is stored at an offset unless it fits within the 4 byte value.
+    fn ascii(&self, ifd: usize, tag: u16) -> Option<&str> {
+        let (_, count, offset) = self.entry(ifd, tag).filter(|(kind, count, _)| *kind == TYPE_ASCII && *count > 4)?;
+        let start = offset as usize;
+        let bytes = self.raw.get(start..start.checked_add(count as usize)?)?;
+        std::str::from_utf8(bytes).ok().map(|s| s.trim_end_matches('\0'))
+    }
+}
+
+/// Returns the time at which a photo was taken from its raw EXIF chunk, in the local time of the camera since the
+/// offset is rarely recorded.
+pub(super) fn capture_time(chunk: &[u8]) -> Option<NaiveDateTime> {
+    let little_endian = match chunk.get(..4)? {
+        [0x49, 0x49, 42, 0] => true,
+        [0x4d, 0x4d, 0, 42] => false,
+        _ => return None,
+    };
+    let tiff = Tiff { raw: chunk, little_endian };
+    let ifd0 = tiff.u32(4)? as usize;
+    let (_, _, exif_ifd) = tiff.entry(ifd0, EXIF_IFD_POINTER).filter(|(kind, ..)| *kind == TYPE_LONG)?;
+    let raw = tiff.ascii(exif_ifd as usize, DATE_TIME_ORIGINAL)?;
+    NaiveDateTime::parse_from_str(raw, "%Y:%m:%d %H:%M:%S").ok()
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    /// Returns a little endian EXIF chunk with the given orientation, taken at 2024-05-06 07:08:09.
+    pub(in crate::store) fn example_chunk(orientation: u16) -> Vec<u8> {
+        let mut raw = vec![0x49, 0x49, 42, 0, 8, 0, 0, 0];
+        // IFD0 at 8 with the orientation and the pointer to the EXIF IFD at 38.
+        raw.extend(2u16.to_le_bytes());
+        raw.extend([0x12, 0x01, 3, 0, 1, 0, 0, 0]);
+        raw.extend([orientation.to_le_bytes(), [0, 0]].concat());
+        raw.extend([0x69, 0x87, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+        raw.extend([0, 0, 0, 0]);
+        // The EXIF IFD at 38 with the date time original at 56.
+        raw.extend(1u16.to_le_bytes());
+        raw.extend([0x03, 0x90, 2, 0, 20, 0, 0, 0, 56, 0, 0, 0]);
+        raw.extend([0, 0, 0, 0]);
+        raw.extend(b"2024:05:06 07:08:09\0");
+        raw
+    }
+
+    #[test]
+    fn test_capture_time() {
+        let expected = NaiveDate::from_ymd_opt(2024, 5, 6).and_then(|d| d.and_hms_opt(7, 8, 9));
+        assert_eq!(capture_time(&example_chunk(1)), expected);
+        assert_eq!(capture_time(&example_chunk(1)[..60]), None);
+        assert_eq!(capture_time(&example_chunk(1)[..8]), None);
+        assert_eq!(capture_time(b"not exif"), None);
+    }
+}
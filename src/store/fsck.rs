@@ -0,0 +1,313 @@
+use super::mirror::MIRROR_STATE_NAME;
+use super::{ExpectedVersion, Image, PostMetadata, Store, IMAGE_INDEX_NAME, IMAGE_METADATA_NAME};
+use anyhow::Error;
+use itertools::Itertools;
+use object_store::path::{Path, PathPart};
+use object_store::ObjectMeta;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use tracing::{info_span, instrument, Instrument};
+
+/// The kinds of inconsistency found by [Store::fsck].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FsckProblemKind {
+    /// A legacy post has content but no props, so it is listed with a default title and date.
+    MissingProps,
+    /// A props object can't be decoded, so it is ignored.
+    UndecodableProps,
+    /// A commit object can't be decoded, so the post can't be read.
+    UndecodableCommit,
+    /// A label object which readers ignore, because the post has been committed or has no content.
+    StrayLabel,
+    /// A legacy content or props object which readers ignore, because the post has been committed or has no content.
+    StrayPostObject,
+    /// An image variant without its original, which is not listed as an image.
+    OrphanImageVariant,
+    /// An object which is not part of the layout of the store.
+    UnknownPath,
+}
+
+impl FsckProblemKind {
+    /// Whether the repair mode of [Store::fsck] can fix the problem. Unknown paths and commits are left alone since they
+    /// may hold something worth keeping.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, FsckProblemKind::UndecodableCommit | FsckProblemKind::UnknownPath)
+    }
+}
+
+impl Display for FsckProblemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            FsckProblemKind::MissingProps => "post has no props",
+            FsckProblemKind::UndecodableProps => "undecodable props",
+            FsckProblemKind::UndecodableCommit => "undecodable commit",
+            FsckProblemKind::StrayLabel => "stray label",
+            FsckProblemKind::StrayPostObject => "stray post object",
+            FsckProblemKind::OrphanImageVariant => "orphan image variant",
+            FsckProblemKind::UnknownPath => "unknown path",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A problem found by [Store::fsck]. The path is relative to the sub path of the store.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FsckProblem {
+    pub path: String,
+    pub kind: FsckProblemKind,
+    pub repaired: bool,
+}
+
+/// How a problem is repaired.
+enum Repair {
+    Delete(Path),
+    /// Commit the legacy post as an unpublished draft so that it gets valid metadata, which also removes the legacy
+    /// objects. The etag is that of the legacy content.
+    CommitDraft {
+        slug: String,
+        e_tag: Option<String>,
+    },
+}
+
+impl Store {
+    /// Check the store for objects which readers ignore or misread, and optionally repair them. Uncommitted
+    /// transactions are not reported since they may belong to a write in progress; see [Store::repair_posts].
+    #[instrument(skip_all, err)]
+    pub async fn fsck(&self, repair: bool) -> Result<Vec<FsckProblem>, Error> {
+        let objects = self.list_object_meta().await?;
+        let mut found: Vec<(FsckProblem, Option<Repair>)> = vec![];
+        let mut posts: BTreeMap<String, Vec<&ObjectMeta>> = BTreeMap::new();
+        let mut images: BTreeMap<String, Vec<&ObjectMeta>> = BTreeMap::new();
+        for meta in objects.iter() {
+            let parts = meta.location.parts().collect_vec();
+            match parts.iter().map(|p| p.as_ref()).collect_vec().as_slice() {
+                ["posts", slug, _, ..] => posts.entry(slug.to_string()).or_default().push(meta),
+                ["images", dir, _] => images.entry(dir.to_string()).or_default().push(meta),
+                ["index"] | [IMAGE_INDEX_NAME] | ["redirects"] | ["schema"] | [MIRROR_STATE_NAME] => {}
+                _ => found.push((problem(meta, FsckProblemKind::UnknownPath), None)),
+            }
+        }
+
+        for (slug, metas) in posts.iter() {
+            found.extend(self.check_post(slug, metas).await?);
+        }
+
+        for (dir, metas) in images.iter() {
+            let original = Image::try_from_path_part(PathPart::from(dir.as_str()))
+                .ok()
+                .filter(|i| i.to_original() == *i);
+            let has_original = metas.iter().any(|m| m.location.filename() == Some(dir.as_str()));
+            for meta in metas {
+                // The metadata object belongs to the original in the same way that the variants do.
+                let image = meta.location.filename().and_then(|f| match f {
+                    IMAGE_METADATA_NAME => original.clone(),
+                    _ => Image::try_from_path_part(PathPart::from(f)).ok(),
+                });
+                match (&original, image) {
+                    (Some(original), Some(image)) if image.to_original() == *original => {
+                        if !has_original {
+                            let repair = Repair::Delete(self.full_path(&meta.location));
+                            found.push((problem(meta, FsckProblemKind::OrphanImageVariant), Some(repair)));
+                        }
+                    }
+                    _ => found.push((problem(meta, FsckProblemKind::UnknownPath), None)),
+                }
+            }
+        }
+
+        let mut problems = vec![];
+        let mut posts_changed = false;
+        let mut images_changed = false;
+        for (mut problem, fix) in found {
+            if let (true, Some(fix)) = (repair, fix) {
+                match fix {
+                    Repair::Delete(path) => {
+                        self.os.delete(&path).instrument(info_span!("delete")).await?;
+                    }
+                    Repair::CommitDraft { slug, e_tag } => {
+                        self.commit_draft(slug.as_str(), e_tag).await?;
+                    }
+                }
+                posts_changed |= problem.path.starts_with("posts/");
+                images_changed |= problem.path.starts_with("images/");
+                problem.repaired = true;
+            }
+            problems.push(problem);
+        }
+        if posts_changed {
+            self.rebuild_post_index().await?;
+        }
+        if images_changed {
+            self.rebuild_image_index().await?;
+        }
+        problems.sort();
+        Ok(problems)
+    }
+
+    async fn check_post(&self, slug: &str, metas: &[&ObjectMeta]) -> Result<Vec<(FsckProblem, Option<Repair>)>, Error> {
+        let post_path = self.sub_path.child("posts").child(slug);
+        let section = |m: &ObjectMeta| m.location.parts().nth(2).map(|p| p.as_ref().to_string()).unwrap_or_default();
+        let has_commit = metas.iter().any(|m| section(m) == "commit");
+        let content = metas.iter().find(|m| section(m) == "content" && m.location.parts().count() == 3);
+        // Legacy objects are only read when there is content and no commit.
+        let is_legacy = !has_commit && content.is_some();
+        let mut found = vec![];
+        let mut decodable_props = 0;
+        let mut undecodable_props = vec![];
+
+        for meta in metas {
+            let depth = meta.location.parts().count();
+            let delete = || Some(Repair::Delete(self.full_path(&meta.location)));
+            match (section(meta).as_str(), depth) {
+                ("commit", 3) => {
+                    if self.get_post_commit(&post_path).await.is_err() {
+                        found.push((problem(meta, FsckProblemKind::UndecodableCommit), None));
+                    }
+                }
+                ("tx", 5) if meta.location.filename() == Some("content") => {}
+                ("revisions", 4) => {}
+                ("content", 3) if !is_legacy => found.push((problem(meta, FsckProblemKind::StrayPostObject), delete())),
+                ("content", 3) => {}
+                ("props", 4) if !is_legacy => found.push((problem(meta, FsckProblemKind::StrayPostObject), delete())),
+                ("props", 4) => match meta.location.filename().map(|f| PostMetadata::try_from(PathPart::from(f))) {
+                    Some(Ok(_)) => decodable_props += 1,
+                    _ => undecodable_props.push(*meta),
+                },
+                ("labels", 4) if !is_legacy => found.push((problem(meta, FsckProblemKind::StrayLabel), delete())),
+                ("labels", 4) => {}
+                _ => found.push((problem(meta, FsckProblemKind::UnknownPath), None)),
+            }
+        }
+
+        if let Some(content) = content.filter(|_| is_legacy) {
+            let commit_draft = || {
+                Some(Repair::CommitDraft {
+                    slug: slug.to_string(),
+                    e_tag: content.e_tag.clone(),
+                })
+            };
+            if decodable_props > 0 {
+                for meta in undecodable_props {
+                    found.push((
+                        problem(meta, FsckProblemKind::UndecodableProps),
+                        Some(Repair::Delete(self.full_path(&meta.location))),
+                    ));
+                }
+            } else if undecodable_props.is_empty() {
+                let missing = FsckProblem {
+                    path: format!("posts/{}/props", slug),
+                    kind: FsckProblemKind::MissingProps,
+                    repaired: false,
+                };
+                found.push((missing, commit_draft()));
+            } else {
+                // Committing the post replaces all of its props, so only the first needs the repair.
+                for (i, meta) in undecodable_props.into_iter().enumerate() {
+                    let repair = if i == 0 { commit_draft() } else { None };
+                    found.push((problem(meta, FsckProblemKind::UndecodableProps), repair));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Commit a legacy post whose props are missing or broken as an unpublished draft, keeping its content and labels.
+    /// The slug stands in for the title, and the date is taken from the content object.
+    async fn commit_draft(&self, slug: &str, e_tag: Option<String>) -> Result<(), Error> {
+        let post_path = self.sub_path.child("posts").child(slug);
+        let Some((mut post, content)) = self.get_post_raw(slug).await? else {
+            return Ok(());
+        };
+        if let Some(meta) = self.head_object(&post_path.child("content")).await? {
+            post.date = meta.last_modified.date_naive();
+        }
+        post.title = slug.to_string();
+        post.published = false;
+        let expected = e_tag.map(ExpectedVersion::ETag).unwrap_or(ExpectedVersion::Any);
+        self.save_post(&post, content.as_str(), &expected).await?;
+        Ok(())
+    }
+}
+
+fn problem(meta: &ObjectMeta, kind: FsckProblemKind) -> FsckProblem {
+    FsckProblem {
+        path: meta.location.to_string(),
+        kind,
+        repaired: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{IsPublished, Post};
+    use chrono::NaiveDate;
+    use object_store::PutPayload;
+
+    #[tokio::test]
+    async fn test_store_fsck() -> Result<(), Error> {
+        let store = Store::default();
+        let post = Post {
+            date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap_or_default(),
+            slug: "good-post".to_string(),
+            title: "Good".to_string(),
+            published: true,
+            ..Post::default()
+        };
+        store.upsert_post(&post, "good", &ExpectedVersion::Absent).await?;
+        store
+            .create_image("photo", br#"<svg xmlns="http://www.w3.org/2000/svg"></svg>"#)
+            .await?;
+        assert_eq!(store.fsck(false).await?, vec![]);
+
+        let put = |path: &str| {
+            let path = Path::parse(path).unwrap_or_default();
+            let os = &store.os;
+            async move { os.put(&path, PutPayload::from("x")).await }
+        };
+        put("posts/good-post/labels/stray").await?;
+        put("posts/no-props/content").await?;
+        put("posts/no-props/labels/blue").await?;
+        put("posts/bad-props/content").await?;
+        put("posts/bad-props/props/AAAA").await?;
+        let meta = PathPart::from(PostMetadata::V1((post.date, "Old".to_string(), IsPublished(true))));
+        put(format!("posts/old-post/props/{}", meta.as_ref()).as_str()).await?;
+        put("images/gone.webp/gone.thumb.jpg").await?;
+        put("images/gone.webp/meta").await?;
+        put("images/photo.svg/other.thumb.jpg").await?;
+        put("random").await?;
+
+        let found = store.fsck(false).await?;
+        let kinds = found.iter().map(|p| (p.path.as_str(), p.kind, p.repaired)).collect_vec();
+        assert_eq!(
+            kinds,
+            vec![
+                ("images/gone.webp/gone.thumb.jpg", FsckProblemKind::OrphanImageVariant, false),
+                ("images/gone.webp/meta", FsckProblemKind::OrphanImageVariant, false),
+                ("images/photo.svg/other.thumb.jpg", FsckProblemKind::UnknownPath, false),
+                ("posts/bad-props/props/AAAA", FsckProblemKind::UndecodableProps, false),
+                ("posts/good-post/labels/stray", FsckProblemKind::StrayLabel, false),
+                ("posts/no-props/props", FsckProblemKind::MissingProps, false),
+                (
+                    format!("posts/old-post/props/{}", meta.as_ref()).as_str(),
+                    FsckProblemKind::StrayPostObject,
+                    false
+                ),
+                ("random", FsckProblemKind::UnknownPath, false),
+            ]
+        );
+
+        // Repairing fixes everything except the unknown paths, and the legacy posts become drafts.
+        let repaired = store.fsck(true).await?;
+        assert_eq!(repaired.iter().filter(|p| p.repaired).count(), 6);
+        assert!(repaired.iter().all(|p| p.repaired == p.kind.is_repairable()));
+        let remaining = store.fsck(false).await?;
+        assert!(remaining.iter().all(|p| p.kind == FsckProblemKind::UnknownPath), "{:?}", remaining);
+        let (draft, content) = store.get_post_raw("no-props").await?.unwrap_or_default();
+        assert_eq!((draft.title.as_str(), draft.published, content.as_str()), ("no-props", false, "x"));
+        assert_eq!(draft.labels, vec!["blue".to_string()]);
+        assert!(draft.date > NaiveDate::default());
+        assert_eq!(store.list_posts().await?.len(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use super::{Store, IMAGE_INDEX_NAME};
+use anyhow::Error;
+use futures::{StreamExt, TryFutureExt, TryStreamExt};
+use itertools::Itertools;
+use object_store::path::Path;
+use object_store::{ObjectMeta, PutPayload};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use tracing::{info_span, instrument, Instrument};
+
+/// Options for [Store::mirror_to].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorOptions {
+    /// Delete the objects in the target which are not in the source.
+    pub delete: bool,
+    /// Compare the checksums of the objects in both stores instead of copying anything.
+    pub verify: bool,
+    /// The number of objects to copy or compare at once.
+    pub concurrency: usize,
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        Self {
+            delete: false,
+            verify: false,
+            concurrency: 8,
+        }
+    }
+}
+
+/// The outcome of [Store::mirror_to]. The paths are relative to the sub path of each store.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MirrorSummary {
+    pub copied: usize,
+    pub copied_bytes: u64,
+    /// Objects which were already up to date, or matched when verifying.
+    pub unchanged: usize,
+    pub deleted: usize,
+    /// Objects which are only in the target, and were not deleted.
+    pub extra: Vec<String>,
+    /// Objects which are missing from the target when verifying.
+    pub missing: Vec<String>,
+    /// Objects whose content differs between the stores when verifying.
+    pub mismatched: Vec<String>,
+}
+
+/// Records the etag of each object in the source when it was last copied, along with the etag the copy was given by the
+/// target. Etags are not comparable between providers, so this is how unchanged objects are found on the next run.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+enum MirrorState {
+    V1(BTreeMap<String, MirroredObject>),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct MirroredObject {
+    source_e_tag: String,
+    target_e_tag: Option<String>,
+}
+
+/// The name of the mirror state object, which is kept in the target and is never copied or deleted.
+pub(super) const MIRROR_STATE_NAME: &str = "mirror";
+
+impl Store {
+    fn mirror_state_path(&self) -> Path {
+        self.sub_path.child(MIRROR_STATE_NAME)
+    }
+
+    pub(super) fn full_path(&self, relative: &Path) -> Path {
+        self.sub_path.parts().chain(relative.parts()).collect()
+    }
+
+    /// Copy the objects in this store into the target so that it becomes a replica. Objects are only copied if they are
+    /// missing from the target, their size differs, or their etag has changed since the last mirror. The commit and
+    /// index objects are copied after everything else, so that readers of the target never see a commit before the
+    /// content it refers to.
+    #[instrument(skip_all, err)]
+    pub async fn mirror_to(&self, target: &Store, opts: &MirrorOptions) -> Result<MirrorSummary, Error> {
+        let state_path = Path::from(MIRROR_STATE_NAME);
+        let sources = self
+            .list_object_meta()
+            .await?
+            .into_iter()
+            .filter(|m| m.location != state_path)
+            .collect::<Vec<ObjectMeta>>();
+        let mut targets = target
+            .list_object_meta()
+            .await?
+            .into_iter()
+            .filter(|m| m.location != state_path)
+            .map(|m| (m.location.to_string(), m))
+            .collect::<HashMap<String, ObjectMeta>>();
+
+        let mut summary = MirrorSummary::default();
+        if opts.verify {
+            let results = futures::stream::iter(sources.iter())
+                .map(|source| {
+                    let is_missing = !targets.contains_key(source.location.as_ref());
+                    async move {
+                        match is_missing {
+                            true => Ok::<_, Error>((source.location.to_string(), None)),
+                            false => {
+                                let matches = self.checksum(&source.location).await? == target.checksum(&source.location).await?;
+                                Ok((source.location.to_string(), Some(matches)))
+                            }
+                        }
+                    }
+                })
+                .buffer_unordered(opts.concurrency.max(1))
+                .try_collect::<Vec<_>>()
+                .await?;
+            for (location, result) in results {
+                targets.remove(&location);
+                match result {
+                    None => summary.missing.push(location),
+                    Some(true) => summary.unchanged += 1,
+                    Some(false) => summary.mismatched.push(location),
+                }
+            }
+            summary.missing.sort();
+            summary.mismatched.sort();
+            summary.extra = targets.into_keys().sorted().collect_vec();
+            return Ok(summary);
+        }
+
+        let MirrorState::V1(previous) = target.get_mirror_state().await?;
+        let mut state = BTreeMap::new();
+        let (pointers, data): (Vec<&ObjectMeta>, Vec<&ObjectMeta>) = sources.iter().partition(|m| is_pointer(&m.location));
+        for phase in [data, pointers] {
+            let mut to_copy = vec![];
+            for source in phase {
+                let location = source.location.to_string();
+                let target_meta = targets.remove(&location);
+                let mirrored = previous.get(&location);
+                if is_unchanged(source, target_meta.as_ref(), mirrored) {
+                    summary.unchanged += 1;
+                    if let Some(mirrored) = mirrored {
+                        state.insert(location, mirrored.clone());
+                    }
+                } else {
+                    to_copy.push(source);
+                }
+            }
+            let copied = futures::stream::iter(to_copy)
+                .map(|source| async move {
+                    let (size, target_e_tag) = self.copy_object(target, &source.location).await?;
+                    Ok::<_, Error>((source, size, target_e_tag))
+                })
+                .buffer_unordered(opts.concurrency.max(1))
+                .try_collect::<Vec<_>>()
+                .await?;
+            for (source, size, target_e_tag) in copied {
+                summary.copied += 1;
+                summary.copied_bytes += size;
+                if let Some(source_e_tag) = source.e_tag.clone() {
+                    state.insert(
+                        source.location.to_string(),
+                        MirroredObject {
+                            source_e_tag,
+                            target_e_tag,
+                        },
+                    );
+                }
+            }
+        }
+
+        let extra = targets.into_values().sorted_by(|a, b| a.location.cmp(&b.location)).collect_vec();
+        if opts.delete {
+            for meta in extra.iter() {
+                target
+                    .os
+                    .delete(&target.full_path(&meta.location))
+                    .instrument(info_span!("delete"))
+                    .await?;
+                summary.deleted += 1;
+            }
+        } else {
+            summary.extra = extra.into_iter().map(|m| m.location.to_string()).collect();
+        }
+        target.put_mirror_state(MirrorState::V1(state)).await?;
+        Ok(summary)
+    }
+
+    async fn copy_object(&self, target: &Store, location: &Path) -> Result<(u64, Option<String>), Error> {
+        let raw = self
+            .os
+            .get(&self.full_path(location))
+            .and_then(|gr| gr.bytes())
+            .instrument(info_span!("get"))
+            .await?;
+        let size = raw.len() as u64;
+        let put = target
+            .os
+            .put(&target.full_path(location), PutPayload::from(raw))
+            .instrument(info_span!("put"))
+            .await?;
+        Ok((size, put.e_tag))
+    }
+
+    async fn checksum(&self, location: &Path) -> Result<Vec<u8>, Error> {
+        let mut stream = self
+            .os
+            .get(&self.full_path(location))
+            .instrument(info_span!("get"))
+            .await?
+            .into_stream();
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = stream.try_next().await? {
+            hasher.update(&chunk);
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+
+    async fn get_mirror_state(&self) -> Result<MirrorState, Error> {
+        match self
+            .os
+            .get(&self.mirror_state_path())
+            .and_then(|gr| gr.bytes())
+            .instrument(info_span!("get"))
+            .await
+        {
+            // A state which can't be decoded only means that everything is copied again.
+            Ok(raw) => Ok(postcard::from_bytes(&raw).unwrap_or(MirrorState::V1(BTreeMap::new()))),
+            Err(object_store::Error::NotFound { .. }) => Ok(MirrorState::V1(BTreeMap::new())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn put_mirror_state(&self, state: MirrorState) -> Result<(), Error> {
+        self.os
+            .put(&self.mirror_state_path(), PutPayload::from(postcard::to_allocvec(&state)?))
+            .instrument(info_span!("put"))
+            .await?;
+        Ok(())
+    }
+}
+
+/// Whether the object makes other objects visible to readers, and so must be copied after them.
+fn is_pointer(location: &Path) -> bool {
+    matches!(location.filename(), Some("commit")) || ["index", IMAGE_INDEX_NAME, "redirects", "schema"].contains(&location.as_ref())
+}
+
+fn is_unchanged(source: &ObjectMeta, target: Option<&ObjectMeta>, mirrored: Option<&MirroredObject>) -> bool {
+    match (target, mirrored, source.e_tag.as_ref()) {
+        (Some(target), Some(mirrored), Some(source_e_tag)) => {
+            target.size == source.size && mirrored.source_e_tag == *source_e_tag && mirrored.target_e_tag == target.e_tag
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{ExpectedVersion, Post};
+    use chrono::NaiveDate;
+
+    fn post(slug: &str) -> Post {
+        Post {
+            date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap_or_default(),
+            slug: slug.to_string(),
+            title: "Post".to_string(),
+            published: true,
+            ..Post::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_mirror() -> Result<(), Error> {
+        let source = Store::default();
+        let target = Store::default();
+        source.upsert_post(&post("first-post"), "first", &ExpectedVersion::Absent).await?;
+        source.upsert_post(&post("second-post"), "second", &ExpectedVersion::Absent).await?;
+        let objects = source.list_object_meta().await?.len();
+
+        let summary = source.mirror_to(&target, &MirrorOptions::default()).await?;
+        assert_eq!((summary.copied, summary.unchanged), (objects, 0));
+        assert_eq!(target.get_post_raw("first-post").await?, source.get_post_raw("first-post").await?);
+
+        // Nothing is copied again until an object changes.
+        let summary = source.mirror_to(&target, &MirrorOptions::default()).await?;
+        assert_eq!((summary.copied, summary.unchanged), (0, objects));
+        source.upsert_post(&post("first-post"), "changed", &ExpectedVersion::Any).await?;
+        let summary = source.mirror_to(&target, &MirrorOptions::default()).await?;
+        assert!(summary.copied > 0 && summary.unchanged > 0, "{:?}", summary);
+        assert_eq!(
+            target.get_post_raw("first-post").await?.map(|(_, c)| c),
+            Some("changed".to_string())
+        );
+
+        // Extra objects in the target are only deleted when asked to.
+        target.os.put(&Path::from("extra"), PutPayload::from("x")).await?;
+        let summary = source.mirror_to(&target, &MirrorOptions::default()).await?;
+        assert_eq!(summary.extra, vec!["extra".to_string()]);
+        let verified = source
+            .mirror_to(
+                &target,
+                &MirrorOptions {
+                    verify: true,
+                    ..MirrorOptions::default()
+                },
+            )
+            .await?;
+        assert_eq!((verified.extra.len(), verified.missing.len(), verified.mismatched.len()), (1, 0, 0));
+        let summary = source
+            .mirror_to(
+                &target,
+                &MirrorOptions {
+                    delete: true,
+                    ..MirrorOptions::default()
+                },
+            )
+            .await?;
+        assert_eq!((summary.deleted, summary.extra.len()), (1, 0));
+
+        // Verifying compares the content, and does not copy anything.
+        let commit = Path::from("posts/second-post/commit");
+        let original = target.os.get(&commit).and_then(|gr| gr.bytes()).await?;
+        let mut corrupt = original.to_vec();
+        corrupt[0] ^= 0xff;
+        target.os.put(&commit, PutPayload::from(corrupt)).await?;
+        target.os.delete(&Path::from("index")).await?;
+        let verified = source
+            .mirror_to(
+                &target,
+                &MirrorOptions {
+                    verify: true,
+                    ..MirrorOptions::default()
+                },
+            )
+            .await?;
+        assert_eq!(verified.mismatched, vec![commit.to_string()]);
+        assert_eq!(verified.missing, vec!["index".to_string()]);
+        assert_eq!(verified.copied, 0);
+        Ok(())
+    }
+}
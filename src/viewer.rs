@@ -1,7 +1,11 @@
+mod cache;
+mod feeds;
+mod search;
 mod views;
 
 use crate::conversion::convert;
 use crate::htmx::HtmxContext;
+use crate::signing::{preview_purpose, SigningKey};
 use crate::statics::{get_favicon_ico_handler, get_static_handler};
 use crate::store::{Image, Store};
 use crate::{conversion, customhttptrace, statics};
@@ -9,30 +13,92 @@ use axum::extract::{Path, Query, State};
 use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
-use axum::Router;
-use chrono::Datelike;
+use axum::{Extension, Router};
+use cache::{RenderedPost, ViewerCache};
+use chrono::{Datelike, Utc};
+use feeds::{FeedEntry, FeedMeta, SitemapEntry};
 use itertools::Itertools;
-use log::info;
+use log::{info, warn};
 use maud::PreEscaped;
 use object_store::path::PathPart;
-use std::collections::{HashMap, HashSet};
+use search::{SearchIndex, SearchQuery};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tower_http::trace::TraceLayer;
 use tracing::instrument;
+use url::Url;
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone)]
+pub const DEFAULT_TITLE: &str = "Ben's Blog";
+pub const DEFAULT_AUTHOR: &str = "Ben Meier";
+pub const DEFAULT_DESCRIPTION: &str = "Ben's blog on distributed systems, security, networking, and programming.";
+
+#[derive(Debug, Clone)]
 pub struct Config {
     pub port: u16,
+    pub preview_key: Option<SigningKey>,
+    pub base_url: Option<Url>,
+    /// How often the cache and search index check the store for changed posts.
+    pub refresh_interval: Duration,
+    /// The title of the blog, used for the index page and the feeds.
+    pub title: String,
+    /// The author of any post which does not name its own.
+    pub author: String,
+    /// The description of the blog in the feeds.
+    pub description: String,
+}
+
+impl Config {
+    fn site(&self) -> views::Site<'_> {
+        views::Site {
+            title: self.title.as_str(),
+            author: self.author.as_str(),
+            feeds: self.base_url.is_some(),
+        }
+    }
+
+    /// The feeds are only served when the base url is configured.
+    fn feed_meta(&self) -> Option<FeedMeta<'_>> {
+        self.base_url.as_ref().map(|base| FeedMeta {
+            base,
+            title: self.title.as_str(),
+            author: self.author.as_str(),
+            description: self.description.as_str(),
+        })
+    }
 }
 
 impl Default for Config {
     fn default() -> Config {
-        Config { port: 8080 }
+        Config {
+            port: 8080,
+            preview_key: None,
+            base_url: None,
+            refresh_interval: Duration::from_secs(60),
+            title: DEFAULT_TITLE.to_string(),
+            author: DEFAULT_AUTHOR.to_string(),
+            description: DEFAULT_DESCRIPTION.to_string(),
+        }
     }
 }
 
 pub async fn run(cfg: Config, store: Store) -> Result<(), anyhow::Error> {
-    validate(&store).await?;
+    store.check_schema_version().await?;
+    let store = Arc::new(store);
+    let cache = Arc::new(ViewerCache::default());
+    let search_index = Arc::new(SearchIndex::default());
+    validate(&store, &cache, &search_index).await?;
+    tokio::spawn(refresh_loop(
+        cfg.refresh_interval,
+        store.clone(),
+        cache.clone(),
+        search_index.clone(),
+    ));
+    if cfg.base_url.is_none() {
+        info!("No base url configured, the feeds are disabled");
+    }
+    let port = cfg.port;
     let app = Router::new()
         .route("/", get(index_handler))
         .route(statics::FAVICON_ICO, get(get_favicon_ico_handler))
@@ -42,8 +108,16 @@ pub async fn run(cfg: Config, store: Store) -> Result<(), anyhow::Error> {
         .route("/livez", get(livez_handler))
         .route("/readyz", get(readyz_handler))
         .route("/robots.txt", get(robots_handler))
+        .route("/feed.xml", get(atom_feed_handler))
+        .route("/rss.xml", get(rss_feed_handler))
+        .route("/sitemap.xml", get(sitemap_handler))
+        .route("/search", get(search_handler))
+        .route("/metrics", get(metrics_handler))
         .fallback(not_found_handler)
-        .with_state(Arc::new(store))
+        .layer(Extension(Arc::new(cfg)))
+        .layer(Extension(cache))
+        .layer(Extension(search_index))
+        .with_state(store)
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(customhttptrace::HttpTraceLayerHooks)
@@ -51,27 +125,97 @@ pub async fn run(cfg: Config, store: Store) -> Result<(), anyhow::Error> {
                 .on_response(customhttptrace::HttpTraceLayerHooks)
                 .on_failure(customhttptrace::HttpTraceLayerHooks),
         );
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", cfg.port)).await?;
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
     axum::serve(listener, app).await?;
     Ok(())
 }
 
+/// Validates the conversion of every post and fills the cache and search index as we go, since all of them need the
+/// raw content of every post.
 #[instrument(skip_all, err)]
-async fn validate(store: &Store) -> Result<(), anyhow::Error> {
+async fn validate(store: &Store, cache: &ViewerCache, search_index: &SearchIndex) -> Result<(), anyhow::Error> {
     tracing::event!(tracing::Level::DEBUG, "starting post conversion validation");
-    let images = store.list_images().await?;
-    let posts = store.list_posts().await?;
-    let valid_links = conversion::build_valid_links(&posts, &images);
-    for (i, p) in posts.iter().enumerate() {
-        info!("Validating  {}/{} ({})", i + 1, posts.len(), p.slug);
-        if let Some((_, raw)) = store.get_post_raw(p.slug.as_ref()).await? {
-            convert(raw.as_ref(), &valid_links)?;
-        }
-    }
+    let started = Instant::now();
+    let rendered = refresh(store, cache, search_index, true).await?;
+    cache.metrics.record_refresh(started.elapsed(), Some(rendered));
     tracing::event!(tracing::Level::INFO, "post conversion validation complete");
     Ok(())
 }
 
+/// Fetch and render any posts which have been added or changed since the last refresh, and drop any which were
+/// deleted. When validating, the links in each post are checked too and any conversion error is returned, otherwise
+/// the previous rendering is kept. Returns the number of posts rendered.
+#[instrument(skip_all, err)]
+async fn refresh(store: &Store, cache: &ViewerCache, search_index: &SearchIndex, validating: bool) -> Result<usize, anyhow::Error> {
+    let posts = store.list_posts_with_versions().await?;
+    let search_stale = search_index.sync(&posts.iter().map(|(p, v)| (p.clone(), v.last_modified)).collect_vec())?;
+    let stale = posts
+        .iter()
+        .filter(|(p, v)| !cache.is_fresh(p.slug.as_str(), v) || search_stale.iter().any(|(sp, _)| sp.slug == p.slug))
+        .collect_vec();
+    let valid_links = if validating {
+        let images = store.list_images().await?;
+        let redirects = store.list_redirects().await?;
+        conversion::build_valid_links(&posts.iter().map(|(p, _)| p.clone()).collect_vec(), &images, &redirects)
+    } else {
+        HashSet::default()
+    };
+    let image_metadata = match stale.is_empty() {
+        true => BTreeMap::new(),
+        false => store.list_image_metadata().await?,
+    };
+    let mut rendered = Vec::with_capacity(stale.len());
+    for (i, (p, version)) in stale.iter().enumerate() {
+        if validating {
+            info!("Validating  {}/{} ({})", i + 1, stale.len(), p.slug);
+        }
+        let Some((post, raw)) = store.get_post_raw(p.slug.as_ref()).await? else {
+            continue;
+        };
+        let (content_html, toc) = match convert(raw.as_ref(), &valid_links, &image_metadata) {
+            Ok(converted) => converted,
+            Err(e) if !validating => {
+                warn!("Failed to convert post {}, keeping the previous version: {}", p.slug, e);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        search_index.upsert(post.clone(), version.last_modified, raw.as_str())?;
+        rendered.push(RenderedPost {
+            post,
+            version: version.clone(),
+            content_html: Arc::from(content_html),
+            toc: Arc::from(toc),
+        });
+    }
+    let count = rendered.len();
+    cache.apply(posts, rendered);
+    cache.set_redirects(store.list_redirects().await?);
+    Ok(count)
+}
+
+async fn refresh_loop(period: Duration, store: Arc<Store>, cache: Arc<ViewerCache>, search_index: Arc<SearchIndex>) {
+    let mut interval = tokio::time::interval(period);
+    // The first tick completes immediately, and we've only just validated and filled the cache.
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let started = Instant::now();
+        match refresh(&store, &cache, &search_index, false).await {
+            Ok(n) => {
+                cache.metrics.record_refresh(started.elapsed(), Some(n));
+                if n > 0 {
+                    info!("Refreshed {} posts in the cache ({} indexed for search)", n, search_index.len());
+                }
+            }
+            Err(e) => {
+                cache.metrics.record_refresh(started.elapsed(), None);
+                warn!("Failed to refresh the cache: {}", e);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ResponseError(anyhow::Error, Option<Box<HtmxContext>>);
 
@@ -96,8 +240,35 @@ where
     }
 }
 
-async fn not_found_handler(uri: Uri, headers: HeaderMap) -> Response {
-    views::not_found_page(uri, HtmxContext::try_from(&headers).map(Box::new).ok()).into_response()
+async fn not_found_handler(
+    Extension(cfg): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ViewerCache>>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Response, ResponseError> {
+    redirect_or_not_found(&cfg, &cache, uri, HtmxContext::try_from(&headers).map(Box::new).ok())
+}
+
+/// Answers a request for a path which does not exist with its redirect if there is one, keeping the query string, or
+/// with the not found page otherwise.
+fn redirect_or_not_found(
+    cfg: &Config,
+    cache: &ViewerCache,
+    uri: Uri,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> Result<Response, ResponseError> {
+    let Some((redirect, location)) = cache.find_redirect(uri.path()) else {
+        return Ok(views::not_found_page(&cfg.site(), uri, htmx_context).into_response());
+    };
+    let location = match uri.query() {
+        Some(query) if !location.contains('?') => format!("{}?{}", location, query),
+        _ => location,
+    };
+    let code = match redirect.permanent {
+        true => StatusCode::MOVED_PERMANENTLY,
+        false => StatusCode::FOUND,
+    };
+    redirect_response(code, location.as_str()).map_resp_err(&htmx_context)
 }
 
 async fn get_image_handler(
@@ -128,36 +299,188 @@ async fn get_image_handler(
 }
 
 async fn index_handler(
-    State(store): State<Arc<Store>>,
+    Extension(cfg): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ViewerCache>>,
     query: Query<HashMap<String, String>>,
     headers: HeaderMap,
 ) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
     let label_filter = query.get("label");
-    let mut posts = store.list_posts().await.map_resp_err(&htmx_context)?;
-    posts.retain_mut(|p| p.published && label_filter.as_ref().is_none_or(|l| p.labels.contains(l)));
+    let now = Utc::now();
+    let mut posts = cache.list_posts().into_iter().map(|r| r.post.clone()).collect_vec();
+    posts.retain_mut(|p| p.is_live(now) && label_filter.as_ref().is_none_or(|l| p.labels.contains(l)));
     posts.sort();
     posts.reverse();
     let group_map = posts.iter().into_group_map_by(|p| p.date.year());
     let year_groups = group_map.iter().sorted().rev().collect_vec();
-    Ok(views::get_index_page(label_filter.map(|s| s.to_string()), year_groups, htmx_context).into_response())
+    Ok(views::get_index_page(&cfg.site(), label_filter.map(|s| s.to_string()), year_groups, htmx_context).into_response())
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PostQuery {
+    preview: Option<String>,
 }
 
 async fn get_post_handler(
-    State(store): State<Arc<Store>>,
+    Extension(cfg): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ViewerCache>>,
     headers: HeaderMap,
     uri: Uri,
     Path(slug): Path<String>,
+    Query(query): Query<PostQuery>,
 ) -> Result<Response, ResponseError> {
     let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
-    if let Some((post, content)) = store.get_post_raw(&slug).await.map_resp_err(&htmx_context)? {
-        let (content_html, toc) = convert(content.as_str(), &HashSet::default()).map_resp_err(&htmx_context)?;
-        Ok(views::get_post_page(post, PreEscaped(content_html), PreEscaped(toc), htmx_context).into_response())
-    } else {
-        Ok(views::not_found_page(uri, htmx_context).into_response())
+    let is_preview = query.preview.as_ref().is_some_and(|token| {
+        cfg.preview_key
+            .as_ref()
+            .is_some_and(|k| k.verify_expiring(preview_purpose(&slug).as_str(), token))
+    });
+    // Posts created since the last refresh are not found until the next refresh, so that a request for a missing post
+    // never reaches the store.
+    match cache.get_post(&slug) {
+        Some(r) if r.post.is_live(Utc::now()) || is_preview => {
+            let mut resp = views::get_post_page(
+                &cfg.site(),
+                r.post.clone(),
+                PreEscaped(r.content_html.to_string()),
+                PreEscaped(r.toc.to_string()),
+                cfg.base_url.as_ref(),
+                htmx_context,
+            )
+            .into_response();
+            if is_preview {
+                // Drafts must never be cached by shared caches or indexed through a leaked link.
+                resp.headers_mut()
+                    .insert("Cache-Control", HeaderValue::from_static("private, no-store"));
+                resp.headers_mut().insert("X-Robots-Tag", HeaderValue::from_static("noindex"));
+            }
+            Ok(resp)
+        }
+        Some(_) => Ok(views::not_found_page(&cfg.site(), uri, htmx_context).into_response()),
+        // The post may have been renamed.
+        None => redirect_or_not_found(&cfg, &cache, uri, htmx_context),
     }
 }
 
+fn redirect_response(code: StatusCode, location: &str) -> Result<Response, anyhow::Error> {
+    let mut hm = HeaderMap::new();
+    hm.insert("Location", HeaderValue::from_str(location)?);
+    Ok((code, hm).into_response())
+}
+
+/// Collect the most recent live posts for the feeds, optionally filtered by label, along with their content.
+fn list_feed_entries(cache: &ViewerCache, label_filter: Option<&String>) -> Vec<FeedEntry> {
+    let now = Utc::now();
+    let mut posts = cache.list_posts();
+    posts.retain(|r| r.post.is_live(now) && label_filter.is_none_or(|l| r.post.labels.contains(l)));
+    posts.sort_by(|a, b| b.post.cmp(&a.post));
+    posts.truncate(feeds::FEED_LIMIT);
+    posts
+        .into_iter()
+        .map(|r| FeedEntry {
+            post: r.post.clone(),
+            content_html: r.content_html.to_string(),
+        })
+        .collect()
+}
+
+fn feed_response(content_type: &'static str, body: String) -> Response {
+    let mut hm = HeaderMap::new();
+    hm.insert("Content-Type", HeaderValue::from_static(content_type));
+    hm.insert(
+        "Cache-Control",
+        HeaderValue::from_static("public, max-age=300, stale-while-revalidate=30"),
+    );
+    (StatusCode::OK, hm, body).into_response()
+}
+
+async fn atom_feed_handler(
+    Extension(cfg): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ViewerCache>>,
+    query: Query<HashMap<String, String>>,
+    uri: Uri,
+) -> Result<Response, ResponseError> {
+    let Some(meta) = cfg.feed_meta() else {
+        return Ok(views::not_found_page(&cfg.site(), uri, None).into_response());
+    };
+    let label_filter = query.get("label");
+    let entries = list_feed_entries(&cache, label_filter);
+    let self_path = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or(uri.path());
+    Ok(feed_response(
+        "application/atom+xml; charset=utf-8",
+        feeds::render_atom(&meta, self_path, label_filter.map(|l| l.as_str()), &entries),
+    ))
+}
+
+async fn rss_feed_handler(
+    Extension(cfg): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ViewerCache>>,
+    query: Query<HashMap<String, String>>,
+    uri: Uri,
+) -> Result<Response, ResponseError> {
+    let Some(meta) = cfg.feed_meta() else {
+        return Ok(views::not_found_page(&cfg.site(), uri, None).into_response());
+    };
+    let label_filter = query.get("label");
+    let entries = list_feed_entries(&cache, label_filter);
+    Ok(feed_response(
+        "application/rss+xml; charset=utf-8",
+        feeds::render_rss(&meta, label_filter.map(|l| l.as_str()), &entries),
+    ))
+}
+
+async fn sitemap_handler(
+    Extension(cfg): Extension<Arc<Config>>,
+    Extension(cache): Extension<Arc<ViewerCache>>,
+    uri: Uri,
+) -> Result<Response, ResponseError> {
+    let Some(base_url) = cfg.base_url.as_ref() else {
+        return Ok(views::not_found_page(&cfg.site(), uri, None).into_response());
+    };
+    let now = Utc::now();
+    let posts = cache
+        .list_posts()
+        .into_iter()
+        .filter(|r| r.post.is_live(now))
+        .map(|r| SitemapEntry {
+            post: r.post.clone(),
+            last_modified: r.version.last_modified,
+        })
+        .collect_vec();
+    let out = feeds::render_sitemap(base_url, &posts);
+
+    let mut hm = HeaderMap::new();
+    hm.insert("Content-Type", HeaderValue::from_static("application/xml; charset=utf-8"));
+    hm.insert(
+        "Cache-Control",
+        HeaderValue::from_static("public, max-age=3600, stale-while-revalidate=300"),
+    );
+    Ok((StatusCode::OK, hm, out).into_response())
+}
+
+async fn search_handler(
+    Extension(cfg): Extension<Arc<Config>>,
+    Extension(search_index): Extension<Arc<SearchIndex>>,
+    query: Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, ResponseError> {
+    let htmx_context = HtmxContext::try_from(&headers).map(Box::new).ok();
+    let raw_query = query.get("q").map(|q| q.trim().to_string()).unwrap_or_default();
+    let parsed = SearchQuery::parse(raw_query.as_str());
+    let results = if parsed.is_empty() {
+        vec![]
+    } else {
+        search_index.search(&parsed).map_resp_err(&htmx_context)?
+    };
+    Ok(views::get_search_page(&cfg.site(), raw_query, results, htmx_context).into_response())
+}
+
+async fn metrics_handler(Extension(cache): Extension<Arc<ViewerCache>>) -> Response {
+    let mut hm = HeaderMap::new();
+    hm.insert("Content-Type", HeaderValue::from_static("text/plain; version=0.0.4"));
+    (StatusCode::OK, hm, cache.metrics.render()).into_response()
+}
+
 async fn livez_handler() -> Response {
     StatusCode::NO_CONTENT.into_response()
 }
@@ -199,8 +522,12 @@ User-agent: DotBot
 Disallow: /
 "#;
 
-async fn robots_handler() -> Response {
+async fn robots_handler(Extension(cfg): Extension<Arc<Config>>) -> Response {
     let mut hm = HeaderMap::new();
     hm.insert("Content-Type", HeaderValue::from_static("text/plain"));
-    (StatusCode::OK, hm, ROBOTS_TXT).into_response()
+    // The sitemap must be referenced by an absolute url, so we can only do so when we know the base url.
+    match cfg.base_url.as_ref().and_then(|b| b.join("/sitemap.xml").ok()) {
+        Some(sitemap) => (StatusCode::OK, hm, format!("{}\nSitemap: {}\n", ROBOTS_TXT, sitemap)).into_response(),
+        None => (StatusCode::OK, hm, ROBOTS_TXT).into_response(),
+    }
 }
@@ -0,0 +1,240 @@
+use crate::store::{find_redirect, ContentVersion, Post, PostWithVersion, Redirect};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// A post along with its rendered html and table of contents.
+#[derive(Debug)]
+pub(crate) struct RenderedPost {
+    pub(crate) post: Post,
+    pub(crate) version: ContentVersion,
+    pub(crate) content_html: Arc<str>,
+    pub(crate) toc: Arc<str>,
+}
+
+/// Holds the post list and the rendered content of every post, so that requests to the viewer do not need to touch
+/// the object store. The entries are replaced as a whole by [ViewerCache::apply] after each refresh, and the rendered
+/// content is only replaced when the version of the content object changes.
+#[derive(Debug, Default)]
+pub(crate) struct ViewerCache {
+    posts: RwLock<Arc<HashMap<String, Arc<RenderedPost>>>>,
+    redirects: RwLock<Arc<Vec<Redirect>>>,
+    pub(crate) metrics: CacheMetrics,
+}
+
+impl ViewerCache {
+    fn snapshot(&self) -> Arc<HashMap<String, Arc<RenderedPost>>> {
+        self.posts.read().map(|p| p.clone()).unwrap_or_default()
+    }
+
+    /// Returns whether the cached rendering of the post is up-to-date with the given content version. If the backend
+    /// gives us no version at all, the post is always considered stale.
+    pub(crate) fn is_fresh(&self, slug: &str, version: &ContentVersion) -> bool {
+        *version != ContentVersion::default() && self.snapshot().get(slug).is_some_and(|r| r.version == *version)
+    }
+
+    /// Replace the cache contents with the listed posts, using the newly rendered posts where given and the previous
+    /// rendering otherwise. Posts which were not listed are dropped, as are listed posts which have never rendered.
+    pub(crate) fn apply(&self, listed: Vec<PostWithVersion>, rendered: Vec<RenderedPost>) {
+        let previous = self.snapshot();
+        let mut rendered: HashMap<String, RenderedPost> = rendered.into_iter().map(|r| (r.post.slug.clone(), r)).collect();
+        let next: HashMap<String, Arc<RenderedPost>> = listed
+            .into_iter()
+            .filter_map(|(post, version)| match rendered.remove(&post.slug) {
+                Some(r) => Some((post.slug.clone(), Arc::new(RenderedPost { post, version, ..r }))),
+                // The metadata may change without the content changing, so we always take the listed post.
+                None => previous.get(&post.slug).map(|r| {
+                    (
+                        post.slug.clone(),
+                        Arc::new(RenderedPost {
+                            post,
+                            version: r.version.clone(),
+                            content_html: r.content_html.clone(),
+                            toc: r.toc.clone(),
+                        }),
+                    )
+                }),
+            })
+            .collect();
+        self.metrics.posts.store(next.len() as u64, Ordering::Relaxed);
+        if let Ok(mut posts) = self.posts.write() {
+            *posts = Arc::new(next);
+        }
+    }
+
+    /// Returns the rendered post, recording whether it was a hit or a miss.
+    pub(crate) fn get_post(&self, slug: &str) -> Option<Arc<RenderedPost>> {
+        let found = self.snapshot().get(slug).cloned();
+        self.metrics.record_lookup(found.is_some());
+        found
+    }
+
+    /// Replace the cached redirects.
+    pub(crate) fn set_redirects(&self, redirects: Vec<Redirect>) {
+        if let Ok(mut r) = self.redirects.write() {
+            *r = Arc::new(redirects);
+        }
+    }
+
+    /// Returns the redirect for a request path along with the location to redirect to, if there is one.
+    pub(crate) fn find_redirect(&self, path: &str) -> Option<(Redirect, String)> {
+        let redirects = self.redirects.read().map(|r| r.clone()).unwrap_or_default();
+        find_redirect(&redirects, path).map(|(r, location)| (r.clone(), location))
+    }
+
+    /// Returns all the cached posts, recording a hit.
+    pub(crate) fn list_posts(&self) -> Vec<Arc<RenderedPost>> {
+        self.metrics.record_lookup(true);
+        self.snapshot().values().cloned().collect()
+    }
+}
+
+/// Counters for the cache, exposed in the Prometheus text format by [CacheMetrics::render].
+#[derive(Debug, Default)]
+pub(crate) struct CacheMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    refreshes: AtomicU64,
+    refresh_errors: AtomicU64,
+    refresh_micros_total: AtomicU64,
+    last_refresh_micros: AtomicU64,
+    rendered_total: AtomicU64,
+    posts: AtomicU64,
+}
+
+impl CacheMetrics {
+    fn record_lookup(&self, hit: bool) {
+        match hit {
+            true => self.hits.fetch_add(1, Ordering::Relaxed),
+            false => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Record a refresh of the cache along with the number of posts which were re-rendered, if it succeeded.
+    pub(crate) fn record_refresh(&self, elapsed: Duration, rendered: Option<usize>) {
+        let micros = elapsed.as_micros() as u64;
+        self.refreshes.fetch_add(1, Ordering::Relaxed);
+        self.refresh_micros_total.fetch_add(micros, Ordering::Relaxed);
+        self.last_refresh_micros.store(micros, Ordering::Relaxed);
+        match rendered {
+            Some(n) => self.rendered_total.fetch_add(n as u64, Ordering::Relaxed),
+            None => self.refresh_errors.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub(crate) fn render(&self) -> String {
+        let load = |a: &AtomicU64| a.load(Ordering::Relaxed);
+        let seconds = |a: &AtomicU64| a.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}{} {}", name, labels, value);
+            }
+        };
+        metric(
+            "bloog_viewer_cache_lookups_total",
+            "counter",
+            "Lookups of the viewer cache by result.",
+            &[
+                (r#"{result="hit"}"#, load(&self.hits).to_string()),
+                (r#"{result="miss"}"#, load(&self.misses).to_string()),
+            ],
+        );
+        metric(
+            "bloog_viewer_cache_refresh_errors_total",
+            "counter",
+            "Refreshes of the viewer cache which failed.",
+            &[("", load(&self.refresh_errors).to_string())],
+        );
+        metric(
+            "bloog_viewer_cache_refresh_duration_seconds",
+            "summary",
+            "Time taken to refresh the viewer cache from the store.",
+            &[
+                ("_sum", seconds(&self.refresh_micros_total).to_string()),
+                ("_count", load(&self.refreshes).to_string()),
+            ],
+        );
+        metric(
+            "bloog_viewer_cache_last_refresh_duration_seconds",
+            "gauge",
+            "Time taken by the most recent refresh of the viewer cache.",
+            &[("", seconds(&self.last_refresh_micros).to_string())],
+        );
+        metric(
+            "bloog_viewer_cache_rendered_total",
+            "counter",
+            "Posts fetched and rendered because their content changed.",
+            &[("", load(&self.rendered_total).to_string())],
+        );
+        metric(
+            "bloog_viewer_cache_posts",
+            "gauge",
+            "Posts currently held in the viewer cache.",
+            &[("", load(&self.posts).to_string())],
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(e_tag: &str) -> ContentVersion {
+        ContentVersion {
+            last_modified: None,
+            e_tag: Some(e_tag.to_string()),
+        }
+    }
+
+    fn rendered(post: &Post, e_tag: &str, html: &str) -> RenderedPost {
+        RenderedPost {
+            post: post.clone(),
+            version: version(e_tag),
+            content_html: Arc::from(html),
+            toc: Arc::from(""),
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let cache = ViewerCache::default();
+        let a = Post {
+            slug: "a".to_string(),
+            ..Post::default()
+        };
+        let b = Post {
+            slug: "b".to_string(),
+            ..Post::default()
+        };
+        assert!(!cache.is_fresh("a", &version("1")));
+        cache.apply(
+            vec![(a.clone(), version("1")), (b.clone(), version("1"))],
+            vec![rendered(&a, "1", "a1"), rendered(&b, "1", "b1")],
+        );
+        assert!(cache.is_fresh("a", &version("1")));
+        assert!(!cache.is_fresh("a", &version("2")));
+        assert!(!cache.is_fresh("a", &ContentVersion::default()));
+
+        // A metadata change is picked up without re-rendering, and unlisted posts are dropped.
+        let renamed = Post {
+            title: "A".to_string(),
+            ..a.clone()
+        };
+        cache.apply(vec![(renamed.clone(), version("1"))], vec![]);
+        let found = cache.get_post("a").map(|r| (r.post.clone(), r.content_html.to_string()));
+        assert_eq!(found, Some((renamed, "a1".to_string())));
+        assert!(cache.get_post("b").is_none());
+        assert_eq!(cache.list_posts().len(), 1);
+
+        let metrics = cache.metrics.render();
+        assert!(metrics.contains("bloog_viewer_cache_lookups_total{result=\"hit\"} 2\n"));
+        assert!(metrics.contains("bloog_viewer_cache_lookups_total{result=\"miss\"} 1\n"));
+        assert!(metrics.contains("bloog_viewer_cache_posts 1\n"));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::store::Post;
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use std::collections::BTreeMap;
+use url::Url;
+
+/// The number of most recent posts included in each feed. Each entry carries the full content, so this keeps the
+/// size of the feed documents down.
+pub(crate) const FEED_LIMIT: usize = 20;
+
+/// The blog-wide details of a feed. Feeds need absolute links, so they are only rendered once the base url is known.
+pub(crate) struct FeedMeta<'a> {
+    pub(crate) base: &'a Url,
+    pub(crate) title: &'a str,
+    pub(crate) author: &'a str,
+    pub(crate) description: &'a str,
+}
+
+/// A published post along with its rendered html content.
+pub(crate) struct FeedEntry {
+    pub(crate) post: Post,
+    pub(crate) content_html: String,
+}
+
+/// A live post along with when its content was last written.
+pub(crate) struct SitemapEntry {
+    pub(crate) post: Post,
+    pub(crate) last_modified: Option<DateTime<Utc>>,
+}
+
+fn escape_xml(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn to_datetime(date: NaiveDate) -> DateTime<Utc> {
+    date.and_time(NaiveTime::MIN).and_utc()
+}
+
+fn updated_datetime(post: &Post) -> DateTime<Utc> {
+    to_datetime(post.updated.unwrap_or(post.date))
+}
+
+/// Feed readers do not know the origin of the feed content, so the relative links to posts and images are
+/// rewritten to be absolute. This includes every candidate in the srcset of a responsive image.
+fn absolute_links(base: &Url, html: &str) -> String {
+    let origin = base.as_str().trim_end_matches('/');
+    let html = html
+        .replace("href=\"/", format!("href=\"{}/", origin).as_str())
+        .replace("src=\"/", format!("src=\"{}/", origin).as_str());
+    absolute_srcsets(origin, html.as_str())
+}
+
+/// Prefix the root relative candidates of each srcset attribute with the origin. Quotes in text and in other attribute
+/// values are escaped when the markdown is rendered, so each attribute value runs to the next quote.
+fn absolute_srcsets(origin: &str, html: &str) -> String {
+    const ATTRIBUTE: &str = "srcset=\"";
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find(ATTRIBUTE) {
+        let (before, value) = rest.split_at(start + ATTRIBUTE.len());
+        let end = value.find('"').unwrap_or(value.len());
+        let candidates = value[..end]
+            .split(',')
+            .map(|candidate| match candidate.trim_start() {
+                c if c.starts_with('/') => format!("{}{}", origin, c),
+                c => c.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        out.push_str(before);
+        out.push_str(candidates.as_str());
+        rest = &value[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn join(base: &Url, path: &str) -> String {
+    base.join(path).map(|u| u.to_string()).unwrap_or_else(|_| base.to_string())
+}
+
+fn feed_title(title: &str, label: Option<&str>) -> String {
+    match label {
+        Some(l) => format!("{} - #{}", title, l),
+        None => title.to_string(),
+    }
+}
+
+fn index_link(base: &Url, label: Option<&str>) -> String {
+    match label {
+        Some(l) => join(
+            base,
+            format!(
+                "/?{}",
+                url::form_urlencoded::Serializer::new(String::new())
+                    .append_pair("label", l)
+                    .finish()
+            )
+            .as_str(),
+        ),
+        None => join(base, "/"),
+    }
+}
+
+/// Render an Atom feed document. The entries are expected to be sorted newest first.
+pub(crate) fn render_atom(meta: &FeedMeta, self_path: &str, label: Option<&str>, entries: &[FeedEntry]) -> String {
+    let base = meta.base;
+    let updated = entries
+        .iter()
+        .map(|e| updated_datetime(&e.post))
+        .max()
+        .unwrap_or_default()
+        .to_rfc3339();
+    let mut out = String::new();
+    out.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    out.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+    out.push_str(format!("<id>{}</id>", escape_xml(join(base, self_path).as_str())).as_str());
+    out.push_str(format!("<title>{}</title>", escape_xml(feed_title(meta.title, label).as_str())).as_str());
+    out.push_str(format!("<subtitle>{}</subtitle>", escape_xml(meta.description)).as_str());
+    out.push_str(format!("<updated>{}</updated>", updated).as_str());
+    out.push_str(format!("<author><name>{}</name></author>", escape_xml(meta.author)).as_str());
+    out.push_str(format!(r#"<link rel="self" href="{}"/>"#, escape_xml(join(base, self_path).as_str())).as_str());
+    out.push_str(format!(r#"<link rel="alternate" href="{}"/>"#, escape_xml(index_link(base, label).as_str())).as_str());
+    for e in entries {
+        let link = join(base, format!("/posts/{}", e.post.slug).as_str());
+        out.push_str("<entry>");
+        out.push_str(format!("<id>{}</id>", escape_xml(link.as_str())).as_str());
+        out.push_str(format!("<title>{}</title>", escape_xml(e.post.title.as_str())).as_str());
+        out.push_str(format!("<updated>{}</updated>", updated_datetime(&e.post).to_rfc3339()).as_str());
+        out.push_str(format!("<published>{}</published>", to_datetime(e.post.date).to_rfc3339()).as_str());
+        out.push_str(format!(r#"<link rel="alternate" href="{}"/>"#, escape_xml(link.as_str())).as_str());
+        if let Some(author) = &e.post.author {
+            out.push_str(format!("<author><name>{}</name></author>", escape_xml(author)).as_str());
+        }
+        if let Some(summary) = &e.post.summary {
+            out.push_str(format!("<summary>{}</summary>", escape_xml(summary)).as_str());
+        }
+        for l in &e.post.labels {
+            out.push_str(format!(r#"<category term="{}"/>"#, escape_xml(l)).as_str());
+        }
+        out.push_str(
+            format!(
+                r#"<content type="html">{}</content>"#,
+                escape_xml(absolute_links(base, e.content_html.as_str()).as_str())
+            )
+            .as_str(),
+        );
+        out.push_str("</entry>");
+    }
+    out.push_str("</feed>");
+    out
+}
+
+/// Render an RSS 2.0 feed document. The entries are expected to be sorted newest first.
+pub(crate) fn render_rss(meta: &FeedMeta, label: Option<&str>, entries: &[FeedEntry]) -> String {
+    let base = meta.base;
+    let mut out = String::new();
+    out.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    out.push_str(r#"<rss version="2.0"><channel>"#);
+    out.push_str(format!("<title>{}</title>", escape_xml(feed_title(meta.title, label).as_str())).as_str());
+    out.push_str(format!("<link>{}</link>", escape_xml(index_link(base, label).as_str())).as_str());
+    out.push_str(format!("<description>{}</description>", escape_xml(meta.description)).as_str());
+    out.push_str("<language>en</language>");
+    if let Some(e) = entries.iter().max_by_key(|e| e.post.date) {
+        out.push_str(format!("<lastBuildDate>{}</lastBuildDate>", to_datetime(e.post.date).to_rfc2822()).as_str());
+    }
+    for e in entries {
+        let link = join(base, format!("/posts/{}", e.post.slug).as_str());
+        out.push_str("<item>");
+        out.push_str(format!("<title>{}</title>", escape_xml(e.post.title.as_str())).as_str());
+        out.push_str(format!("<link>{}</link>", escape_xml(link.as_str())).as_str());
+        out.push_str(format!(r#"<guid isPermaLink="true">{}</guid>"#, escape_xml(link.as_str())).as_str());
+        out.push_str(format!("<pubDate>{}</pubDate>", to_datetime(e.post.date).to_rfc2822()).as_str());
+        for l in &e.post.labels {
+            out.push_str(format!("<category>{}</category>", escape_xml(l)).as_str());
+        }
+        out.push_str(
+            format!(
+                "<description>{}</description>",
+                escape_xml(absolute_links(base, e.content_html.as_str()).as_str())
+            )
+            .as_str(),
+        );
+        out.push_str("</item>");
+    }
+    out.push_str("</channel></rss>");
+    out
+}
+
+/// Render a sitemap of the index, the posts, and the label pages, each with the time it last changed.
+pub(crate) fn render_sitemap(base: &Url, entries: &[SitemapEntry]) -> String {
+    // A scheduled post changes the pages it appears on when it goes live, rather than when it was last written.
+    let mut posts = entries
+        .iter()
+        .map(|e| (&e.post, e.last_modified.max(e.post.publish_at.map(|t| t.to_utc()))))
+        .collect::<Vec<_>>();
+    posts.sort();
+
+    // The index and label pages change whenever one of the posts on them changes.
+    let index_modified = posts.iter().filter_map(|(_, lm)| *lm).max();
+    let mut label_modified: BTreeMap<&str, Option<DateTime<Utc>>> = BTreeMap::new();
+    for (p, lm) in posts.iter() {
+        for l in p.labels.iter() {
+            let entry = label_modified.entry(l.as_str()).or_default();
+            *entry = (*entry).max(*lm);
+        }
+    }
+
+    let mut urls: Vec<(String, Option<DateTime<Utc>>)> = vec![(index_link(base, None), index_modified)];
+    urls.extend(posts.iter().map(|(p, lm)| (join(base, format!("/posts/{}", p.slug).as_str()), *lm)));
+    urls.extend(label_modified.iter().map(|(l, lm)| (index_link(base, Some(l)), *lm)));
+
+    let mut out = String::new();
+    out.push_str(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    out.push_str(r#"<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">"#);
+    for (loc, lm) in urls {
+        out.push_str("<url>");
+        out.push_str(format!("<loc>{}</loc>", escape_xml(loc.as_str())).as_str());
+        if let Some(lm) = lm {
+            out.push_str(format!("<lastmod>{}</lastmod>", lm.to_rfc3339_opts(SecondsFormat::Secs, true)).as_str());
+        }
+        out.push_str("</url>");
+    }
+    out.push_str("</urlset>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(base: &Url) -> FeedMeta<'_> {
+        FeedMeta {
+            base,
+            title: "Ben's Blog",
+            author: "Ben Meier",
+            description: "Ben's blog on distributed systems, security, networking, and programming.",
+        }
+    }
+
+    fn entries() -> Vec<FeedEntry> {
+        vec![FeedEntry {
+            post: Post {
+                date: NaiveDate::from_ymd_opt(2024, 2, 3).unwrap_or_default(),
+                slug: "my-post".to_string(),
+                title: "Fish & Chips".to_string(),
+                published: true,
+                labels: vec!["food".to_string()],
+                ..Post::default()
+            },
+            content_html: r#"<p><a href="/posts/other">x</a><img src="/images/a.webp" /></p>"#.to_string(),
+        }]
+    }
+
+    #[test]
+    fn test_render_atom() -> Result<(), url::ParseError> {
+        let base = Url::parse("https://example.com")?;
+        assert_eq!(
+            render_atom(&meta(&base), "/feed.xml", Some("food"), &entries()),
+            r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom"><id>https://example.com/feed.xml</id><title>Ben&apos;s Blog - #food</title><subtitle>Ben&apos;s blog on distributed systems, security, networking, and programming.</subtitle><updated>2024-02-03T00:00:00+00:00</updated><author><name>Ben Meier</name></author><link rel="self" href="https://example.com/feed.xml"/><link rel="alternate" href="https://example.com/?label=food"/><entry><id>https://example.com/posts/my-post</id><title>Fish &amp; Chips</title><updated>2024-02-03T00:00:00+00:00</updated><published>2024-02-03T00:00:00+00:00</published><link rel="alternate" href="https://example.com/posts/my-post"/><category term="food"/><content type="html">&lt;p&gt;&lt;a href=&quot;https://example.com/posts/other&quot;&gt;x&lt;/a&gt;&lt;img src=&quot;https://example.com/images/a.webp&quot; /&gt;&lt;/p&gt;</content></entry></feed>"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_absolute_links() -> Result<(), url::ParseError> {
+        let base = Url::parse("https://example.com/")?;
+        assert_eq!(
+            absolute_links(
+                &base,
+                r#"<source srcset="/images/a.w400.avif 400w, /images/a.w800.avif 800w"><img src="/images/a.webp">"#
+            ),
+            r#"<source srcset="https://example.com/images/a.w400.avif 400w, https://example.com/images/a.w800.avif 800w"><img src="https://example.com/images/a.webp">"#
+        );
+        // Only srcset attributes are rewritten, not text which happens to look like a candidate.
+        assert_eq!(
+            absolute_links(&base, r#"<p>Each 400w, /tmp and a new, /var directory</p>"#),
+            r#"<p>Each 400w, /tmp and a new, /var directory</p>"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_rss() -> Result<(), url::ParseError> {
+        let base = Url::parse("https://example.com/")?;
+        assert_eq!(
+            render_rss(&meta(&base), None, &entries()),
+            r#"<?xml version="1.0" encoding="utf-8"?><rss version="2.0"><channel><title>Ben&apos;s Blog</title><link>https://example.com/</link><description>Ben&apos;s blog on distributed systems, security, networking, and programming.</description><language>en</language><lastBuildDate>Sat, 3 Feb 2024 00:00:00 +0000</lastBuildDate><item><title>Fish &amp; Chips</title><link>https://example.com/posts/my-post</link><guid isPermaLink="true">https://example.com/posts/my-post</guid><pubDate>Sat, 3 Feb 2024 00:00:00 +0000</pubDate><category>food</category><description>&lt;p&gt;&lt;a href=&quot;https://example.com/posts/other&quot;&gt;x&lt;/a&gt;&lt;img src=&quot;https://example.com/images/a.webp&quot; /&gt;&lt;/p&gt;</description></item></channel></rss>"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_sitemap() -> Result<(), anyhow::Error> {
+        let base = Url::parse("https://example.com/")?;
+        let at = |raw: &str| DateTime::parse_from_rfc3339(raw).map(|t| t.to_utc());
+        let entries = vec![
+            SitemapEntry {
+                post: Post {
+                    date: NaiveDate::from_ymd_opt(2024, 2, 3).unwrap_or_default(),
+                    slug: "bits&bobs".to_string(),
+                    labels: vec!["fish & chips".to_string(), "food".to_string()],
+                    ..Post::default()
+                },
+                last_modified: Some(at("2024-02-03T10:00:00Z")?),
+            },
+            SitemapEntry {
+                post: Post {
+                    date: NaiveDate::from_ymd_opt(2024, 3, 4).unwrap_or_default(),
+                    slug: "scheduled".to_string(),
+                    labels: vec!["food".to_string()],
+                    publish_at: Some(DateTime::parse_from_rfc3339("2024-03-05T09:30:00+02:00")?),
+                    ..Post::default()
+                },
+                last_modified: Some(at("2024-03-01T00:00:00Z")?),
+            },
+        ];
+        assert_eq!(
+            render_sitemap(&base, &entries),
+            r#"<?xml version="1.0" encoding="utf-8"?><urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9"><url><loc>https://example.com/</loc><lastmod>2024-03-05T07:30:00Z</lastmod></url><url><loc>https://example.com/posts/bits&amp;bobs</loc><lastmod>2024-02-03T10:00:00Z</lastmod></url><url><loc>https://example.com/posts/scheduled</loc><lastmod>2024-03-05T07:30:00Z</lastmod></url><url><loc>https://example.com/?label=fish+%26+chips</loc><lastmod>2024-02-03T10:00:00Z</lastmod></url><url><loc>https://example.com/?label=food</loc><lastmod>2024-03-05T07:30:00Z</lastmod></url></urlset>"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,403 @@
+use crate::store::{Post, PostWithLastModified};
+use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+use maud::{html, Markup};
+use pulldown_cmark::{Event, Parser, TagEnd};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+/// The number of tokens shown either side of the first match in a result snippet.
+const SNIPPET_TOKENS_BEFORE: usize = 10;
+const SNIPPET_TOKENS_AFTER: usize = 25;
+
+/// Matches in the title are worth more than matches in the body.
+const TITLE_BOOST: f64 = 3.0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    term: String,
+    start: usize,
+    end: usize,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut out = vec![];
+    let mut start: Option<usize> = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                out.push(Token {
+                    term: text[s..i].to_lowercase(),
+                    start: s,
+                    end: i,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Extract the human-readable text from the markdown so that link targets and syntax are not indexed.
+fn markdown_to_text(raw: &str) -> String {
+    let mut out = String::with_capacity(raw.len());
+    for evt in Parser::new(raw) {
+        match evt {
+            Event::Text(t) | Event::Code(t) => out.push_str(t.as_ref()),
+            Event::SoftBreak | Event::HardBreak => out.push(' '),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item | TagEnd::CodeBlock | TagEnd::TableCell) => out.push('\n'),
+            _ => {}
+        }
+    }
+    out
+}
+
+#[derive(Debug)]
+struct Document {
+    post: Post,
+    last_modified: Option<DateTime<Utc>>,
+    title_terms: HashSet<String>,
+    text: String,
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    docs: HashMap<String, Document>,
+    /// Maps each term to the documents it appears in and the token positions within each document.
+    postings: HashMap<String, HashMap<String, Vec<usize>>>,
+}
+
+impl Inner {
+    fn remove(&mut self, slug: &str) {
+        if let Some(doc) = self.docs.remove(slug) {
+            for term in doc.tokens.iter().map(|t| &t.term).unique() {
+                if let Some(p) = self.postings.get_mut(term) {
+                    p.remove(slug);
+                    if p.is_empty() {
+                        self.postings.remove(term);
+                    }
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, doc: Document) {
+        let slug = doc.post.slug.clone();
+        self.remove(slug.as_str());
+        for (i, t) in doc.tokens.iter().enumerate() {
+            self.postings
+                .entry(t.term.clone())
+                .or_default()
+                .entry(slug.clone())
+                .or_default()
+                .push(i);
+        }
+        self.docs.insert(slug, doc);
+    }
+
+    fn idf(&self, term: &str) -> f64 {
+        let df = self.postings.get(term).map(|p| p.len()).unwrap_or_default();
+        ((self.docs.len() as f64 + 1.0) / (df as f64 + 1.0)).ln() + 1.0
+    }
+
+    fn positions(&self, term: &str, slug: &str) -> &[usize] {
+        self.postings
+            .get(term)
+            .and_then(|p| p.get(slug))
+            .map(|v| v.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the starting token positions of every occurrence of the phrase in the document.
+    fn phrase_positions(&self, phrase: &[String], slug: &str) -> Vec<usize> {
+        let Some((first, rest)) = phrase.split_first() else {
+            return vec![];
+        };
+        self.positions(first, slug)
+            .iter()
+            .copied()
+            .filter(|p| {
+                rest.iter()
+                    .enumerate()
+                    .all(|(i, t)| self.positions(t, slug).binary_search(&(p + i + 1)).is_ok())
+            })
+            .collect()
+    }
+}
+
+/// A parsed search query. Bare words must all appear in the post, quoted phrases must appear as written, and
+/// `label:x` restricts the results to posts with that label.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct SearchQuery {
+    terms: Vec<String>,
+    phrases: Vec<Vec<String>>,
+    labels: Vec<String>,
+}
+
+impl SearchQuery {
+    pub(crate) fn parse(raw: &str) -> Self {
+        let mut out = SearchQuery::default();
+        for (i, segment) in raw.split('"').enumerate() {
+            if i % 2 == 1 {
+                let phrase = tokenize(segment).into_iter().map(|t| t.term).collect_vec();
+                match phrase.len() {
+                    0 => {}
+                    1 => out.terms.extend(phrase),
+                    _ => out.phrases.push(phrase),
+                }
+                continue;
+            }
+            for word in segment.split_whitespace() {
+                match word.strip_prefix("label:") {
+                    Some(l) if !l.is_empty() => out.labels.push(l.to_string()),
+                    _ => out.terms.extend(tokenize(word).into_iter().map(|t| t.term)),
+                }
+            }
+        }
+        out
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.terms.is_empty() && self.phrases.is_empty() && self.labels.is_empty()
+    }
+}
+
+pub(crate) struct SearchResult {
+    pub(crate) post: Post,
+    pub(crate) snippet: Markup,
+    score: f64,
+}
+
+/// An in-memory inverted index over the text of every post. This is cheap enough to hold entirely in memory for a
+/// personal blog and means that searches never touch the object store.
+#[derive(Debug, Default)]
+pub(crate) struct SearchIndex {
+    inner: RwLock<Inner>,
+}
+
+impl SearchIndex {
+    /// Add or replace the post in the index.
+    pub(crate) fn upsert(&self, post: Post, last_modified: Option<DateTime<Utc>>, raw_content: &str) -> Result<(), Error> {
+        let text = markdown_to_text(raw_content);
+        let doc = Document {
+            title_terms: tokenize(post.title.as_str()).into_iter().map(|t| t.term).collect(),
+            tokens: tokenize(text.as_str()),
+            text,
+            post,
+            last_modified,
+        };
+        self.inner.write().map_err(|e| anyhow!("{}", e))?.insert(doc);
+        Ok(())
+    }
+
+    /// Determine which of the given posts are new or have changed since they were indexed, and drop any indexed posts
+    /// which no longer exist.
+    pub(crate) fn sync(&self, posts: &[PostWithLastModified]) -> Result<Vec<PostWithLastModified>, Error> {
+        let mut inner = self.inner.write().map_err(|e| anyhow!("{}", e))?;
+        let slugs: HashSet<&str> = posts.iter().map(|(p, _)| p.slug.as_str()).collect();
+        let removed = inner.docs.keys().filter(|s| !slugs.contains(s.as_str())).cloned().collect_vec();
+        for slug in removed {
+            inner.remove(slug.as_str());
+        }
+        Ok(posts
+            .iter()
+            .filter(|(p, lm)| {
+                inner
+                    .docs
+                    .get(p.slug.as_str())
+                    .is_none_or(|d| d.post.ne(p) || d.last_modified.ne(lm))
+            })
+            .cloned()
+            .collect())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.inner.read().map(|i| i.docs.len()).unwrap_or_default()
+    }
+
+    /// Search the live posts, returning the results ranked by relevance.
+    pub(crate) fn search(&self, query: &SearchQuery) -> Result<Vec<SearchResult>, Error> {
+        let inner = self.inner.read().map_err(|e| anyhow!("{}", e))?;
+        let now = Utc::now();
+        let mut results = vec![];
+        for (slug, doc) in inner.docs.iter() {
+            if !doc.post.is_live(now) || !query.labels.iter().all(|l| doc.post.labels.contains(l)) {
+                continue;
+            }
+            let mut score = 0.0;
+            let mut first_hit: Option<usize> = None;
+            let mut matched = true;
+            for term in query.terms.iter() {
+                let positions = inner.positions(term, slug);
+                let in_title = doc.title_terms.contains(term);
+                if positions.is_empty() && !in_title {
+                    matched = false;
+                    break;
+                }
+                score += inner.idf(term) * (positions.len() as f64 + if in_title { TITLE_BOOST } else { 0.0 });
+                first_hit = first_hit.into_iter().chain(positions.first().copied()).min();
+            }
+            for phrase in query.phrases.iter().filter(|_| matched) {
+                let positions = inner.phrase_positions(phrase, slug);
+                if positions.is_empty() {
+                    matched = false;
+                    break;
+                }
+                score += phrase.iter().map(|t| inner.idf(t)).sum::<f64>() * positions.len() as f64;
+                first_hit = first_hit.into_iter().chain(positions.first().copied()).min();
+            }
+            if matched {
+                results.push(SearchResult {
+                    post: doc.post.clone(),
+                    snippet: snippet(doc, first_hit.unwrap_or_default(), query),
+                    score,
+                });
+            }
+        }
+        results.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| b.post.cmp(&a.post))
+        });
+        Ok(results)
+    }
+}
+
+/// Render a window of the document text around the given token position with the query terms highlighted.
+fn snippet(doc: &Document, position: usize, query: &SearchQuery) -> Markup {
+    if doc.tokens.is_empty() {
+        return html! {};
+    }
+    let highlight: HashSet<&String> = query.terms.iter().chain(query.phrases.iter().flatten()).collect();
+    let first = position.saturating_sub(SNIPPET_TOKENS_BEFORE);
+    let last = (position + SNIPPET_TOKENS_AFTER).min(doc.tokens.len() - 1);
+    // Pair each token with the text between it and the previous token so that punctuation is preserved.
+    let segments = doc.tokens[first..=last]
+        .iter()
+        .scan(doc.tokens[first].start, |cursor, t| {
+            let gap = doc.text[*cursor..t.start].replace('\n', " ");
+            *cursor = t.end;
+            Some((gap, &doc.text[t.start..t.end], highlight.contains(&t.term)))
+        })
+        .collect_vec();
+    html! {
+        @if first > 0 { "… " }
+        @for (gap, word, is_match) in segments {
+            (gap)
+            @if is_match { mark { (word) } } @else { (word) }
+        }
+        @if last < doc.tokens.len() - 1 { " …" }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn post(slug: &str, title: &str, labels: &[&str]) -> Post {
+        Post {
+            date: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap_or_default(),
+            slug: slug.to_string(),
+            title: title.to_string(),
+            published: true,
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            ..Post::default()
+        }
+    }
+
+    fn index() -> Result<SearchIndex, Error> {
+        let index = SearchIndex::default();
+        index.upsert(
+            post("rust-post", "Rust things", &["rust"]),
+            None,
+            "# Hello\n\nThe quick brown fox jumps over the [lazy](/posts/dog) dog.",
+        )?;
+        index.upsert(
+            post("go-post", "Go things", &["go"]),
+            None,
+            "A brown dog and a quick fox. Quick quick quick.",
+        )?;
+        index.upsert(
+            Post {
+                published: false,
+                ..post("draft-post", "Draft", &[])
+            },
+            None,
+            "quick fox",
+        )?;
+        index.upsert(
+            Post {
+                publish_at: Some((Utc::now() + chrono::TimeDelta::days(1)).fixed_offset()),
+                ..post("scheduled-post", "Scheduled", &[])
+            },
+            None,
+            "quick fox",
+        )?;
+        Ok(index)
+    }
+
+    fn slugs(results: Vec<SearchResult>) -> Vec<String> {
+        results.into_iter().map(|r| r.post.slug).collect()
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            SearchQuery::parse(r#"Quick "brown fox" label:rust "single" label:"#),
+            SearchQuery {
+                terms: vec!["quick".to_string(), "single".to_string(), "label".to_string()],
+                phrases: vec![vec!["brown".to_string(), "fox".to_string()]],
+                labels: vec!["rust".to_string()],
+            }
+        );
+        assert!(SearchQuery::parse("  ").is_empty());
+    }
+
+    #[test]
+    fn test_search_terms() -> Result<(), Error> {
+        let index = index()?;
+        assert_eq!(slugs(index.search(&SearchQuery::parse("quick"))?), vec!["go-post", "rust-post"]);
+        assert_eq!(slugs(index.search(&SearchQuery::parse("quick lazy"))?), vec!["rust-post"]);
+        assert_eq!(slugs(index.search(&SearchQuery::parse("rust"))?), vec!["rust-post"]);
+        assert!(index.search(&SearchQuery::parse("posts"))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_phrases_and_labels() -> Result<(), Error> {
+        let index = index()?;
+        assert_eq!(slugs(index.search(&SearchQuery::parse(r#""brown fox""#))?), vec!["rust-post"]);
+        assert_eq!(slugs(index.search(&SearchQuery::parse(r#""quick fox""#))?), vec!["go-post"]);
+        assert_eq!(slugs(index.search(&SearchQuery::parse("fox label:go"))?), vec!["go-post"]);
+        assert_eq!(slugs(index.search(&SearchQuery::parse("label:rust"))?), vec!["rust-post"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snippet() -> Result<(), Error> {
+        let index = index()?;
+        let results = index.search(&SearchQuery::parse("lazy"))?;
+        assert_eq!(
+            results.first().map(|r| r.snippet.0.as_str()),
+            Some("Hello The quick brown fox jumps over the <mark>lazy</mark> dog")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sync() -> Result<(), Error> {
+        let index = index()?;
+        let changed = post("rust-post", "Rust things renamed", &["rust"]);
+        let stale = index.sync(&[(post("go-post", "Go things", &["go"]), None), (changed.clone(), None)])?;
+        assert_eq!(stale, vec![(changed, None)]);
+        assert_eq!(index.len(), 2);
+        Ok(())
+    }
+}
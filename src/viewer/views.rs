@@ -1,27 +1,52 @@
 use crate::htmx::HtmxContext;
 use crate::store::Post;
+use crate::viewer::search::SearchResult;
+use crate::viewer::{DEFAULT_AUTHOR, DEFAULT_TITLE};
 use crate::viewhelpers::{render_body_html_or_htmx, COMMON_CSS};
 use axum::http::{StatusCode, Uri};
 use axum::response::IntoResponse;
 use chrono::{Datelike, Local};
 use clap::crate_version;
-use lazy_static::lazy_static;
 use maud::{html, Markup, PreEscaped, DOCTYPE};
-use std::ops::Deref;
+use url::Url;
 
 const RFC3339_DATE_FORMAT: &str = "%Y-%m-%dT00:00:00Z";
 
-fn render_body_html(title: &str, body: Markup) -> Markup {
+/// The blog-wide details shared by every page.
+pub(crate) struct Site<'a> {
+    pub(crate) title: &'a str,
+    pub(crate) author: &'a str,
+    /// Whether the feeds are served and so can be advertised.
+    pub(crate) feeds: bool,
+}
+
+/// Used where the configuration is not available, which is only the case for internal errors.
+const DEFAULT_SITE: Site<'static> = Site {
+    title: DEFAULT_TITLE,
+    author: DEFAULT_AUTHOR,
+    feeds: false,
+};
+
+fn render_body_html(site: &Site, title: &str, body: Markup) -> Markup {
+    render_body_html_with_meta(site, title, html! { meta name="author" content=(site.author); }, body)
+}
+
+/// Renders the full page with the given meta tags in the head, which must include the author.
+fn render_body_html_with_meta(site: &Site, title: &str, meta: Markup, body: Markup) -> Markup {
     html! {
         (DOCTYPE)
         html lang="en" {
             head {
                 title { (title) }
                 meta charset="utf-8";
-                meta name="author" content="Ben Meier";
+                (meta)
                 meta name="keywords" content="golang, rust, distributed systems, programming, security";
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
                 link rel="shortcut icon" href="/statics/favicon.svg" type="image/svg+xml";
+                @if site.feeds {
+                    link rel="alternate" type="application/atom+xml" title={ (site.title) " (Atom)" } href="/feed.xml";
+                    link rel="alternate" type="application/rss+xml" title={ (site.title) " (RSS)" } href="/rss.xml";
+                }
                 link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/modern-normalize/3.0.1/modern-normalize.min.css" integrity="sha512-q6WgHqiHlKyOqslT/lgBgodhd03Wp4BEqKeW6nNtlOY4quzyG3VoQKFrieaCeSnuVseNKRGpGeDU3qPmabCANg==" crossorigin="anonymous" referrerpolicy="no-referrer";
                 link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/milligram/1.4.1/milligram.min.css" integrity="sha512-xiunq9hpKsIcz42zt0o2vCo34xV0j6Ny8hgEylN3XBglZDtTZ2nwnqF/Z/TTCc18sGdvCjbFInNd++6q3J0N6g==" crossorigin="anonymous" referrerpolicy="no-referrer";
                 link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.9.0/styles/default.min.css" crossorigin="anonymous" referrerpolicy="no-referrer";
@@ -29,9 +54,9 @@ fn render_body_html(title: &str, body: Markup) -> Markup {
                     (PreEscaped(COMMON_CSS))
                     (PreEscaped(r#"
                     .index-nav-ul { margin: 0; list-style: circle outside; }
-                    header.row { justify-content: space-between; align-items: center; }
-                    header.row .column { max-width: fit-content; }
-                    header.row nav.column { margin: 0; }
+                    header .row { justify-content: space-between; align-items: center; }
+                    header .row .column { max-width: fit-content; }
+                    header .row nav.column { margin: 0; }
                     header img {
                       height: 1.3em;
                       vertical-align: middle;
@@ -40,6 +65,12 @@ fn render_body_html(title: &str, body: Markup) -> Markup {
                     .block { display: block; }
                     .m-b-05 { margin-bottom: 0.5em; }
                     .m-b-1 { margin-bottom: 1em; }
+                    .summary { display: block; font-size: smaller; margin-bottom: 0.5em; }
+                    img.cover { display: block; max-width: 100%; margin: 0 auto 1em; }
+                    form.search { display: flex; gap: 1em; margin-bottom: 1em; }
+                    form.search input[type=search] { flex-grow: 1; margin: 0; }
+                    form.search input[type=submit] { margin: 0; }
+                    .search-results mark { background-color: rgb(252, 238, 168); padding: 0; }
                     main.container {
                       margin: 2em auto 0;
                       flex-grow: 1;
@@ -66,31 +97,25 @@ fn render_body_html(title: &str, body: Markup) -> Markup {
     }
 }
 
-lazy_static! {
-    static ref FOOTER: Markup = html! {
+fn footer(site: &Site) -> Markup {
+    html! {
         footer.container {
             small {
-                "© Ben Meier " (Local::now().year()) " - "
+                "© " (site.author) " " (Local::now().year()) " - "
                 a target="_blank" href={"https://github.com/astromechza/bloog/releases/tag/" (crate_version!()) } { "astromechza/bloog@" (crate_version!()) }
             }
         }
-    };
+    }
 }
 
 pub(crate) fn internal_error_page(err: anyhow::Error, htmx_context: Option<Box<HtmxContext>>) -> impl IntoResponse {
+    let site = &DEFAULT_SITE;
     render_body_html_or_htmx(
         StatusCode::INTERNAL_SERVER_ERROR,
         "Internal Error",
         html! {
             main.container {
-                header.row.m-b-05 {
-                    h1.column {
-                        a href="/" title="Back to index" {
-                            "/ "
-                        }
-                        "Error"
-                    }
-                }
+                (page_header("Error", html! {}, ""))
                 section {
                     details {
                         summary {
@@ -111,27 +136,20 @@ pub(crate) fn internal_error_page(err: anyhow::Error, htmx_context: Option<Box<H
                     }
                 }
             }
-            (FOOTER.deref())
+            (footer(site))
         },
-        render_body_html,
+        |title, body| render_body_html(site, title, body),
         htmx_context,
     )
 }
 
-pub(crate) fn not_found_page(uri: Uri, htmx_context: Option<Box<HtmxContext>>) -> impl IntoResponse {
+pub(crate) fn not_found_page(site: &Site, uri: Uri, htmx_context: Option<Box<HtmxContext>>) -> impl IntoResponse {
     render_body_html_or_htmx(
         StatusCode::NOT_FOUND,
         "Not Found",
         html! {
             main.container {
-                header.row.m-b-05 {
-                    h1.column {
-                        a href="/" title="Back to index" {
-                            "/ "
-                        }
-                        "Not Found"
-                    }
-                }
+                (page_header("Not Found", html! {}, ""))
                 section {
                     p {
                         "Page " (uri) " not found. Go back to the "
@@ -142,43 +160,40 @@ pub(crate) fn not_found_page(uri: Uri, htmx_context: Option<Box<HtmxContext>>) -
                     }
                 }
             }
-            (FOOTER.deref())
+            (footer(site))
         },
-        render_body_html,
+        |title, body| render_body_html(site, title, body),
         htmx_context,
     )
+    .into_response()
 }
 
 pub(crate) fn get_index_page(
+    site: &Site,
     label_filter: Option<String>,
     year_groups: Vec<(&i32, &Vec<&Post>)>,
     htmx_context: Option<Box<HtmxContext>>,
 ) -> impl IntoResponse {
+    let links = html! {
+        div.column style="flex: 0 0 auto" {
+            img src="/statics/bluesky.svg" alt="Bluesky logo";
+            a href="https://bsky.app/profile/ben.bsky.meierhost.com" target="_blank" {
+                "@ben.bsky.meierhost.com"
+            }
+        }
+        div.column style="flex: 0 0 auto" {
+            img src="/statics/github.svg" alt="Github logo";
+            a href="https://github.com/astromechza" target="_blank" {
+                "github/astromechza"
+            }
+        }
+    };
     render_body_html_or_htmx(
         StatusCode::OK,
-        "Ben's Blog",
+        site.title,
         html! {
             main.container {
-                header.row.m-b-05 {
-                    h1.column style="max-width: none" {
-                        a href="/" title="Back to index" {
-                            "/ "
-                        }
-                        "Ben's Blog"
-                    }
-                    div.column style="flex: 0 0 auto" {
-                        img src="/statics/bluesky.svg" alt="Bluesky logo";
-                        a href="https://bsky.app/profile/ben.bsky.meierhost.com" target="_blank" {
-                            "@ben.bsky.meierhost.com"
-                        }
-                    }
-                    div.column style="flex: 0 0 auto" {
-                        img src="/statics/github.svg" alt="Github logo";
-                        a href="https://github.com/astromechza" target="_blank" {
-                            "github/astromechza"
-                        }
-                    }
-                }
+                (page_header(site.title, links, ""))
                 section {
                     p.block style="font-size: smaller" {
                         r#"
@@ -223,6 +238,9 @@ pub(crate) fn get_index_page(
                                                 ")"
                                             }
                                         }
+                                        @if let Some(summary) = &p.summary {
+                                            span.summary { (summary) }
+                                        }
                                     }
                                 }
                             }
@@ -230,30 +248,144 @@ pub(crate) fn get_index_page(
                     }
                 }
             }
-            (FOOTER.deref())
+            (footer(site))
         },
-        render_body_html,
+        |title, body| render_body_html(site, title, body),
         htmx_context,
     ).into_response()
 }
 
-pub(crate) fn get_post_page(post: Post, content_html: Markup, toc: Markup, htmx_context: Option<Box<HtmxContext>>) -> impl IntoResponse {
+/// The header shared by every page. It carries the search form so that posts can be searched from anywhere, and any
+/// links are shown alongside the heading.
+fn page_header(heading: &str, links: Markup, query: &str) -> Markup {
+    html! {
+        header.m-b-05 {
+            div.row {
+                h1.column style="max-width: none" {
+                    a href="/" title="Back to index" {
+                        "/ "
+                    }
+                    (heading)
+                }
+                (links)
+            }
+            (search_form(query))
+        }
+    }
+}
+
+fn search_form(query: &str) -> Markup {
+    html! {
+        form.search method="get" action="/search" role="search" {
+            input type="search" name="q" value=(query) placeholder="Search posts, e.g. rust \"error handling\" label:golang" aria-label="Search posts";
+            input type="submit" value="Search";
+        }
+    }
+}
+
+pub(crate) fn get_search_page(
+    site: &Site,
+    query: String,
+    results: Vec<SearchResult>,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> impl IntoResponse {
     render_body_html_or_htmx(
         StatusCode::OK,
-        post.title.as_str(),
+        format!("Search - {}", site.title).as_str(),
         html! {
             main.container {
-                header.row.m-b-05 {
-                    h1.column {
-                        a href="/" title="Back to index" {
-                            "/ "
+                (page_header("Search", html! {}, query.as_str()))
+                section.search-results {
+                    @if query.is_empty() {
+                        p { "Enter some words to search for. Use quotes to match a phrase and label:name to filter by label." }
+                    } @else if results.is_empty() {
+                        p { "No posts matched '" (query) "'." }
+                    } @else {
+                        p { (results.len()) " post(s) matched '" (query) "'." }
+                        ul.index-nav-ul {
+                            @for r in results {
+                                li.m-b-1 {
+                                    a href={ "/posts/" (&r.post.slug) } {
+                                        time datetime=(&r.post.date.format(RFC3339_DATE_FORMAT).to_string()) {
+                                            (&r.post.date.format("%d %b %Y").to_string())
+                                        }
+                                        ": "
+                                        (&r.post.title)
+                                    }
+                                    br;
+                                    small { (r.snippet) }
+                                }
+                            }
                         }
-                        (post.title)
                     }
                 }
+            }
+            (footer(site))
+        },
+        |title, body| render_body_html(site, title, body),
+        htmx_context,
+    )
+    .into_response()
+}
+
+/// The meta tags describing a post for search engines and link previews. Link previews need absolute urls, so the url
+/// and image are only included when the base url is known.
+fn post_meta(site: &Site, post: &Post, base_url: Option<&Url>) -> Markup {
+    let absolute = |path: &str| base_url.and_then(|b| b.join(path).ok()).map(|u| u.to_string());
+    html! {
+        meta name="author" content=(post.author.as_deref().unwrap_or(site.author));
+        @if let Some(summary) = &post.summary {
+            meta name="description" content=(summary);
+            meta property="og:description" content=(summary);
+        }
+        meta property="og:type" content="article";
+        meta property="og:title" content=(post.title);
+        @if let Some(url) = absolute(format!("/posts/{}", post.slug).as_str()) {
+            meta property="og:url" content=(url);
+        }
+        @if let Some(image) = post.cover_image.as_deref().and_then(absolute) {
+            meta property="og:image" content=(image);
+            meta name="twitter:card" content="summary_large_image";
+        }
+        meta property="article:published_time" content=(post.date.format(RFC3339_DATE_FORMAT).to_string());
+        @if let Some(updated) = post.updated {
+            meta property="article:modified_time" content=(updated.format(RFC3339_DATE_FORMAT).to_string());
+        }
+        @for l in &post.labels {
+            meta property="article:tag" content=(l);
+        }
+    }
+}
+
+pub(crate) fn get_post_page(
+    site: &Site,
+    post: Post,
+    content_html: Markup,
+    toc: Markup,
+    base_url: Option<&Url>,
+    htmx_context: Option<Box<HtmxContext>>,
+) -> impl IntoResponse {
+    let meta = post_meta(site, &post, base_url);
+    render_body_html_or_htmx(
+        StatusCode::OK,
+        post.title.as_str(),
+        html! {
+            main.container {
+                (page_header(post.title.as_str(), html! {}, ""))
                 section {
+                    @if let Some(summary) = &post.summary {
+                        p.block.m-b-05 { em { (summary) } }
+                    }
                     p.block.m-b-1 {
+                        @if let Some(author) = &post.author {
+                            (author) " | "
+                        }
                         time datetime=(post.date.format(RFC3339_DATE_FORMAT).to_string()) { (post.date.format("%e %B %Y").to_string()) }
+                        @if let Some(updated) = post.updated {
+                            " (updated "
+                            time datetime=(updated.format(RFC3339_DATE_FORMAT).to_string()) { (updated.format("%e %B %Y").to_string()) }
+                            ")"
+                        }
                         @if !post.labels.is_empty() {
                             @for l in post.labels {
                                 " | "
@@ -262,6 +394,9 @@ pub(crate) fn get_post_page(post: Post, content_html: Markup, toc: Markup, htmx_
                         }
                     }
                     hr;
+                    @if let Some(cover_image) = &post.cover_image {
+                        img.cover src=(cover_image) alt="";
+                    }
                     article {
                         nav.toc { ul { (toc) } }
                         (content_html)
@@ -271,9 +406,9 @@ pub(crate) fn get_post_page(post: Post, content_html: Markup, toc: Markup, htmx_
                     }
                 }
             }
-            (FOOTER.deref())
+            (footer(site))
         },
-        render_body_html,
+        |title, body| render_body_html_with_meta(site, title, meta, body),
         htmx_context,
     )
     .into_response()
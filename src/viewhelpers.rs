@@ -8,7 +8,7 @@ pub(crate) fn render_body_html_or_htmx(
     code: StatusCode,
     title: impl AsRef<str>,
     inner: Markup,
-    outer: fn(&str, Markup) -> Markup,
+    outer: impl FnOnce(&str, Markup) -> Markup,
     htmx_context: Option<Box<HtmxContext>>,
 ) -> impl IntoResponse {
     let mut hm = HeaderMap::new();
@@ -91,6 +91,8 @@ article a[href^="http"]::after {
 article a.hlink { color: var(--main-tx-colour); text-decoration: none; }
 article a.hlink:hover { text-decoration-line: underline; text-decoration-style: dotted; }
 
+/* The width and height attributes of images only reserve their aspect ratio, since they may be scaled down. */
+article img { max-width: 100%; height: auto; }
 article img:not([src$=".svg"]) {
   border-radius: 0.3em;
 }